@@ -1,5 +1,7 @@
 use clap::{App, AppSettings, Arg};
 
+use ia_sandbox::format::Template;
+
 const ABOUT: &str = "
 ia-sandbox sandboxes applications for secure running of executables";
 
@@ -13,9 +15,78 @@ particular infoarena.ro";
 const ARGS_AFTER_HELP: &str = "
 All of the trailing arguments are passed to the command to run. If you're passing
 arguments to both ia-sandbox and the binary, the ones after `--` go to the command,
-the ones before go to ia-sandbox.";
+the ones before go to ia-sandbox.
+
+Other commands:
+    ia-sandbox setup    creates the cgroup hierarchies (see ia-sandbox setup --help)
+    ia-sandbox doctor   checks what is missing to run (see ia-sandbox doctor --help)
+    ia-sandbox batch    runs the command over many test cases (see ia-sandbox batch --help)
+    ia-sandbox job      compiles, then runs what was compiled (see ia-sandbox job --help)
+
+To run a command with the same name as one of these, put it after `--`.";
+
+const BATCH_ABOUT: &str = "
+Runs the same command over many test cases, sharing the setup of the jail";
+
+const BATCH_LONG_ABOUT: &str = "
+Runs the same command over many test cases, sharing the setup of the jail
+
+The new root and its mounts are set up once, then every case runs in a fresh jail
+with the usage cleared. A case is given as STDIN:STDOUT[:LIMITS], where LIMITS is a
+comma separated list of time=<duration>, wall-time=<duration> and memory=<space>
+replacing the limits given as options. An empty STDIN or STDOUT keeps the --stdin
+or --stdout redirect.
+
+The cases run in order. By default the batch stops after the first case that is not
+successful. With --output json the run information of all the cases that ran is
+printed as a single json array.";
+
+const BATCH_AFTER_HELP: &str = "
+All of the trailing arguments are passed to the command to run, as with a single run.";
+
+const JOB_ABOUT: &str = "
+Compiles in one sandbox, then runs the compiled artifact in another";
+
+const JOB_LONG_ABOUT: &str = "
+Compiles in one sandbox, then runs the compiled artifact in another
+
+The compile stage is configured with the --compile-* options, the run stage with
+the usual options (and COMMAND). The compiler leaves the artifact at the SOURCE of
+--artifact (usually inside a writable --compile-mount), from where it is copied to
+DESTINATION in the run stage, inside its new root. The stdout and stderr of both
+stages are captured (unless redirected) and reported along with their run
+information. The run stage is skipped if the compile stage is not successful.";
+
+const DOCTOR_ABOUT: &str = "
+Checks whether ia-sandbox can run on this machine and explains how to fix it";
+
+const DOCTOR_LONG_ABOUT: &str = "
+Checks whether ia-sandbox can run on this machine and explains how to fix it
+
+Every prerequisite is probed the same way a sandbox uses it: user, pid, ipc, uts,
+mount, network and cgroup namespaces, uid/gid maps, mounting /proc, writable cgroup
+controllers, swap accounting and Landlock. Run it as the user running the sandbox.
+Exits with a non zero status if the sandbox can not run.";
+
+const SETUP_ABOUT: &str = "
+Creates the cgroup hierarchies used by ia-sandbox and hands them over to a user";
+
+const SETUP_LONG_ABOUT: &str = "
+Creates the cgroup hierarchies used by ia-sandbox and hands them over to a user
+
+For cgroup v1 /sys/fs/cgroup/{cpuacct,memory,pids}/ia-sandbox are created and
+chowned to the user. For cgroup v2 /sys/fs/cgroup/ia-sandbox is created with the
+cpu, memory and pids controllers enabled and delegated to the user. Needs root.
+
+Also checks the kernel features ia-sandbox uses: user namespaces, swap accounting
+(memsw) and cgroup namespaces.";
 
 pub(crate) fn app() -> App<'static, 'static> {
+    jail_app().args(&checker_args())
+}
+
+/// The options of a jail, shared by single runs and batches.
+fn jail_app() -> App<'static, 'static> {
     App::new("ia-sandbox")
         .author(crate_authors!())
         .version(crate_version!())
@@ -105,6 +176,140 @@ pub(crate) fn app() -> App<'static, 'static> {
                      suffixes: ns(nanoseconds), ms(milliseconds) or s(seconds)",
                 ),
         )
+        .arg(
+            Arg::with_name("cpu-quota")
+                .long("cpu-quota")
+                .takes_value(true)
+                .help("How many cores the executable may use at the same time")
+                .long_help(
+                    "How many cores the executable may use at the same time, like 1.5.\n\
+                     Unlike --time the executable is throttled (by the cpu controller)\n\
+                     instead of killed.",
+                ),
+        )
+        .arg(
+            Arg::with_name("io-device")
+                .long("io-device")
+                .takes_value(true)
+                .help("Device the --io-* limits apply to")
+                .long_help(
+                    "Device the --io-* limits apply to, as major:minor or as a path\n\
+                     on it (its disk is used, limits do not work on partitions).",
+                ),
+        )
+        .arg(
+            Arg::with_name("io-read-bps")
+                .long("io-read-bps")
+                .takes_value(true)
+                .requires("io-device")
+                .help("Bytes per second that can be read from --io-device")
+                .long_help(
+                    "Bytes per second that can be read from --io-device, with the\n\
+                     same suffixes as --memory.",
+                ),
+        )
+        .arg(
+            Arg::with_name("io-write-bps")
+                .long("io-write-bps")
+                .takes_value(true)
+                .requires("io-device")
+                .help("Bytes per second that can be written to --io-device")
+                .long_help(
+                    "Bytes per second that can be written to --io-device, with the\n\
+                     same suffixes as --memory.",
+                ),
+        )
+        .arg(
+            Arg::with_name("io-read-iops")
+                .long("io-read-iops")
+                .takes_value(true)
+                .requires("io-device")
+                .help("Read operations per second on --io-device"),
+        )
+        .arg(
+            Arg::with_name("io-write-iops")
+                .long("io-write-iops")
+                .takes_value(true)
+                .requires("io-device")
+                .help("Write operations per second on --io-device"),
+        )
+        .arg(
+            Arg::with_name("cpus")
+                .long("cpus")
+                .takes_value(true)
+                .conflicts_with("exclusive-cpus")
+                .help("Cpus the executable may run on")
+                .long_help(
+                    "Cpus the executable may run on, as a list like 0-3,6. Enforced with\n\
+                     the cpuset controller, or with the cpu affinity if it is missing.",
+                ),
+        )
+        .arg(
+            Arg::with_name("exclusive-cpus")
+                .long("exclusive-cpus")
+                .takes_value(true)
+                .help("Number of cpus to run on, not shared with other jails")
+                .long_help(
+                    "Number of cpus to run on, not shared with other jails using\n\
+                     --exclusive-cpus. Fails if there are not enough free cpus.",
+                ),
+        )
+        .arg(
+            Arg::with_name("mems")
+                .long("mems")
+                .takes_value(true)
+                .help("Memory nodes the executable may use")
+                .long_help(
+                    "Memory nodes the executable may use, as a list like 0-1. Needs the\n\
+                     cpuset controller. By default those of the parent cgroup.",
+                ),
+        )
+        .arg(
+            Arg::with_name("soft-wall-time")
+                .long("soft-wall-time")
+                .takes_value(true)
+                .help("Soft wall time limit")
+                .long_help(
+                    "Soft wall time limit. If the executable runs for more than this\n\
+                     (in real time) it is sent --soft-limit-signal, and has until the\n\
+                     wall time limit to exit. Same format as --wall-time",
+                ),
+        )
+        .arg(
+            Arg::with_name("soft-time")
+                .long("soft-time")
+                .takes_value(true)
+                .help("Soft user time limit")
+                .long_help(
+                    "Soft user time limit. If the executable uses more user time than\n\
+                     this it is sent --soft-limit-signal, and has until the user time\n\
+                     limit to exit. Same format as --time",
+                ),
+        )
+        .arg(
+            Arg::with_name("soft-limit-signal")
+                .long("soft-limit-signal")
+                .takes_value(true)
+                .help("Signal sent at a soft limit, SIGTERM by default")
+                .long_help(
+                    "Signal sent when a soft limit is exceeded, SIGTERM by default.\n\
+                     Given as a number or a name (e.g. SIGTERM, SIGXCPU, SIGUSR1).\n\
+                     The executable only receives it if it handles it, since it\n\
+                     is the init process of its pid namespace",
+                ),
+        )
+        .arg(
+            Arg::with_name("idle-time")
+                .long("idle-time")
+                .takes_value(true)
+                .help("Idle time limit")
+                .long_help(
+                    "Idle time limit. If the executable does not use the cpu for this\n\
+                     long (e.g. it waits for input or is deadlocked) it is killed.\n\
+                     Given as an unsigned number followed by one of the following\n\
+                     suffixes: ns(nanoseconds), ms(milliseconds) or s(seconds)",
+                ),
+        )
         .arg(
             Arg::with_name("memory")
                 .long("memory")
@@ -149,56 +354,75 @@ pub(crate) fn app() -> App<'static, 'static> {
                 .takes_value(true)
                 .help("Instance name for cgroups")
                 .long_help(
-                    "Instance name for cgroups. If you plan on running multiple\n\
-                     sandboxes at the same time, it is mandatory they be given\n\
-                     different instance name, otherwise their user times will\n\
-                     add up.",
+                    "Instance name for cgroups. If not given, a unique instance is\n\
+                     created for this run and removed afterwards, so multiple sandboxes\n\
+                     can run at the same time. A named instance is kept between runs\n\
+                     (see --no-clear-usage), so sandboxes running at the same time must\n\
+                     be given different names, otherwise their user times will add up.",
                 ),
         )
+        .args(&controller_args())
         .arg(
-            Arg::with_name("cpuacct-controller")
-                .long("cpuacct-controller")
-                .takes_value(true)
-                .help("cpuacct contrroller path")
-                .long_help(
-                    "cpuacct controller path. Must have write permissions with the\n\
-                     user running the sandbox.",
-                ),
-        )
-        .arg(
-            Arg::with_name("memory-controller")
-                .long("memory-controller")
+            Arg::with_name("output")
+                .short("o")
+                .long("output")
                 .takes_value(true)
-                .help("memory contrroller path")
+                .possible_values(&["human", "oneline", "json", "key-value", "csv"])
+                .default_value("human")
+                .help("how to output the run information.")
                 .long_help(
-                    "memory controller path. Must have write permissions with the\n\
-                     user running the sandbox.",
+                    "how to output the run information.\n\
+                     human - multiline string describing everything, not suitable for\n\
+                     parsing.\n\
+                     oneline - USER_TIME MEMORY VERDICT\n\
+                     json - a single json object with 4 fields. When the sandbox fails,\n\
+                     an object with a single `error` field (with its stable `code`, the\n\
+                     `message`, its `causes` and the `fields` of the error) instead\n\
+                     key-value - a line of field=value pairs (see --output-format for\n\
+                     the fields), values with spaces are quoted\n\
+                     csv - a header line with the names of the fields, then a line per run\n",
                 ),
         )
         .arg(
-            Arg::with_name("pids-controller")
-                .long("pids-controller")
+            Arg::with_name("output-format")
+                .long("output-format")
                 .takes_value(true)
-                .help("pids controller path")
+                .validator(|template| template.parse::<Template>().map(|_| ()))
+                .help("a template for the run information, takes precedence over --output")
                 .long_help(
-                    "pids controller path. Must have write permissions with then\n\
-                     user running the sandbox.",
+                    "a template for the run information, printed on a line for every run.\n\
+                     Takes precedence over --output. Fields are given in braces, like\n\
+                     '{verdict} {user_time_ms} {memory_kib}', and braces themselves as\n\
+                     {{ and }}. Times are in milliseconds, space in kibibytes and the\n\
+                     fields not applying to the run are empty. The fields:\n\
+                     verdict, result (human readable), exit_code, signal,\n\
+                     killed_by_sandbox, soft_limit_signaled, limit_ms, limit_kib,\n\
+                     limit_usage_ms, limit_usage_kib (of the limit exceeded),\n\
+                     user_time_ms, wall_time_ms, memory_kib, idle_time_ms, nr_throttled,\n\
+                     throttled_time_ms, io_read_bytes, io_write_bytes, disk_usage_kib,\n\
+                     check_verdict, check_message (with --answer).",
                 ),
         )
         .arg(
-            Arg::with_name("output")
-                .short("o")
-                .long("output")
+            Arg::with_name("exit-codes")
+                .long("exit-codes")
                 .takes_value(true)
-                .possible_values(&["human", "oneline", "json"])
-                .default_value("human")
-                .help("how to output the run information.")
+                .possible_values(&["simple", "detailed"])
+                .default_value("simple")
+                .help("how to choose the exit code.")
                 .long_help(
-                    "how to output the run information.\n\
-                     human - multiline string describing everything, not suitable for\n\
-                     parsing.\n\
-                     oneline - USER_TIME MEMORY VERDICT\n\
-                     json - a single json object with 4 fields\n",
+                    "how to choose the exit code.\n\
+                     simple - 0 whenever the program ran, whatever the verdict, 1 if the\n\
+                     sandbox failed.\n\
+                     detailed - 0 for success (and an accepted answer), otherwise:\n\
+                     1 non zero exit status, 2 killed by signal, 3 memory limit exceeded,\n\
+                     4 time limit exceeded, 5 wall time limit exceeded, 6 idle,\n\
+                     7 wrong answer, 8 presentation error, 9 compile error (jobs).\n\
+                     Batches exit with the code of the first case not successful.\n\
+                     When the sandbox fails: 100 invalid arguments, 101 supervisor,\n\
+                     102 ffi, 103 exec failure, 104 cgroup, 105 cpuset, 106 disk quota,\n\
+                     107 rootfs, 108 setup, 109 checker, 110 job, 111 preset,\n\
+                     112 doctor.",
                 ),
         )
         .arg(
@@ -217,7 +441,10 @@ pub(crate) fn app() -> App<'static, 'static> {
                      Mount options are given as a comma separated list of the following:\n\
                      - rw, default is to mount read-only\n\
                      - exec, default is to mount with no exec permissions\n\
-                     - dev, default is to mount with no access to devices\n",
+                     - dev, default is to mount with no access to devices\n\
+                     - size=<space>, only with rw on a folder: the executable works on a\n\
+                       copy limited to that size (writing past it fails with ENOSPC),\n\
+                       copied back when it is done\n",
                 ),
         )
         .arg(
@@ -235,6 +462,67 @@ pub(crate) fn app() -> App<'static, 'static> {
                      open output and then input.",
                 ),
         )
+        .arg(
+            Arg::with_name("preset")
+                .long("preset")
+                .takes_value(true)
+                .possible_values(&["c", "cpp", "java", "python", "rust", "go"])
+                .help("what the runtime of a language needs inside the new root")
+                .long_help(
+                    "what the runtime of a language needs inside the new root. Mounts the\n\
+                     runtime found on this machine (shared libraries, interpreter, JDK),\n\
+                     sets its environment (PATH, JAVA_HOME, ...) and a pids limit fitting\n\
+                     its threads. The --mount, --env and --pids options win over the preset.\n\
+                     The memory and stack limits are passed on to managed runtimes\n\
+                     (-Xmx and -Xss for Java, GOMEMLIMIT for Go).",
+                ),
+        )
+        .arg(
+            Arg::with_name("memory-baseline")
+                .long("memory-baseline")
+                .takes_value(true)
+                .conflicts_with("memory-baseline-command")
+                .help("Memory taken by the runtime itself, left out of the memory usage")
+                .long_help(
+                    "Memory taken by the runtime itself, left out of the memory usage.\n\
+                     The memory limit enforced is raised by it, so the limit applies to what\n\
+                     the program uses on top of its runtime. Given as an unsigned number\n\
+                     followed by one of the usual suffixes b, kb, mb, gb, kib, mib, gib.",
+                ),
+        )
+        .arg(
+            Arg::with_name("memory-baseline-command")
+                .long("memory-baseline-command")
+                .takes_value(true)
+                .requires("preset")
+                .help("An empty program measuring the memory baseline of the preset")
+                .long_help(
+                    "An empty program measuring the memory baseline of the preset. It is run\n\
+                     first, with the same preset, options and limits, and the memory it used\n\
+                     is left out of the memory usage (see --memory-baseline).",
+                ),
+        )
+        .arg(
+            Arg::with_name("memory-baseline-arg")
+                .long("memory-baseline-arg")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .allow_hyphen_values(true)
+                .requires("memory-baseline-command")
+                .help("An argument passed to the empty program, can be given multiple times"),
+        )
+        .arg(
+            Arg::with_name("detailed-usage")
+                .long("detailed-usage")
+                .help("whether to also report the usage of every process and thread")
+                .long_help(
+                    "whether to also report the usage of every process and thread.\n\
+                     The process tree is sampled from /proc along with the usage, with the\n\
+                     command line, cpu time, peak memory and exit status of every process.\n\
+                     Processes living shorter than a sample (5ms) may be missed.",
+                ),
+        )
         .arg(
             Arg::with_name("no-clear-usage")
                 .long("no-clear-usage")
@@ -246,7 +534,9 @@ pub(crate) fn app() -> App<'static, 'static> {
                     "whether to not clear usage (time/memory/pids) from cgroups.\n\
                      For multi-run tasks cpu usage might be added for all run of the task.\n\
                      Because usage is not cleared, it does not make sense to change limits\n\
-                     so this option conflicts with time/memory/pids limits.",
+                     so this option conflicts with time/memory/pids limits. The usage is\n\
+                     kept in a named instance, the one given by --instance-name or else\n\
+                     the `default` instance.",
                 ),
         )
         .arg(
@@ -283,4 +573,288 @@ pub(crate) fn app() -> App<'static, 'static> {
                      this might expose sensitive information.",
                 ),
         )
+        .arg(
+            Arg::with_name("landlock-read")
+                .long("landlock-read")
+                .multiple(true)
+                .number_of_values(1)
+                .help("a path the sandboxed program may read, enforced with Landlock")
+                .long_help(
+                    "a path the sandboxed program may read, enforced with Landlock.\n\
+                     Giving any of the landlock options denies access to every path that\n\
+                     is not explicitly allowed. Paths are resolved inside the jail, after\n\
+                     pivoting to the new root. Requires Linux 5.13 with Landlock enabled.",
+                ),
+        )
+        .arg(
+            Arg::with_name("landlock-write")
+                .long("landlock-write")
+                .multiple(true)
+                .number_of_values(1)
+                .help("a path the sandboxed program may write, enforced with Landlock")
+                .long_help(
+                    "a path the sandboxed program may write, enforced with Landlock.\n\
+                     Files under it can be written, created and removed, but not read\n\
+                     unless also given with --landlock-read.",
+                ),
+        )
+        .arg(
+            Arg::with_name("landlock-exec")
+                .long("landlock-exec")
+                .multiple(true)
+                .number_of_values(1)
+                .help("a path the sandboxed program may execute, enforced with Landlock")
+                .long_help(
+                    "a path the sandboxed program may execute, enforced with Landlock.\n\
+                     Also allows reading, which is needed for loading shared libraries.\n\
+                     The command itself and its libraries must be covered by this.",
+                ),
+        )
+}
+
+fn checker_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("answer")
+            .long("answer")
+            .takes_value(true)
+            .requires("stdout")
+            .help("the expected output, checked against --stdout")
+            .long_help(
+                "the expected output, checked against --stdout if the run is\n\
+                 successful. The verdict is added to the run information.",
+            ),
+        Arg::with_name("checker")
+            .long("checker")
+            .takes_value(true)
+            .requires("answer")
+            .help("how to check the output against --answer")
+            .long_help(
+                "how to check the output against --answer. One of:\n\
+                 exact - byte for byte, whitespace differences are a presentation error\n\
+                 ignore-whitespace - line by line, ignoring the whitespace around tokens\n\
+                 and trailing empty lines, line break differences are a presentation error\n\
+                 tokens (default) - token by token, ignoring whitespace\n\
+                 floats=<epsilon> - token by token, numbers within the absolute or\n\
+                 relative error are equal\n\
+                 custom=<path> - a testlib style checker program, run in a sandbox with\n\
+                 the new root, the mounts (read only) and the limits of the command. It\n\
+                 gets the input, the output and the answer as arguments (mounted under\n\
+                 /ia-sandbox-checker with a new root).",
+            ),
+    ]
+}
+
+fn controller_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("cpuacct-controller")
+            .long("cpuacct-controller")
+            .takes_value(true)
+            .help("cpuacct contrroller path")
+            .long_help(
+                "cpuacct controller path. Must have write permissions with the\n\
+                 user running the sandbox.",
+            ),
+        Arg::with_name("memory-controller")
+            .long("memory-controller")
+            .takes_value(true)
+            .help("memory contrroller path")
+            .long_help(
+                "memory controller path. Must have write permissions with the\n\
+                 user running the sandbox.",
+            ),
+        Arg::with_name("pids-controller")
+            .long("pids-controller")
+            .takes_value(true)
+            .help("pids controller path")
+            .long_help(
+                "pids controller path. Must have write permissions with then\n\
+                 user running the sandbox.",
+            ),
+        Arg::with_name("cpu-controller")
+            .long("cpu-controller")
+            .takes_value(true)
+            .help("cpu controller path")
+            .long_help(
+                "cpu controller path. Must have write permissions with the\n\
+                 user running the sandbox. Only used with --cpu-quota.",
+            ),
+        Arg::with_name("blkio-controller")
+            .long("blkio-controller")
+            .takes_value(true)
+            .help("blkio controller path")
+            .long_help(
+                "blkio controller path. Must have write permissions with the\n\
+                 user running the sandbox. Needed with --io-* limits, otherwise\n\
+                 only used (to measure I/O) if it exists.",
+            ),
+        Arg::with_name("cpuset-controller")
+            .long("cpuset-controller")
+            .takes_value(true)
+            .help("cpuset controller path")
+            .long_help(
+                "cpuset controller path. Must have write permissions with the\n\
+                 user running the sandbox. Only used with --cpus or --exclusive-cpus.",
+            ),
+    ]
+}
+
+pub(crate) fn setup_app() -> App<'static, 'static> {
+    App::new("setup")
+        .bin_name("ia-sandbox setup")
+        .author(crate_authors!())
+        .version(crate_version!())
+        .about(SETUP_ABOUT)
+        .long_about(SETUP_LONG_ABOUT)
+        .arg(
+            Arg::with_name("user")
+                .long("user")
+                .short("u")
+                .takes_value(true)
+                .required(true)
+                .help("The user (name or uid) ia-sandbox will run as"),
+        )
+        .arg(
+            Arg::with_name("cgroup-version")
+                .long("cgroup-version")
+                .takes_value(true)
+                .possible_values(&["v1", "v2"])
+                .help("Which cgroup version to set up")
+                .long_help(
+                    "Which cgroup version to set up. By default the one mounted\n\
+                     at /sys/fs/cgroup is used.",
+                ),
+        )
+}
+
+pub(crate) fn batch_app() -> App<'static, 'static> {
+    jail_app()
+        .bin_name("ia-sandbox batch")
+        .about(BATCH_ABOUT)
+        .long_about(BATCH_LONG_ABOUT)
+        .after_help(BATCH_AFTER_HELP)
+        .arg(
+            Arg::with_name("case")
+                .long("case")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .required(true)
+                .help("A test case, STDIN:STDOUT[:LIMITS]")
+                .long_help(
+                    "A test case, STDIN:STDOUT[:LIMITS]. Can be given multiple times,\n\
+                     the cases run in the order they are given. A : in a path is written\n\
+                     \\: (and a backslash \\\\).",
+                ),
+        )
+        .arg(
+            Arg::with_name("keep-going")
+                .long("keep-going")
+                .help("Runs all the cases, even after one is not successful"),
+        )
+}
+
+pub(crate) fn job_app() -> App<'static, 'static> {
+    jail_app()
+        .bin_name("ia-sandbox job")
+        .about(JOB_ABOUT)
+        .long_about(JOB_LONG_ABOUT)
+        .after_help(BATCH_AFTER_HELP)
+        .arg(
+            Arg::with_name("compile-command")
+                .long("compile-command")
+                .takes_value(true)
+                .required(true)
+                .help("The compiler to run, relative to --compile-root"),
+        )
+        .arg(
+            Arg::with_name("compile-arg")
+                .long("compile-arg")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .allow_hyphen_values(true)
+                .help("An argument passed to the compiler, can be given multiple times"),
+        )
+        .arg(
+            Arg::with_name("compile-root")
+                .long("compile-root")
+                .takes_value(true)
+                .help("The new root of the compile stage"),
+        )
+        .arg(
+            Arg::with_name("compile-mount")
+                .long("compile-mount")
+                .multiple(true)
+                .number_of_values(1)
+                .requires("compile-root")
+                .help("which files/folders to mount inside the new root of the compile stage")
+                .long_help(
+                    "which files/folders to mount inside the new root of the compile stage.\n\
+                     Same format as --mount.",
+                ),
+        )
+        .arg(
+            Arg::with_name("compile-time")
+                .long("compile-time")
+                .takes_value(true)
+                .help("User time limit of the compile stage"),
+        )
+        .arg(
+            Arg::with_name("compile-wall-time")
+                .long("compile-wall-time")
+                .takes_value(true)
+                .help("Wall time limit of the compile stage"),
+        )
+        .arg(
+            Arg::with_name("compile-memory")
+                .long("compile-memory")
+                .takes_value(true)
+                .help("Memory limit of the compile stage"),
+        )
+        .arg(
+            Arg::with_name("compile-pids")
+                .long("compile-pids")
+                .takes_value(true)
+                .default_value("50")
+                .help("Number of pids limit of the compile stage"),
+        )
+        .arg(
+            Arg::with_name("compile-env")
+                .long("compile-env")
+                .multiple(true)
+                .number_of_values(1)
+                .help("an environment variable to pass to the compiler, NAME=VALUE"),
+        )
+        .arg(
+            Arg::with_name("artifact")
+                .long("artifact")
+                .takes_value(true)
+                .required(true)
+                .help("Where the artifact is left and where it is run from")
+                .long_help(
+                    "Where the artifact is left and where it is run from, given as\n\
+                     SOURCE:DESTINATION[:mount]. SOURCE is outside the jails, DESTINATION\n\
+                     is inside the new root of the run stage. The artifact is copied, with\n\
+                     `mount` it is bind mounted instead.",
+                ),
+        )
+}
+
+pub(crate) fn doctor_app() -> App<'static, 'static> {
+    App::new("doctor")
+        .bin_name("ia-sandbox doctor")
+        .author(crate_authors!())
+        .version(crate_version!())
+        .about(DOCTOR_ABOUT)
+        .long_about(DOCTOR_LONG_ABOUT)
+        .args(&controller_args())
+        .arg(
+            Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .takes_value(true)
+                .possible_values(&["human", "json"])
+                .default_value("human")
+                .help("how to output the report."),
+        )
 }
@@ -1,29 +1,199 @@
+use std::env;
 use std::ffi::{OsStr, OsString};
 use std::ops;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::result;
 use std::time::Duration;
 
+use ia_sandbox::batch::{Case, OnFailure};
+use ia_sandbox::checker::Checker;
 use ia_sandbox::config::{
-    ClearUsage, Config, ControllerPath, Environment, Interactive, Limits, Mount, MountOptions,
-    ShareNet, SpaceUsage, SwapRedirects,
+    ClearUsage, Config, ControllerPath, CpuQuota, CpuSet, DetailedUsage, Environment, Interactive,
+    IoDevice, IoLimits, LandlockRules, Limits, Mount, MountOptions, ShareNet, SoftLimits,
+    SpaceUsage, SwapRedirects,
 };
+use ia_sandbox::cpuset::{self, CpuAllocator, CpuLease};
+use ia_sandbox::format::Template;
+use ia_sandbox::job::{Artifact, ArtifactMode, CompileJob};
+use ia_sandbox::preset::{Language, Preset};
+use ia_sandbox::setup::{CGroupVersion, Owner, SetupOptions};
 
+use anyhow::{self, Context};
 use app;
 use clap;
-use failure::{self, ResultExt};
+use exit_code::ExitCodes;
+use libc;
+
+type Result<T> = result::Result<T, anyhow::Error>;
+
+/// The command, along with how to output its result and how to choose the exit code (of its
+/// failure as well, setting it up can fail too).
+pub(crate) fn parse() -> (Result<Command>, OutputType, ExitCodes) {
+    let args: Vec<OsString> = env::args_os().collect();
+    let parse_error = |err: clap::Error| invalid_arguments(err, &args);
+
+    // Other commands are only recognized as the first argument, anything else is a run
+    if args.get(1) == Some(&OsString::from("setup")) {
+        let matches = match app::setup_app().get_matches_from_safe(&args[1..]) {
+            Ok(matches) => ArgMatches(matches),
+            Err(err) => return parse_error(err),
+        };
+        return (
+            matches.to_setup_options().map(Command::Setup),
+            OutputType::Human,
+            ExitCodes::Simple,
+        );
+    }
+    if args.get(1) == Some(&OsString::from("doctor")) {
+        let matches = match app::doctor_app().get_matches_from_safe(&args[1..]) {
+            Ok(matches) => ArgMatches(matches),
+            Err(err) => return parse_error(err),
+        };
+        let output = matches.output_type();
+        return (
+            Ok(Command::Doctor(matches.controller_path(), output.clone())),
+            output,
+            ExitCodes::Simple,
+        );
+    }
+
+    if args.get(1) == Some(&OsString::from("batch")) {
+        let matches = match app::batch_app().get_matches_from_safe(&args[1..]) {
+            Ok(matches) => ArgMatches(matches),
+            Err(err) => return parse_error(err),
+        };
+        return (
+            parse_batch(&matches),
+            matches.output_type(),
+            matches.exit_codes(),
+        );
+    }
+
+    if args.get(1) == Some(&OsString::from("job")) {
+        let matches = match app::job_app().get_matches_from_safe(&args[1..]) {
+            Ok(matches) => ArgMatches(matches),
+            Err(err) => return parse_error(err),
+        };
+        return (
+            parse_job(&matches),
+            matches.output_type(),
+            matches.exit_codes(),
+        );
+    }
+
+    let matches = match app::app().get_matches_from_safe(&args) {
+        Ok(matches) => ArgMatches(matches),
+        Err(err) => return parse_error(err),
+    };
+    (
+        parse_run(&matches),
+        matches.output_type(),
+        matches.exit_codes(),
+    )
+}
+
+/// Arguments clap rejected. The output and the exit codes can not be taken from the matches, so
+/// they are looked up in the raw arguments, to report the error the way the caller asked for.
+/// Help and version are not errors, clap prints them and exits with 0.
+fn invalid_arguments(
+    err: clap::Error,
+    args: &[OsString],
+) -> (Result<Command>, OutputType, ExitCodes) {
+    match err.kind {
+        clap::ErrorKind::HelpDisplayed | clap::ErrorKind::VersionDisplayed => err.exit(),
+        _ => {}
+    }
+
+    let output = if raw_value(args, "--output", Some("-o")) == Some("json".to_owned()) {
+        OutputType::Json
+    } else {
+        OutputType::Human
+    };
+    let exit_codes = if raw_value(args, "--exit-codes", None) == Some("detailed".to_owned()) {
+        ExitCodes::Detailed
+    } else {
+        ExitCodes::Simple
+    };
+    (Err(err.into()), output, exit_codes)
+}
+
+/// The last value given to an option, as `--long value`, `--long=value` or `-s value`. Anything
+/// after `--` belongs to the command.
+fn raw_value(args: &[OsString], long: &str, short: Option<&str>) -> Option<String> {
+    let args: Vec<_> = args
+        .iter()
+        .take_while(|arg| *arg != "--")
+        .map(|arg| arg.to_string_lossy())
+        .collect();
+
+    let mut value = None;
+    for (index, arg) in args.iter().enumerate() {
+        if *arg == long || Some(arg.as_ref()) == short {
+            value = args.get(index + 1).map(|value| value.to_string());
+        } else if arg.starts_with(long) && arg[long.len()..].starts_with('=') {
+            value = Some(arg[long.len() + 1..].to_owned());
+        }
+    }
+    value
+}
+
+fn parse_batch(matches: &ArgMatches) -> Result<Command> {
+    let (config, output, lease) = matches.to_config_and_output()?;
+    let cases = matches.cases(config.limits())?;
+    Ok(Command::Batch(
+        Box::new(config),
+        cases,
+        matches.on_failure(),
+        output,
+        lease,
+    ))
+}
+
+fn parse_job(matches: &ArgMatches) -> Result<Command> {
+    let (config, output, lease) = matches.to_config_and_output()?;
+    let job = CompileJob::new(
+        matches.compile_config(&config)?,
+        config,
+        matches.artifact()?,
+    );
+    Ok(Command::Job(Box::new(job), output, lease))
+}
+
+fn parse_run(matches: &ArgMatches) -> Result<Command> {
+    let (config, output, lease) = matches.to_config_and_output()?;
+    let check = matches.check(&config)?;
+    Ok(Command::Run(Box::new(config), output, lease, check))
+}
 
-type Result<T> = result::Result<T, failure::Error>;
+pub(crate) enum Command {
+    /// The lease keeps the exclusive cpus of the jail, if any
+    Run(Box<Config>, OutputType, Option<CpuLease>, Option<Check>),
+    Batch(
+        Box<Config>,
+        Vec<Case>,
+        OnFailure,
+        OutputType,
+        Option<CpuLease>,
+    ),
+    Job(Box<CompileJob>, OutputType, Option<CpuLease>),
+    Setup(SetupOptions),
+    Doctor(ControllerPath, OutputType),
+}
 
-pub(crate) fn parse() -> Result<(Config, OutputType)> {
-    let matches = app::app().get_matches();
-    ArgMatches(matches).to_config_and_output()
+/// How to check the output of a run, and against what.
+pub(crate) struct Check {
+    pub(crate) checker: Checker,
+    pub(crate) answer: PathBuf,
 }
 
+#[derive(Clone, PartialEq, Eq)]
 pub(crate) enum OutputType {
     Human,
     Oneline,
     Json,
+    KeyValue,
+    Csv,
+    Template(Template),
 }
 
 struct ArgMatches<'a>(clap::ArgMatches<'a>);
@@ -38,22 +208,22 @@ impl<'a> ops::Deref for ArgMatches<'a> {
 fn parse_duration(string: &str) -> Result<Duration> {
     let number_index = string
         .find(|c: char| !c.is_digit(10))
-        .ok_or_else(|| format_err!("Could not find duration suffix (s/ns/ms): {}", string))?;
+        .ok_or_else(|| anyhow!("Could not find duration suffix (s/ns/ms): {}", string))?;
     let (number, suffix) = string.split_at(number_index);
     let number = number
         .parse::<u64>()
-        .context(format_err!("Could not parse number {}", number))?;
+        .with_context(|| format!("Could not parse number {}", number))?;
     match suffix {
         "ns" => Ok(Duration::from_nanos(number)),
         "ms" => Ok(Duration::from_millis(number)),
         "s" => Ok(Duration::from_secs(number)),
-        suffix => Err(format_err!("Unrecognized suffix: {}", suffix)),
+        suffix => Err(anyhow!("Unrecognized suffix: {}", suffix)),
     }
 }
 
 fn parse_space_usage(string: &str) -> Result<SpaceUsage> {
     let number_index = string.find(|c: char| !c.is_digit(10)).ok_or_else(|| {
-        format_err!(
+        anyhow!(
             "Could not find duration suffix (b/kb/mb/gb/kib/mib/gib): {}",
             string
         )
@@ -62,7 +232,7 @@ fn parse_space_usage(string: &str) -> Result<SpaceUsage> {
     let (number, suffix) = string.split_at(number_index);
     let number = number
         .parse::<u64>()
-        .context(format_err!("Could not parse number {}", number))?;
+        .with_context(|| format!("Could not parse number {}", number))?;
     match suffix {
         "b" => Ok(SpaceUsage::from_bytes(number)),
         "kb" => Ok(SpaceUsage::from_kilobytes(number)),
@@ -71,10 +241,79 @@ fn parse_space_usage(string: &str) -> Result<SpaceUsage> {
         "kib" => Ok(SpaceUsage::from_kibibytes(number)),
         "mib" => Ok(SpaceUsage::from_mebibytes(number)),
         "gib" => Ok(SpaceUsage::from_gibibytes(number)),
-        suffix => Err(format_err!("Unrecognized suffix: {}", suffix)),
+        suffix => Err(anyhow!("Unrecognized suffix: {}", suffix)),
+    }
+}
+
+fn parse_signal(string: &str) -> Result<libc::c_int> {
+    if let Ok(signal) = string.parse() {
+        return Ok(signal);
+    }
+
+    let name = string.trim_start_matches("SIG");
+    match name {
+        "HUP" => Ok(libc::SIGHUP),
+        "INT" => Ok(libc::SIGINT),
+        "QUIT" => Ok(libc::SIGQUIT),
+        "USR1" => Ok(libc::SIGUSR1),
+        "USR2" => Ok(libc::SIGUSR2),
+        "ALRM" => Ok(libc::SIGALRM),
+        "TERM" => Ok(libc::SIGTERM),
+        "XCPU" => Ok(libc::SIGXCPU),
+        _ => Err(anyhow!("Unrecognized signal: {}", string)),
     }
 }
 
+/// Parses a number of cores with at most 3 decimals (like `1.5`) as millicores.
+fn parse_cores(string: &str) -> Result<u64> {
+    let (whole, fraction) = match string.find('.') {
+        Some(index) => (&string[..index], &string[index + 1..]),
+        None => (string, ""),
+    };
+    if fraction.len() > 3 {
+        return Err(anyhow!("Too many decimals: {}", string));
+    }
+
+    let whole = whole
+        .parse::<u64>()
+        .with_context(|| format!("Could not parse number {}", whole))?;
+    let fraction = if fraction.is_empty() {
+        0
+    } else {
+        format!("{:0<3}", fraction)
+            .parse::<u64>()
+            .with_context(|| format!("Could not parse number {}", fraction))?
+    };
+    let millicores = whole * 1_000 + fraction;
+    if millicores == 0 {
+        return Err(anyhow!("The quota must be positive"));
+    }
+    Ok(millicores)
+}
+
+/// Parses lists of numbers and ranges like `0-3,6`.
+fn parse_list(string: &str) -> Result<Vec<usize>> {
+    let mut list = Vec::new();
+    for part in string.split(',') {
+        let mut bounds = part.splitn(2, '-').map(|bound| {
+            bound
+                .trim()
+                .parse::<usize>()
+                .with_context(|| format!("Could not parse number {}", bound))
+        });
+        let start = bounds.next().expect("split has at least one part")?;
+        let end = match bounds.next() {
+            Some(end) => end?,
+            None => start,
+        };
+        if end < start {
+            return Err(anyhow!("Invalid range {}", part));
+        }
+        list.extend(start..=end);
+    }
+    Ok(list)
+}
+
 fn parse_mount_options(string: &str) -> Result<MountOptions> {
     let mut mount_options = MountOptions::default();
 
@@ -83,14 +322,20 @@ fn parse_mount_options(string: &str) -> Result<MountOptions> {
             "rw" => mount_options.set_read_only(false),
             "dev" => mount_options.set_dev(true),
             "exec" => mount_options.set_exec(true),
+            _ if option.starts_with("size=") => {
+                mount_options.set_size_limit(Some(parse_space_usage(&option[5..])?))
+            }
             _ => {
-                return Err(format_err!(
+                return Err(anyhow!(
                     "Could not parse mount option, unrecognized `{}`",
                     option
                 ));
             }
         }
     }
+    if mount_options.read_only() && mount_options.size_limit().is_some() {
+        return Err(anyhow!("Mount option `size` needs `rw`"));
+    }
     Ok(mount_options)
 }
 
@@ -113,7 +358,162 @@ fn parse_mount(string: &str) -> Result<Mount> {
             PathBuf::from(destination),
             parse_mount_options(options)?,
         )),
-        _ => Err(format_err!("Could not parse mount")),
+        _ => Err(anyhow!("Could not parse mount")),
+    }
+}
+
+/// Parses `STDIN:STDOUT[:LIMITS]`, where the limits (`time`, `wall-time` and `memory`) replace
+/// the ones of the run. An empty path keeps the redirect of the run.
+/// Soft limits only make sense below a hard limit, which kills the program if the signal does
+/// not make it exit.
+fn check_soft_limits(limits: &Limits) -> Result<()> {
+    let pairs = [
+        ("wall time", limits.soft().wall_time(), limits.wall_time()),
+        ("time", limits.soft().user_time(), limits.user_time()),
+    ];
+    for &(name, soft, hard) in &pairs {
+        match (soft, hard) {
+            (Some(_), None) => {
+                return Err(anyhow!(
+                    "The soft {} limit needs a {} limit as well",
+                    name,
+                    name
+                ));
+            }
+            (Some(soft), Some(hard)) if soft >= hard => {
+                return Err(anyhow!(
+                    "The soft {} limit must be lower than the {} limit",
+                    name,
+                    name
+                ));
+            }
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+/// Splits a case on the `:` not escaped with a backslash (`\:` in a path, `\\` for a
+/// backslash).
+fn split_case(string: &str) -> Vec<String> {
+    let mut parts = vec![String::new()];
+    let mut chars = string.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => parts.last_mut().unwrap().extend(chars.next()),
+            ':' => parts.push(String::new()),
+            c => parts.last_mut().unwrap().push(c),
+        }
+    }
+    parts
+}
+
+fn parse_case(string: &str, limits: Limits) -> Result<Case> {
+    let parts = split_case(string);
+    let (stdin, stdout, case_limits) = match *parts.as_slice() {
+        [ref stdin, ref stdout] => (stdin, stdout, ""),
+        [ref stdin, ref stdout, ref case_limits] => (stdin, stdout, case_limits.as_str()),
+        _ => return Err(anyhow!("Could not parse case STDIN:STDOUT[:LIMITS]")),
+    };
+
+    let (mut wall_time, mut user_time, mut memory) =
+        (limits.wall_time(), limits.user_time(), limits.memory());
+    for limit in case_limits.split(',').filter(|limit| !limit.is_empty()) {
+        let parts: Vec<&str> = limit.splitn(2, '=').collect();
+        match *parts.as_slice() {
+            ["time", value] => user_time = Some(parse_duration(value)?),
+            ["wall-time", value] => wall_time = Some(parse_duration(value)?),
+            ["memory", value] => memory = Some(parse_space_usage(value)?),
+            _ => {
+                return Err(anyhow!(
+                    "Could not parse case limit, unrecognized `{}`",
+                    limit
+                ));
+            }
+        }
+    }
+
+    let path = |path: &str| {
+        if path.is_empty() {
+            None
+        } else {
+            Some(PathBuf::from(path))
+        }
+    };
+    let case_limits = Limits::new(
+        wall_time,
+        user_time,
+        memory,
+        limits.stack(),
+        limits.pids(),
+        limits.idle_time(),
+        limits.soft(),
+        limits.cpu_quota(),
+        limits.io(),
+    );
+    check_soft_limits(&case_limits).with_context(|| format!("Invalid case {}", string))?;
+    Ok(Case::new(path(stdin), path(stdout), case_limits))
+}
+
+/// Custom checkers run in the same root as the command they check, with its mounts read only
+/// (for the checker and its libraries) and its limits, nothing else of its configuration.
+fn parse_checker(string: &str, config: &Config) -> Result<Checker> {
+    match string {
+        "exact" => Ok(Checker::Exact),
+        "ignore-whitespace" => Ok(Checker::IgnoreWhitespace),
+        "tokens" => Ok(Checker::Tokens),
+        _ if string.starts_with("floats=") => {
+            Ok(Checker::Floats(string[7..].parse::<f64>().context(
+                anyhow!("Could not parse epsilon {}", &string[7..]),
+            )?))
+        }
+        _ if string.starts_with("custom=") => {
+            let mounts = config
+                .mounts()
+                .iter()
+                .map(|mount| {
+                    let mut mount_options = mount.mount_options();
+                    mount_options.set_read_only(true);
+                    mount_options.set_size_limit(None);
+                    Mount::new(
+                        mount.source().to_path_buf(),
+                        mount.destination().to_path_buf(),
+                        mount_options,
+                    )
+                })
+                .collect();
+            Ok(Checker::Custom(Box::new(
+                Config::for_command(PathBuf::from(&string[7..]), vec![])
+                    .with_new_root(config.new_root().map(Path::to_path_buf))
+                    .with_redirect_stdin(Some(PathBuf::from("/dev/null")))
+                    .with_redirect_stdout(Some(PathBuf::from("/dev/null")))
+                    .with_limits(config.limits())
+                    .with_controller_path(config.controller_path().clone())
+                    .with_mounts(mounts)
+                    .with_cpuset(config.cpuset().cloned()),
+            )))
+        }
+        _ => Err(anyhow!("Unrecognized checker: {}", string)),
+    }
+}
+
+fn parse_artifact(string: &str) -> Result<Artifact> {
+    let parts: Vec<&str> = string.split(':').collect();
+
+    match *parts.as_slice() {
+        [source, destination] => Ok(Artifact::new(
+            PathBuf::from(source),
+            PathBuf::from(destination),
+            ArtifactMode::Copy,
+        )),
+        [source, destination, "mount"] => Ok(Artifact::new(
+            PathBuf::from(source),
+            PathBuf::from(destination),
+            ArtifactMode::Mount,
+        )),
+        _ => Err(anyhow!(
+            "Could not parse artifact SOURCE:DESTINATION[:mount]"
+        )),
     }
 }
 
@@ -122,7 +522,7 @@ fn parse_environment(string: &str) -> Result<(String, String)> {
 
     match *parts.as_slice() {
         [name, value] => Ok((name.to_owned(), value.to_owned())),
-        _ => Err(format_err!("Could not parse env KEY=VALUE")),
+        _ => Err(anyhow!("Could not parse env KEY=VALUE")),
     }
 }
 
@@ -135,44 +535,57 @@ fn flip_option_result<T>(arg: Option<Result<T>>) -> Result<Option<T>> {
 }
 
 impl<'a> ArgMatches<'a> {
-    fn to_config_and_output(&self) -> Result<(Config, OutputType)> {
+    fn to_config_and_output(&self) -> Result<(Config, OutputType, Option<CpuLease>)> {
         let limits = Limits::new(
             self.wall_time()?,
             self.user_time()?,
             self.memory()?,
             self.stack()?,
             self.pids()?,
+            self.idle_time()?,
+            self.soft_limits()?,
+            self.cpu_quota()?,
+            self.io_limits()?,
         );
-        let controller_path = ControllerPath::new(
-            self.cpuacct_controller_path(),
-            self.memory_controller_path(),
-            self.pids_controller_path(),
-        );
-
-        let config = Config::new(
-            self.command()?,
-            self.args(),
-            self.new_root(),
-            self.share_net(),
-            self.redirect_stdin(),
-            self.redirect_stdout(),
-            self.redirect_stderr(),
-            limits,
-            self.instance_name(),
-            controller_path,
-            self.mounts()?,
-            self.swap_redirects(),
-            self.clear_usage(),
-            self.interactive(),
-            self.environment()?,
-        );
+        check_soft_limits(&limits)?;
+        let controller_path = self.controller_path();
+        let (cpuset, lease) = self.cpuset()?;
+
+        let config = Config::for_command(self.command()?, self.args())
+            .with_new_root(self.new_root())
+            .with_share_net(self.share_net())
+            .with_redirect_stdin(self.redirect_stdin())
+            .with_redirect_stdout(self.redirect_stdout())
+            .with_redirect_stderr(self.redirect_stderr())
+            .with_limits(limits)
+            .with_instance_name(self.instance_name())
+            .with_controller_path(controller_path)
+            .with_mounts(self.mounts()?)
+            .with_swap_redirects(self.swap_redirects())
+            .with_clear_usage(self.clear_usage())
+            .with_interactive(self.interactive())
+            .with_environment(self.environment()?)
+            .with_landlock(self.landlock())
+            .with_cpuset(cpuset)
+            .with_detailed_usage(self.detailed_usage())
+            .with_memory_baseline(self.memory_baseline()?);
+
+        let config = match self.preset()? {
+            Some(preset) => self
+                .with_measured_memory_baseline(preset, &config)?
+                .apply(&config),
+            None => config,
+        };
+        Ok((config, self.output_type(), lease))
+    }
 
-        Ok((config, self.output_type()))
+    fn to_setup_options(&self) -> Result<SetupOptions> {
+        Ok(SetupOptions::new(self.cgroup_version(), self.owner()?))
     }
 
     fn command(&self) -> Result<PathBuf> {
         self.value_of_os("COMMAND")
-            .ok_or_else(|| format_err!("No command was specified"))
+            .ok_or_else(|| anyhow!("No command was specified"))
             .map(PathBuf::from)
     }
 
@@ -208,61 +621,295 @@ impl<'a> ArgMatches<'a> {
     }
 
     fn wall_time(&self) -> Result<Option<Duration>> {
-        Ok(
-            flip_option_result(self.value_of("wall-time").map(|x| parse_duration(x)))
-                .context("Could not parse wall time")?,
-        )
+        flip_option_result(self.value_of("wall-time").map(|x| parse_duration(x)))
+            .context("Could not parse wall time")
     }
 
     fn user_time(&self) -> Result<Option<Duration>> {
-        Ok(
-            flip_option_result(self.value_of("time").map(|x| parse_duration(x)))
-                .context("Could not parse time")?,
-        )
+        flip_option_result(self.value_of("time").map(|x| parse_duration(x)))
+            .context("Could not parse time")
+    }
+
+    fn soft_limits(&self) -> Result<SoftLimits> {
+        let wall_time = flip_option_result(self.value_of("soft-wall-time").map(parse_duration))
+            .context("Could not parse soft wall time")?;
+        let user_time = flip_option_result(self.value_of("soft-time").map(parse_duration))
+            .context("Could not parse soft time")?;
+        let signal = flip_option_result(self.value_of("soft-limit-signal").map(parse_signal))
+            .context("Could not parse soft limit signal")?
+            .unwrap_or(libc::SIGTERM);
+        Ok(SoftLimits::new(wall_time, user_time, signal))
+    }
+
+    fn idle_time(&self) -> Result<Option<Duration>> {
+        flip_option_result(self.value_of("idle-time").map(parse_duration))
+            .context("Could not parse idle time")
     }
 
     fn memory(&self) -> Result<Option<SpaceUsage>> {
-        Ok(
-            flip_option_result(self.value_of("memory").map(|x| parse_space_usage(x)))
-                .context("Could not parse memory")?,
-        )
+        flip_option_result(self.value_of("memory").map(|x| parse_space_usage(x)))
+            .context("Could not parse memory")
+    }
+
+    fn memory_baseline(&self) -> Result<Option<SpaceUsage>> {
+        flip_option_result(self.value_of("memory-baseline").map(parse_space_usage))
+            .context("Could not parse memory baseline")
     }
 
     fn stack(&self) -> Result<Option<SpaceUsage>> {
-        Ok(
-            flip_option_result(self.value_of("stack").map(|x| parse_space_usage(x)))
-                .context("Could not parse stack")?,
-        )
+        flip_option_result(self.value_of("stack").map(|x| parse_space_usage(x)))
+            .context("Could not parse stack")
     }
 
     fn pids(&self) -> Result<Option<usize>> {
+        // Left to the preset, unless given explicitly
+        if self.is_present("preset") && self.occurrences_of("pids") == 0 {
+            return Ok(None);
+        }
         flip_option_result(
             self.value_of("pids")
-                .map(|x| Ok(x.parse::<usize>().context("Could not parse pids")?)),
+                .map(|x| x.parse::<usize>().context("Could not parse pids")),
         )
     }
 
+    /// The usage kept by `--no-clear-usage` needs a named instance, without one it is kept in
+    /// the `default` instance (as before unique instances).
     fn instance_name(&self) -> Option<OsString> {
-        self.value_of_os("instance-name").map(OsStr::to_os_string)
+        match self.value_of_os("instance-name") {
+            Some(instance_name) => Some(instance_name.to_os_string()),
+            None if self.is_present("no-clear-usage") => Some(OsString::from("default")),
+            None => None,
+        }
+    }
+
+    fn controller_path(&self) -> ControllerPath {
+        ControllerPath::new(
+            self.cpuacct_controller_path(),
+            self.memory_controller_path(),
+            self.pids_controller_path(),
+            self.cpuset_controller_path(),
+            self.cpu_controller_path(),
+            self.blkio_controller_path(),
+        )
+    }
+
+    fn blkio_controller_path(&self) -> Option<PathBuf> {
+        self.value_of_os("blkio-controller").map(PathBuf::from)
+    }
+
+    fn io_limits(&self) -> Result<Option<IoLimits>> {
+        let device = match self.value_of_os("io-device") {
+            None => return Ok(None),
+            Some(device) => device,
+        };
+        let device = match device.to_str().map(str::parse::<IoDevice>) {
+            Some(Ok(device)) => device,
+            _ => IoDevice::containing(device)
+                .with_context(|| format!("Could not find the disk of {:?}", device))?,
+        };
+
+        let bps = |name| {
+            flip_option_result(self.value_of(name).map(parse_space_usage))
+                .map(|bps| bps.map(SpaceUsage::as_bytes))
+        };
+        let iops = |name| {
+            flip_option_result(self.value_of(name).map(|iops| {
+                iops.parse::<u64>()
+                    .with_context(|| format!("Could not parse number {}", iops))
+            }))
+        };
+        Ok(Some(IoLimits::new(
+            device,
+            bps("io-read-bps").context("Could not parse io read bps")?,
+            bps("io-write-bps").context("Could not parse io write bps")?,
+            iops("io-read-iops").context("Could not parse io read iops")?,
+            iops("io-write-iops").context("Could not parse io write iops")?,
+        )))
+    }
+
+    fn cpu_controller_path(&self) -> Option<PathBuf> {
+        self.value_of_os("cpu-controller").map(PathBuf::from)
+    }
+
+    fn cpu_quota(&self) -> Result<Option<CpuQuota>> {
+        Ok(
+            flip_option_result(self.value_of("cpu-quota").map(parse_cores))
+                .context("Could not parse cpu quota")?
+                .map(CpuQuota::from_millicores),
+        )
+    }
+
+    fn cpuset_controller_path(&self) -> Option<PathBuf> {
+        self.value_of_os("cpuset-controller").map(PathBuf::from)
+    }
+
+    fn cpuset(&self) -> Result<(Option<CpuSet>, Option<CpuLease>)> {
+        let mems = flip_option_result(self.value_of("mems").map(parse_list))
+            .context("Could not parse mems")?
+            .unwrap_or_default();
+
+        if let Some(cpus) = self.value_of("cpus") {
+            let cpus = parse_list(cpus).context("Could not parse cpus")?;
+            cpuset::check_cpus(&cpus)?;
+            return Ok((Some(CpuSet::new(cpus, mems)), None));
+        }
+
+        if let Some(count) = self.value_of("exclusive-cpus") {
+            let count = count
+                .parse::<usize>()
+                .context("Could not parse exclusive cpus")?;
+            let lease = CpuAllocator::default().allocate(count)?;
+            return Ok((Some(lease.cpuset(mems)), Some(lease)));
+        }
+
+        if mems.is_empty() {
+            Ok((None, None))
+        } else {
+            Err(anyhow!("--mems needs --cpus or --exclusive-cpus"))
+        }
     }
 
     fn cpuacct_controller_path(&self) -> Option<PathBuf> {
-        self.value_of_os("cpuacct-path").map(PathBuf::from)
+        self.value_of_os("cpuacct-controller").map(PathBuf::from)
     }
 
     fn memory_controller_path(&self) -> Option<PathBuf> {
-        self.value_of_os("memory-path").map(PathBuf::from)
+        self.value_of_os("memory-controller").map(PathBuf::from)
     }
 
     fn pids_controller_path(&self) -> Option<PathBuf> {
-        self.value_of_os("pids-path").map(PathBuf::from)
+        self.value_of_os("pids-controller").map(PathBuf::from)
+    }
+
+    fn preset(&self) -> Result<Option<Preset>> {
+        Ok(flip_option_result(self.value_of("preset").map(|language| {
+            language
+                .parse::<Language>()
+                .map_err(|err| anyhow!("{}", err))
+        }))?
+        .map(Preset::for_language))
+    }
+
+    /// Runs the empty program given to measure the memory baseline of `preset`, if any.
+    fn with_measured_memory_baseline(&self, preset: Preset, config: &Config) -> Result<Preset> {
+        let command = match self.value_of_os("memory-baseline-command") {
+            Some(command) => PathBuf::from(command),
+            None => return Ok(preset),
+        };
+        let args = match self.values_of_os("memory-baseline-arg") {
+            None => vec![],
+            Some(vals) => vals.map(OsStr::to_os_string).collect(),
+        };
+        let memory_baseline = preset
+            .measure_memory_baseline(config, command, args)
+            .context("Could not measure the memory baseline")?;
+        Ok(preset.with_memory_baseline(memory_baseline))
+    }
+
+    fn check(&self, config: &Config) -> Result<Option<Check>> {
+        let answer = match self.value_of_os("answer") {
+            Some(answer) => PathBuf::from(answer),
+            None => return Ok(None),
+        };
+        let checker = parse_checker(self.value_of("checker").unwrap_or("tokens"), config)
+            .context("Could not parse checker")?;
+        Ok(Some(Check { checker, answer }))
+    }
+
+    /// The compile stage shares the controllers, network, cpus and preset of the run stage.
+    fn compile_config(&self, config: &Config) -> Result<Config> {
+        let preset = self.preset()?;
+        let pids = if preset.is_some() && self.occurrences_of("compile-pids") == 0 {
+            None
+        } else {
+            flip_option_result(self.value_of("compile-pids").map(|pids| {
+                pids.parse::<usize>()
+                    .context("Could not parse compile pids")
+            }))?
+        };
+        let limits = Limits::new(
+            flip_option_result(self.value_of("compile-wall-time").map(parse_duration))
+                .context("Could not parse compile wall time")?,
+            flip_option_result(self.value_of("compile-time").map(parse_duration))
+                .context("Could not parse compile time")?,
+            flip_option_result(self.value_of("compile-memory").map(parse_space_usage))
+                .context("Could not parse compile memory")?,
+            None,
+            pids,
+            None,
+            SoftLimits::default(),
+            None,
+            None,
+        );
+        let mounts = match self.values_of("compile-mount") {
+            None => vec![],
+            Some(args) => args.map(parse_mount).collect::<Result<_>>()?,
+        };
+        let environment = match self.values_of("compile-env") {
+            None => Vec::new(),
+            Some(args) => args.map(parse_environment).collect::<Result<_>>()?,
+        };
+
+        let command = PathBuf::from(
+            self.value_of_os("compile-command")
+                .expect("required argument"),
+        );
+        let args = match self.values_of_os("compile-arg") {
+            None => vec![],
+            Some(vals) => vals.map(OsStr::to_os_string).collect(),
+        };
+        let compile_config = Config::for_command(command, args)
+            .with_new_root(self.value_of_os("compile-root").map(PathBuf::from))
+            .with_share_net(config.share_net())
+            .with_limits(limits)
+            .with_controller_path(config.controller_path().clone())
+            .with_mounts(mounts)
+            .with_environment(Environment::EnvList(environment))
+            .with_cpuset(config.cpuset().cloned());
+        Ok(match preset {
+            Some(preset) => preset.apply(&compile_config),
+            None => compile_config,
+        })
+    }
+
+    fn artifact(&self) -> Result<Artifact> {
+        let artifact = self.value_of("artifact").expect("required argument");
+        parse_artifact(artifact).with_context(|| format!("Could not parse artifact {}", artifact))
+    }
+
+    fn cases(&self, limits: Limits) -> Result<Vec<Case>> {
+        match self.values_of("case") {
+            None => Ok(vec![]),
+            Some(values) => values.map(|value| parse_case(value, limits)).collect(),
+        }
+    }
+
+    fn on_failure(&self) -> OnFailure {
+        if self.is_present("keep-going") {
+            OnFailure::KeepGoing
+        } else {
+            OnFailure::Stop
+        }
     }
 
     fn output_type(&self) -> OutputType {
+        if let Some(template) = self.value_of("output-format") {
+            return OutputType::Template(template.parse().expect("validated template"));
+        }
         match self.value_of("output").expect("output value") {
             "human" => OutputType::Human,
             "oneline" => OutputType::Oneline,
             "json" => OutputType::Json,
+            "key-value" => OutputType::KeyValue,
+            "csv" => OutputType::Csv,
+            _ => unreachable!(),
+        }
+    }
+
+    fn exit_codes(&self) -> ExitCodes {
+        match self.value_of("exit-codes").expect("exit-codes value") {
+            "simple" => ExitCodes::Simple,
+            "detailed" => ExitCodes::Detailed,
             _ => unreachable!(),
         }
     }
@@ -290,6 +937,14 @@ impl<'a> ArgMatches<'a> {
         }
     }
 
+    fn detailed_usage(&self) -> DetailedUsage {
+        if self.is_present("detailed-usage") {
+            DetailedUsage::Yes
+        } else {
+            DetailedUsage::No
+        }
+    }
+
     fn interactive(&self) -> Interactive {
         if self.is_present("interactive") {
             Interactive::Yes
@@ -311,4 +966,40 @@ impl<'a> ArgMatches<'a> {
                 .map(Environment::EnvList),
         }
     }
+
+    fn paths(&self, name: &str) -> Vec<PathBuf> {
+        match self.values_of_os(name) {
+            None => vec![],
+            Some(vals) => vals.map(PathBuf::from).collect(),
+        }
+    }
+
+    fn landlock(&self) -> Option<LandlockRules> {
+        if self.is_present("landlock-read")
+            || self.is_present("landlock-write")
+            || self.is_present("landlock-exec")
+        {
+            Some(LandlockRules::new(
+                self.paths("landlock-read"),
+                self.paths("landlock-write"),
+                self.paths("landlock-exec"),
+            ))
+        } else {
+            None
+        }
+    }
+
+    fn cgroup_version(&self) -> Option<CGroupVersion> {
+        match self.value_of("cgroup-version") {
+            None => None,
+            Some("v1") => Some(CGroupVersion::V1),
+            Some("v2") => Some(CGroupVersion::V2),
+            _ => unreachable!(),
+        }
+    }
+
+    fn owner(&self) -> Result<Owner> {
+        let user = self.value_of("user").expect("user value");
+        Ok(Owner::from_user(user)?)
+    }
 }
@@ -0,0 +1,108 @@
+//! Running the same program over many test cases.
+//!
+//! All the cases share a single supervisor, so the mounts of the new root are only set up once.
+//! Every case still runs in a fresh jail, with the usage cleared before it starts.
+
+use std::path::{Path, PathBuf};
+
+use cgroups;
+use config::{ClearUsage, Config, Limits, ShareNet};
+use errors::{Error, Result};
+use ffi;
+use run_info::{RunInfo, RunUsage};
+use JailRun;
+
+/// A test case: where the program reads from, where it writes to and its limits.
+///
+/// The streams not given are the ones of the configuration.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Case {
+    stdin: Option<PathBuf>,
+    stdout: Option<PathBuf>,
+    limits: Limits,
+}
+
+impl Case {
+    pub fn new(stdin: Option<PathBuf>, stdout: Option<PathBuf>, limits: Limits) -> Self {
+        Self {
+            stdin,
+            stdout,
+            limits,
+        }
+    }
+
+    pub fn stdin(&self) -> Option<&Path> {
+        self.stdin.as_deref()
+    }
+
+    pub fn stdout(&self) -> Option<&Path> {
+        self.stdout.as_deref()
+    }
+
+    pub fn limits(&self) -> Limits {
+        self.limits
+    }
+}
+
+/// What happens to the remaining cases after one that was not successful.
+#[derive(Debug, Default, Eq, PartialEq, Copy, Clone)]
+pub enum OnFailure {
+    #[default]
+    Stop,
+    KeepGoing,
+}
+
+/// Runs `config` over every case, in order.
+///
+/// The limits, stdin and stdout of the configuration are replaced by the ones of each case.
+/// A case the jail could not be started for (like one with a missing stdin) has its error
+/// instead of its run information. With [`OnFailure::Stop`] the result ends with the first case
+/// that was not successful.
+pub fn run(
+    config: &Config,
+    cases: &[Case],
+    on_failure: OnFailure,
+) -> Result<Vec<Result<RunInfo<()>>>> {
+    let user_group_id = ffi::get_user_group_id();
+    let instance = cgroups::Instance::new(config.controller_path(), config.instance_name());
+    let instance_name = instance.name();
+
+    ffi::set_sig_alarm_handler().map_err(Error::FFIError)?;
+
+    let result = ffi::clone(ShareNet::Share, false, || {
+        let mut quota_mounts = ::prepare_supervisor(config, user_group_id)?;
+        let mut run_infos = Vec::with_capacity(cases.len());
+        for case in cases {
+            let jail_run = JailRun::new(
+                case.stdin().or_else(|| config.redirect_stdin()),
+                case.stdout().or_else(|| config.redirect_stdout()),
+                case.limits(),
+                ClearUsage::Yes,
+            );
+            let run_info =
+                ::supervise_jail(config, instance_name, &jail_run, &mut quota_mounts, None);
+            let success = match run_info {
+                Ok(ref run_info) => run_info.is_success(),
+                Err(_) => false,
+            };
+            run_infos.push(run_info);
+            if !success && on_failure == OnFailure::Stop {
+                break;
+            }
+        }
+        quota_mounts.copy_back()?;
+        Ok(run_infos)
+    })?
+    .wait(Limits::default(), |_| Ok(RunUsage::default()))
+    .and_then(|run_info| {
+        run_info
+            .success()
+            .and_then(|x| x)
+            .ok_or(Error::SupervisorProcessDiedError)
+            .and_then(|x| x)
+    });
+
+    // The supervisor was reaped, nothing is left inside the cgroups
+    drop(instance);
+    result
+}
@@ -3,15 +3,19 @@ use std::error::Error;
 use std::ffi::{OsStr, OsString};
 use std::fs::{self, OpenOptions};
 use std::io::{Read, Write};
+use std::num::ParseIntError;
 use std::path::{Path, PathBuf};
 use std::result;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
 use std::time::Duration;
 
-use config::{ClearUsage, ControllerPath, Limits, SpaceUsage};
-use errors::CGroupError;
+use config::{ClearUsage, ControllerPath, CpuQuota, CpuSet, IoLimits, Limits, SpaceUsage};
+use errors::{CGroupError, ChildError, Errno};
 use ffi;
-use run_info::RunUsage;
+use libc;
+use run_info::{CpuThrottling, IoUsage, RunUsage};
 
 type Result<T> = result::Result<T, CGroupError>;
 
@@ -25,7 +29,7 @@ fn cgroup_write<T1: AsRef<Path>, T2: AsRef<str>>(
         CGroupError::OpenCGroupFileError {
             controller_path: controller_path.to_path_buf(),
             file: file.as_ref().to_path_buf(),
-            error: err.description().into(),
+            errno: Errno::from(&err),
         }
     })?;
 
@@ -34,7 +38,7 @@ fn cgroup_write<T1: AsRef<Path>, T2: AsRef<str>>(
         .map_err(|err| CGroupError::WriteCGroupFileError {
             controller_path: controller_path.to_path_buf(),
             file: file.as_ref().to_path_buf(),
-            error: err.description().into(),
+            errno: Errno::from(&err),
         })
 }
 
@@ -47,7 +51,7 @@ where
         CGroupError::OpenCGroupFileError {
             controller_path: controller_path.to_path_buf(),
             file: file.as_ref().to_path_buf(),
-            error: err.description().into(),
+            errno: Errno::from(&err),
         }
     })?;
 
@@ -56,7 +60,7 @@ where
         CGroupError::ReadCGroupFileError {
             controller_path: controller_path.to_path_buf(),
             file: file.as_ref().to_path_buf(),
-            error: err.description().into(),
+            errno: Errno::from(&err),
         }
     })?;
 
@@ -67,7 +71,37 @@ where
             controller_path: controller_path.to_path_buf(),
             file: file.as_ref().to_path_buf(),
             buffer,
-            error: err.description().into(),
+            error: err.to_string(),
+        })
+}
+
+/// Reads the value of `key` from files made of `key value` lines (like `memory.oom_control`).
+fn cgroup_read_key<T: AsRef<Path>>(controller_path: &Path, file: T, key: &str) -> Result<u64> {
+    let buffer: String = cgroup_read(controller_path, file.as_ref())?;
+    let value = buffer
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+            match (parts.next(), parts.next()) {
+                (Some(name), Some(value)) if name == key => Some(value),
+                _ => None,
+            }
+        })
+        .next()
+        .ok_or_else(|| CGroupError::ParseCGroupFileError {
+            controller_path: controller_path.to_path_buf(),
+            file: file.as_ref().to_path_buf(),
+            buffer: buffer.clone(),
+            error: format!("missing key {}", key),
+        })?;
+
+    value
+        .parse()
+        .map_err(|err: ParseIntError| CGroupError::ParseCGroupFileError {
+            controller_path: controller_path.to_path_buf(),
+            file: file.as_ref().to_path_buf(),
+            buffer: buffer.clone(),
+            error: err.to_string(),
         })
 }
 
@@ -79,8 +113,8 @@ pub(crate) fn enter_cgroup(controller_path: &Path) -> Result<()> {
         fs::create_dir(&isolated_cgroup).map_err(|err| {
             CGroupError::InstanceControllerCreateError {
                 controller_path: controller_path.to_path_buf(),
-                instance_name: OsString::from(ISOLATED_CGROUP_NAME),
-                error: err.description().into(),
+                instance_name: ISOLATED_CGROUP_NAME.to_owned(),
+                errno: Errno::from(&err),
             }
         })?;
     }
@@ -88,13 +122,11 @@ pub(crate) fn enter_cgroup(controller_path: &Path) -> Result<()> {
     cgroup_write(&isolated_cgroup, "tasks", format!("{}\n", ffi::getpid()))
 }
 
-const DEFAULT_INSTANCE_NAME: &str = "default";
-fn get_instance_path(controller_path: &Path, instance_name: Option<&OsStr>) -> Result<PathBuf> {
-    let instance = instance_name.unwrap_or_else(|| OsStr::new(DEFAULT_INSTANCE_NAME));
+fn get_instance_path(controller_path: &Path, instance: &OsStr) -> Result<PathBuf> {
     if !controller_path.exists() {
-        return Err(CGroupError::ControllerMissing(
-            controller_path.to_path_buf(),
-        ));
+        return Err(CGroupError::ControllerMissing {
+            controller_path: controller_path.to_path_buf(),
+        });
     }
 
     let instance_path = controller_path.join(instance);
@@ -102,18 +134,228 @@ fn get_instance_path(controller_path: &Path, instance_name: Option<&OsStr>) -> R
         fs::create_dir(&instance_path).map_err(|err| {
             CGroupError::InstanceControllerCreateError {
                 controller_path: controller_path.to_path_buf(),
-                instance_name: instance.to_os_string(),
-                error: err.description().into(),
+                instance_name: instance.to_string_lossy().into_owned(),
+                errno: Errno::from(&err),
             }
         })?;
     }
     Ok(instance_path)
 }
 
+const RUN_INSTANCE_PREFIX: &str = "run-";
+static RUN_INSTANCE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// An instance name no other jail uses, as long as all sandboxes share the pid namespace.
+///
+/// The pid of the current process is part of the name, so stale instances (left behind by a
+/// crashed process) can be recognized.
+fn unique_instance_name() -> OsString {
+    OsString::from(format!(
+        "{}{}-{}",
+        RUN_INSTANCE_PREFIX,
+        ffi::getpid(),
+        RUN_INSTANCE_COUNTER.fetch_add(1, Ordering::SeqCst)
+    ))
+}
+
+fn remove_instance_path(controller_path: &Path, instance: &OsStr) -> Result<()> {
+    let instance_path = controller_path.join(instance);
+    let remove_dir = |path: &Path| {
+        if path.exists() {
+            fs::remove_dir(path).map_err(|err| CGroupError::InstanceControllerRemoveError {
+                controller_path: controller_path.to_path_buf(),
+                instance_name: instance.to_string_lossy().into_owned(),
+                errno: Errno::from(&err),
+            })
+        } else {
+            Ok(())
+        }
+    };
+
+    remove_dir(&instance_path.join(ISOLATED_CGROUP_NAME))?;
+    remove_dir(&instance_path)
+}
+
+/// The controllers used by the sandbox, with their paths.
+pub(crate) fn controllers(controller_path: &ControllerPath) -> [(&'static str, &Path); 3] {
+    [
+        (
+            "cpuacct",
+            controller_path
+                .cpuacct()
+                .unwrap_or_else(|| Path::new(CPUACCT_DEFAULT_CONTROLLER_PATH)),
+        ),
+        (
+            "memory",
+            controller_path
+                .memory()
+                .unwrap_or_else(|| Path::new(MEMORY_DEFAULT_CONTROLLER_PATH)),
+        ),
+        (
+            "pids",
+            controller_path
+                .pids()
+                .unwrap_or_else(|| Path::new(PIDS_DEFAULT_CONTROLLER_PATH)),
+        ),
+    ]
+}
+
+/// The cpuset controller is only needed by jails with a cpuset.
+pub(crate) fn cpuset_controller(controller_path: &ControllerPath) -> &Path {
+    controller_path
+        .cpuset()
+        .unwrap_or_else(|| Path::new(CPUSET_DEFAULT_CONTROLLER_PATH))
+}
+
+/// The cpu controller is only needed by jails with a cpu quota.
+pub(crate) fn cpu_controller(controller_path: &ControllerPath) -> &Path {
+    controller_path
+        .cpu()
+        .unwrap_or_else(|| Path::new(CPU_DEFAULT_CONTROLLER_PATH))
+}
+
+/// The blkio controller is used for I/O limits, and for measuring I/O when available.
+pub(crate) fn blkio_controller(controller_path: &ControllerPath) -> &Path {
+    controller_path
+        .blkio()
+        .unwrap_or_else(|| Path::new(BLKIO_DEFAULT_CONTROLLER_PATH))
+}
+
+/// The controllers only some jails use, with their paths.
+pub(crate) fn optional_controllers(controller_path: &ControllerPath) -> [(&'static str, &Path); 3] {
+    [
+        ("cpuset", cpuset_controller(controller_path)),
+        ("cpu", cpu_controller(controller_path)),
+        ("blkio", blkio_controller(controller_path)),
+    ]
+}
+
+/// Creates and removes an instance under the controller, like a jail would.
+pub(crate) fn probe_controller(controller_path: &Path) -> Result<()> {
+    let instance = OsString::from(format!("doctor-{}", ffi::getpid()));
+    let _ = get_instance_path(controller_path, &instance)?;
+    remove_instance_path(controller_path, &instance)
+}
+
+/// Removes the instance (and its isolated child) from all controllers.
+///
+/// Fails if there are still processes inside.
+fn remove_instance(controller_path: &ControllerPath, instance: &OsStr) -> Result<()> {
+    let mut result = Ok(());
+    let optional = optional_controllers(controller_path);
+    for &(_, path) in controllers(controller_path).iter().chain(&optional) {
+        // Try all of them, even if one fails
+        let removed = remove_instance_path(path, instance);
+        if result.is_ok() {
+            result = removed;
+        }
+    }
+    result
+}
+
+/// Garbage collects the unique instances created by processes that no longer exist.
+fn remove_stale_instances(controller_path: &ControllerPath) {
+    let optional = optional_controllers(controller_path);
+    for &(_, path) in controllers(controller_path).iter().chain(&optional) {
+        let entries = fs::read_dir(path).into_iter().flatten();
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            let name = entry.file_name();
+            let owner = name
+                .to_str()
+                .and_then(|name| name.strip_prefix(RUN_INSTANCE_PREFIX))
+                .and_then(|rest| rest.split('-').next())
+                .and_then(|pid| pid.parse::<libc::pid_t>().ok());
+
+            if let Some(owner) = owner {
+                if !ffi::process_exists(owner) {
+                    // Might still be in use (for example by a different pid namespace), in
+                    // which case it can't be removed
+                    remove_instance_path(path, &name).unwrap_or(());
+                }
+            }
+        }
+    }
+}
+
+/// Retries of killing what is left inside an instance, about a second in all.
+const KILL_ATTEMPTS: u32 = 100;
+const KILL_RETRY_DELAY: Duration = Duration::from_millis(10);
+
+/// Kills the processes left inside the instance (like the jail of a handle dropped without
+/// waiting for it), which would keep it from being removed. Waits for them to leave the cgroups.
+fn kill_instance_tasks(controller_path: &ControllerPath, instance: &OsStr) {
+    let optional = optional_controllers(controller_path);
+    for _ in 0..KILL_ATTEMPTS {
+        let tasks: Vec<libc::pid_t> = controllers(controller_path)
+            .iter()
+            .chain(&optional)
+            .filter_map(|&(_, path)| {
+                let isolated_path = path.join(instance).join(ISOLATED_CGROUP_NAME);
+                fs::read_to_string(isolated_path.join("tasks")).ok()
+            })
+            .flat_map(|tasks| {
+                tasks
+                    .lines()
+                    .filter_map(|pid| pid.parse().ok())
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        if tasks.is_empty() {
+            return;
+        }
+
+        for pid in tasks {
+            ffi::kill(pid, libc::SIGKILL);
+        }
+        thread::sleep(KILL_RETRY_DELAY);
+    }
+}
+
+/// A cgroup instance used by a jail, removed (if it was unique to the jail) on drop.
+pub(crate) struct Instance {
+    controller_path: ControllerPath,
+    name: OsString,
+    unique: bool,
+}
+
+impl Instance {
+    /// Uses the given instance name or generates a unique one if there is none.
+    pub(crate) fn new(controller_path: &ControllerPath, name: Option<&OsStr>) -> Self {
+        if let Some(name) = name {
+            return Self {
+                controller_path: controller_path.clone(),
+                name: name.to_os_string(),
+                unique: false,
+            };
+        }
+
+        remove_stale_instances(controller_path);
+        Self {
+            controller_path: controller_path.clone(),
+            name: unique_instance_name(),
+            unique: true,
+        }
+    }
+
+    pub(crate) fn name(&self) -> &OsStr {
+        &self.name
+    }
+}
+
+impl Drop for Instance {
+    fn drop(&mut self) {
+        if self.unique {
+            kill_instance_tasks(&self.controller_path, &self.name);
+            // If removing fails, the instance will be garbage collected once this process dies
+            remove_instance(&self.controller_path, &self.name).unwrap_or(());
+        }
+    }
+}
+
 const CPUACCT_DEFAULT_CONTROLLER_PATH: &str = "/sys/fs/cgroup/cpuacct/ia-sandbox";
 pub(crate) fn enter_cpuacct_cgroup(
     controller_path: Option<&Path>,
-    instance_name: Option<&OsStr>,
+    instance_name: &OsStr,
     clear_usage: ClearUsage,
 ) -> Result<()> {
     let instance_path = get_instance_path(
@@ -129,10 +371,9 @@ pub(crate) fn enter_cpuacct_cgroup(
 }
 
 const MEMORY_DEFAULT_CONTROLLER_PATH: &str = "/sys/fs/cgroup/memory/ia-sandbox";
-const EXTRA_MEMORY_GIVEN: u64 = 16 * 1_024;
 pub(crate) fn enter_memory_cgroup(
     controller_path: Option<&Path>,
-    instance_name: Option<&OsStr>,
+    instance_name: &OsStr,
     memory_limit: Option<SpaceUsage>,
     clear_usage: ClearUsage,
 ) -> Result<()> {
@@ -150,17 +391,15 @@ pub(crate) fn enter_memory_cgroup(
         cgroup_write(&instance_path, "memory.memsw.limit_in_bytes", "-1\n").unwrap_or(());
         cgroup_write(&instance_path, "memory.limit_in_bytes", "-1\n")?;
         if let Some(memory_limit) = memory_limit {
-            // Assign some extra memory so that we can tell when a killed by signal 9 is actually a
-            // memory limit exceeded
             cgroup_write(
                 &instance_path,
                 "memory.limit_in_bytes",
-                format!("{}\n", memory_limit.as_bytes() + EXTRA_MEMORY_GIVEN),
+                format!("{}\n", memory_limit.as_bytes()),
             )?;
             cgroup_write(
                 &instance_path,
                 "memory.memsw.limit_in_bytes",
-                format!("{}\n", memory_limit.as_bytes() + EXTRA_MEMORY_GIVEN),
+                format!("{}\n", memory_limit.as_bytes()),
             )
             .unwrap_or(());
         }
@@ -172,7 +411,7 @@ pub(crate) fn enter_memory_cgroup(
 const PIDS_DEFAULT_CONTROLLER_PATH: &str = "/sys/fs/cgroup/pids/ia-sandbox";
 pub(crate) fn enter_pids_cgroup(
     controller_path: Option<&Path>,
-    instance_name: Option<&OsStr>,
+    instance_name: &OsStr,
     pids_limit: Option<usize>,
     clear_usage: ClearUsage,
 ) -> Result<()> {
@@ -192,12 +431,190 @@ pub(crate) fn enter_pids_cgroup(
     enter_cgroup(&instance_path)
 }
 
+fn format_list(list: &[usize]) -> String {
+    list.iter()
+        .map(usize::to_string)
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+const CPUSET_DEFAULT_CONTROLLER_PATH: &str = "/sys/fs/cgroup/cpuset/ia-sandbox";
+pub(crate) fn enter_cpuset_cgroup(
+    controller_path: &Path,
+    instance_name: &OsStr,
+    cpuset: &CpuSet,
+) -> Result<()> {
+    let instance_path = get_instance_path(controller_path, instance_name)?;
+    let cpus = format!("{}\n", format_list(cpuset.cpus()));
+    let mems = if cpuset.mems().is_empty() {
+        let mems: String = cgroup_read(controller_path, "cpuset.mems")?;
+        format!("{}\n", mems)
+    } else {
+        format!("{}\n", format_list(cpuset.mems()))
+    };
+
+    // Unlike other controllers, a new cpuset cgroup starts empty and no process can enter it
+    let isolated_path = get_instance_path(&instance_path, OsStr::new(ISOLATED_CGROUP_NAME))?;
+    for path in &[&instance_path, &isolated_path] {
+        cgroup_write(path, "cpuset.cpus", &cpus)?;
+        cgroup_write(path, "cpuset.mems", &mems)?;
+    }
+
+    enter_cgroup(&instance_path)
+}
+
+const CPU_DEFAULT_CONTROLLER_PATH: &str = "/sys/fs/cgroup/cpu/ia-sandbox";
+pub(crate) fn enter_cpu_cgroup(
+    controller_path: &Path,
+    instance_name: &OsStr,
+    cpu_quota: CpuQuota,
+) -> Result<()> {
+    let instance_path = get_instance_path(controller_path, instance_name)?;
+    let quota = cpu_quota.quota().as_micros();
+    let period = cpu_quota.period().as_micros();
+
+    if instance_path.join("cpu.max").exists() {
+        cgroup_write(&instance_path, "cpu.max", format!("{} {}\n", quota, period))?;
+    } else {
+        // Remove the old quota first, it might not fit the new period
+        cgroup_write(&instance_path, "cpu.cfs_quota_us", "-1\n")?;
+        cgroup_write(&instance_path, "cpu.cfs_period_us", format!("{}\n", period))?;
+        cgroup_write(&instance_path, "cpu.cfs_quota_us", format!("{}\n", quota))?;
+    }
+
+    enter_cgroup(&instance_path)
+}
+
+const BLKIO_DEFAULT_CONTROLLER_PATH: &str = "/sys/fs/cgroup/blkio/ia-sandbox";
+const BLKIO_V1_LIMIT_FILES: &[&str] = &[
+    "blkio.throttle.read_bps_device",
+    "blkio.throttle.write_bps_device",
+    "blkio.throttle.read_iops_device",
+    "blkio.throttle.write_iops_device",
+];
+pub(crate) fn enter_blkio_cgroup(
+    controller_path: &Path,
+    instance_name: &OsStr,
+    io_limits: Option<IoLimits>,
+    clear_usage: ClearUsage,
+) -> Result<()> {
+    let instance_path = get_instance_path(controller_path, instance_name)?;
+
+    if clear_usage == ClearUsage::Yes {
+        if instance_path.join("io.max").exists() {
+            // cgroup v2 statistics can not be reset
+            let old_limits: String = cgroup_read(&instance_path, "io.max")?;
+            for device in old_limits.lines().filter_map(|line| line.split(' ').next()) {
+                cgroup_write(
+                    &instance_path,
+                    "io.max",
+                    format!("{} rbps=max wbps=max riops=max wiops=max\n", device),
+                )?;
+            }
+            if let Some(io_limits) = io_limits {
+                let values = [
+                    ("rbps", io_limits.read_bps()),
+                    ("wbps", io_limits.write_bps()),
+                    ("riops", io_limits.read_iops()),
+                    ("wiops", io_limits.write_iops()),
+                ];
+                let mut line = io_limits.device().to_string();
+                for &(key, value) in &values {
+                    if let Some(value) = value {
+                        line.push_str(&format!(" {}={}", key, value));
+                    }
+                }
+                cgroup_write(&instance_path, "io.max", format!("{}\n", line))?;
+            }
+        } else {
+            cgroup_write(&instance_path, "blkio.reset_stats", "1\n")?;
+            for file in BLKIO_V1_LIMIT_FILES {
+                // A limit of 0 removes the rule for the device
+                let old_limits: String = cgroup_read(&instance_path, file)?;
+                for device in old_limits.lines().filter_map(|line| line.split(' ').next()) {
+                    cgroup_write(&instance_path, file, format!("{} 0\n", device))?;
+                }
+            }
+            if let Some(io_limits) = io_limits {
+                let values = [
+                    io_limits.read_bps(),
+                    io_limits.write_bps(),
+                    io_limits.read_iops(),
+                    io_limits.write_iops(),
+                ];
+                for (file, value) in BLKIO_V1_LIMIT_FILES.iter().zip(&values) {
+                    if let Some(value) = value {
+                        let line = format!("{} {}\n", io_limits.device(), value);
+                        cgroup_write(&instance_path, file, line)?;
+                    }
+                }
+            }
+        }
+    }
+
+    enter_cgroup(&instance_path)
+}
+
+/// Bytes read and written by all the devices (zero without the blkio controller).
+fn get_io_usage(controller_path: &ControllerPath, instance_name: &OsStr) -> IoUsage {
+    let instance_path = blkio_controller(controller_path).join(instance_name);
+    let mut read_bytes = 0;
+    let mut write_bytes = 0;
+
+    // v1 has `major:minor Read bytes` lines, v2 `major:minor rbytes=bytes wbytes=bytes ...`
+    let v1_stats: Result<String> =
+        cgroup_read(&instance_path, "blkio.throttle.io_service_bytes_recursive");
+    if let Ok(stats) = v1_stats {
+        for line in stats.lines() {
+            let parts = line.split_whitespace().collect::<Vec<_>>();
+            if let [_, operation, bytes] = parts[..] {
+                let bytes = bytes.parse::<u64>().unwrap_or(0);
+                match operation {
+                    "Read" => read_bytes += bytes,
+                    "Write" => write_bytes += bytes,
+                    _ => {}
+                }
+            }
+        }
+    } else if let Ok(stats) = cgroup_read::<_, String>(&instance_path, "io.stat") {
+        for (key, value) in stats
+            .split_whitespace()
+            .filter_map(|part| part.find('=').map(|index| part.split_at(index)))
+        {
+            let bytes = value[1..].parse::<u64>().unwrap_or(0);
+            match key {
+                "rbytes" => read_bytes += bytes,
+                "wbytes" => write_bytes += bytes,
+                _ => {}
+            }
+        }
+    }
+    IoUsage::new(read_bytes, write_bytes)
+}
+
+/// Throttling statistics of the cpu controller (zero if the jail had no cpu quota).
+///
+/// They can not be cleared, so a reused instance accumulates them over all its runs.
+fn get_cpu_throttling(controller_path: &ControllerPath, instance_name: &OsStr) -> CpuThrottling {
+    let instance_path = cpu_controller(controller_path).join(instance_name);
+    let nr_throttled = cgroup_read_key(&instance_path, "cpu.stat", "nr_throttled").unwrap_or(0);
+    // v1 counts in nanoseconds, v2 in microseconds
+    let throttled_time = cgroup_read_key(&instance_path, "cpu.stat", "throttled_time")
+        .map(Duration::from_nanos)
+        .or_else(|_| {
+            cgroup_read_key(&instance_path, "cpu.stat", "throttled_usec").map(Duration::from_micros)
+        })
+        .unwrap_or_default();
+    CpuThrottling::new(nr_throttled, throttled_time)
+}
+
 pub(crate) fn enter_all_cgroups(
     controller_path: &ControllerPath,
-    instance_name: Option<&OsStr>,
+    instance_name: &OsStr,
     limits: Limits,
     clear_usage: ClearUsage,
-) -> Result<()> {
+    cpuset: Option<&CpuSet>,
+) -> result::Result<(), ChildError> {
     enter_cpuacct_cgroup(controller_path.cpuacct(), instance_name, clear_usage)?;
     enter_memory_cgroup(
         controller_path.memory(),
@@ -210,12 +627,33 @@ pub(crate) fn enter_all_cgroups(
         instance_name,
         limits.pids(),
         clear_usage,
-    )
+    )?;
+
+    if let Some(cpuset) = cpuset {
+        let cpuset_path = cpuset_controller(controller_path);
+        if cpuset_path.exists() {
+            enter_cpuset_cgroup(cpuset_path, instance_name, cpuset)?;
+        } else {
+            // Without the controller the memory nodes can not be enforced
+            ffi::set_cpu_affinity(cpuset.cpus())?;
+        }
+    }
+
+    if let Some(cpu_quota) = limits.cpu_quota() {
+        enter_cpu_cgroup(cpu_controller(controller_path), instance_name, cpu_quota)?;
+    }
+
+    // Without limits the controller is optional, it only measures I/O
+    let blkio_path = blkio_controller(controller_path);
+    if limits.io().is_some() || blkio_path.exists() {
+        enter_blkio_cgroup(blkio_path, instance_name, limits.io(), clear_usage)?;
+    }
+    Ok(())
 }
 
 pub(crate) fn get_usage(
     controller_path: &ControllerPath,
-    instance_name: Option<&OsStr>,
+    instance_name: &OsStr,
     wall_time: Duration,
 ) -> Result<RunUsage> {
     let cpuacct_controller_path = controller_path
@@ -224,15 +662,127 @@ pub(crate) fn get_usage(
     let memory_controller_path = controller_path
         .memory()
         .unwrap_or_else(|| Path::new(MEMORY_DEFAULT_CONTROLLER_PATH));
-    let instance = instance_name.unwrap_or_else(|| OsStr::new(DEFAULT_INSTANCE_NAME));
-
-    let cpuacct_instance_path = cpuacct_controller_path.join(instance);
+    let cpuacct_instance_path = cpuacct_controller_path.join(instance_name);
     let user_time = Duration::from_nanos(cgroup_read(&cpuacct_instance_path, "cpuacct.usage")?);
 
-    let memory_instance_path = memory_controller_path.join(instance);
+    let memory_instance_path = memory_controller_path.join(instance_name);
     let memory = SpaceUsage::from_bytes(cmp::max(
         cgroup_read(&memory_instance_path, "memory.max_usage_in_bytes")?,
         cgroup_read(&memory_instance_path, "memory.memsw.max_usage_in_bytes").unwrap_or(0),
     ));
-    Ok(RunUsage::new(user_time, wall_time, memory))
+    // The idle time is up to whoever samples the usage over time, the disk usage to whoever
+    // set up the size limited mounts
+    Ok(RunUsage::new(
+        user_time,
+        wall_time,
+        memory,
+        Duration::from_secs(0),
+        get_cpu_throttling(controller_path, instance_name),
+        get_io_usage(controller_path, instance_name),
+        SpaceUsage::from_bytes(0),
+    ))
+}
+
+/// Counters of the memory cgroup that tell whether the memory limit was the problem. They can
+/// not be cleared, so compare them with the ones from before the run.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct MemoryEvents {
+    /// Processes killed by the OOM killer, from `memory.oom_control` (or `memory.events` with
+    /// cgroup v2). `None` if the kernel does not keep count (`oom_kill` appeared in Linux 4.13)
+    oom_kills: Option<u64>,
+    /// Times the memory limit was hit, from `memory.failcnt` (or `max` in `memory.events` with
+    /// cgroup v2). `None` if the kernel does not keep count
+    limit_hits: Option<u64>,
+}
+
+impl MemoryEvents {
+    pub(crate) fn oom_killed_since(&self, before: &Self) -> Option<bool> {
+        Some(self.oom_kills? > before.oom_kills?)
+    }
+
+    pub(crate) fn limit_hit_since(&self, before: &Self) -> Option<bool> {
+        Some(self.limit_hits? > before.limit_hits?)
+    }
+}
+
+/// Clears what the jail used so far, for jails prepared in advance whose setup should not count.
+pub(crate) fn reset_usage(controller_path: &ControllerPath, instance_name: &OsStr) -> Result<()> {
+    let cpuacct_instance_path = controller_path
+        .cpuacct()
+        .unwrap_or_else(|| Path::new(CPUACCT_DEFAULT_CONTROLLER_PATH))
+        .join(instance_name);
+    cgroup_write(&cpuacct_instance_path, "cpuacct.usage", "0\n")?;
+
+    let memory_instance_path = controller_path
+        .memory()
+        .unwrap_or_else(|| Path::new(MEMORY_DEFAULT_CONTROLLER_PATH))
+        .join(instance_name);
+    cgroup_write(&memory_instance_path, "memory.max_usage_in_bytes", "0\n")?;
+    cgroup_write(
+        &memory_instance_path,
+        "memory.memsw.max_usage_in_bytes",
+        "0\n",
+    )
+    .unwrap_or(());
+
+    // Without the controller (or with cgroup v2) there is nothing to reset
+    let blkio_instance_path = blkio_controller(controller_path).join(instance_name);
+    cgroup_write(&blkio_instance_path, "blkio.reset_stats", "1\n").unwrap_or(());
+    Ok(())
+}
+
+/// The processes (not threads) inside the jail, as seen from the supervisor's pid namespace.
+pub(crate) fn get_processes(
+    controller_path: &ControllerPath,
+    instance_name: &OsStr,
+) -> Vec<libc::pid_t> {
+    let pids_controller_path = controller_path
+        .pids()
+        .unwrap_or_else(|| Path::new(PIDS_DEFAULT_CONTROLLER_PATH));
+    let isolated_path = pids_controller_path
+        .join(instance_name)
+        .join(ISOLATED_CGROUP_NAME);
+
+    fs::read_to_string(isolated_path.join("cgroup.procs"))
+        .map(|procs| procs.lines().filter_map(|pid| pid.parse().ok()).collect())
+        .unwrap_or_default()
+}
+
+pub(crate) fn get_memory_events(
+    controller_path: &ControllerPath,
+    instance_name: &OsStr,
+) -> MemoryEvents {
+    let memory_controller_path = controller_path
+        .memory()
+        .unwrap_or_else(|| Path::new(MEMORY_DEFAULT_CONTROLLER_PATH));
+    let instance_path = memory_controller_path.join(instance_name);
+    // Kills are counted where the killed process lives, limit hits where the limit is
+    let isolated_path = instance_path.join(ISOLATED_CGROUP_NAME);
+    if !isolated_path.exists() {
+        return MemoryEvents {
+            oom_kills: Some(0),
+            limit_hits: Some(0),
+        };
+    }
+
+    MemoryEvents {
+        oom_kills: cgroup_read_key(&isolated_path, "memory.oom_control", "oom_kill")
+            .or_else(|_| cgroup_read_key(&isolated_path, "memory.events", "oom_kill"))
+            .ok(),
+        limit_hits: get_limit_hits(&instance_path),
+    }
+}
+
+fn get_limit_hits(instance_path: &Path) -> Option<u64> {
+    let failcnt = match cgroup_read::<_, u64>(instance_path, "memory.failcnt") {
+        Ok(failcnt) => failcnt,
+        Err(_) => return cgroup_read_key(instance_path, "memory.events", "max").ok(),
+    };
+    // With swap accounted the memsw counter is charged first, so it is the one failing at the
+    // limit. Newer kernels do not count its failures, a count still at 0 tells nothing.
+    match cgroup_read::<_, u64>(instance_path, "memory.memsw.failcnt") {
+        Ok(0) => None,
+        Ok(memsw_failcnt) => Some(failcnt + memsw_failcnt),
+        Err(_) => Some(failcnt),
+    }
 }
@@ -0,0 +1,269 @@
+//! Checking the output of a program against the expected answer.
+//!
+//! The built-in comparators cover the usual cases, anything else is left to a custom checker
+//! running in its own sandbox. Custom checkers follow the testlib convention: they get the input,
+//! the output and the answer as their last arguments, exit with 0 (accepted), 1 (wrong answer)
+//! or 2 (presentation error) and explain the verdict on stderr.
+
+use std::borrow::Cow;
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::path::Path;
+
+use config::{Config, Mount, MountOptions};
+use errors::{CheckerError, Errno, Result};
+use run_info::{CheckResult, RunInfo, RunInfoResult, Verdict};
+use tempfile;
+use JailHandle;
+
+/// Where the files are mounted inside the new root of a custom checker.
+const CHECKER_FILES_DIR: &str = "/ia-sandbox-checker";
+
+/// Tokens and lines longer than this are cut short in the messages.
+const MESSAGE_MAX_LENGTH: usize = 64;
+
+#[derive(Debug, PartialEq)]
+pub enum Checker {
+    /// Byte for byte. Outputs differing only in whitespace are a presentation error
+    Exact,
+    /// Line by line, ignoring how much whitespace separates the tokens of a line, trailing
+    /// whitespace and trailing empty lines. Outputs differing only in line breaks are a
+    /// presentation error
+    IgnoreWhitespace,
+    /// Token by token, ignoring whitespace entirely
+    Tokens,
+    /// Token by token, numbers are equal if within this absolute or relative error
+    Floats(f64),
+    /// A checker program, run in a sandbox with this configuration. With a new root the files
+    /// are mounted (read only) under `/ia-sandbox-checker`
+    Custom(Box<Config>),
+}
+
+impl Checker {
+    /// Compares the `output` of a program (which read `input`) with the expected `answer`.
+    pub fn check(&self, input: &Path, output: &Path, answer: &Path) -> Result<CheckResult> {
+        match *self {
+            Self::Exact => Ok(compare_exact(&read(output)?, &read(answer)?)),
+            Self::IgnoreWhitespace => Ok(compare_lines(&read(output)?, &read(answer)?)),
+            Self::Tokens => Ok(compare_tokens(&read(output)?, &read(answer)?, None)),
+            Self::Floats(epsilon) => Ok(compare_tokens(
+                &read(output)?,
+                &read(answer)?,
+                Some(epsilon),
+            )),
+            Self::Custom(ref config) => run_custom(config, input, output, answer),
+        }
+    }
+
+    /// Checks the output of a run and adds the verdict to it. Only successful runs are checked.
+    pub fn check_run<T>(
+        &self,
+        run_info: RunInfo<T>,
+        input: &Path,
+        output: &Path,
+        answer: &Path,
+    ) -> Result<RunInfo<T>> {
+        if !run_info.is_success() {
+            return Ok(run_info);
+        }
+        let check = self.check(input, output, answer)?;
+        Ok(run_info.with_check(check))
+    }
+}
+
+fn read(path: &Path) -> Result<Vec<u8>> {
+    fs::read(path).map_err(|err| {
+        CheckerError::ReadError {
+            path: path.to_path_buf(),
+            errno: Errno::from(&err),
+        }
+        .into()
+    })
+}
+
+fn tokens(contents: &[u8]) -> Vec<&[u8]> {
+    contents
+        .split(u8::is_ascii_whitespace)
+        .filter(|token| !token.is_empty())
+        .collect()
+}
+
+/// The tokens of every line, without the trailing empty lines.
+fn lines(contents: &[u8]) -> Vec<Vec<&[u8]>> {
+    let mut lines: Vec<Vec<&[u8]>> = contents.split(|&byte| byte == b'\n').map(tokens).collect();
+    let empty = lines
+        .iter()
+        .rev()
+        .take_while(|line| line.is_empty())
+        .count();
+    lines.truncate(lines.len() - empty);
+    lines
+}
+
+fn shorten(bytes: &[u8]) -> Cow<'_, str> {
+    if bytes.len() <= MESSAGE_MAX_LENGTH {
+        String::from_utf8_lossy(bytes)
+    } else {
+        Cow::Owned(format!(
+            "{}...",
+            String::from_utf8_lossy(&bytes[..MESSAGE_MAX_LENGTH])
+        ))
+    }
+}
+
+fn compare_exact(output: &[u8], answer: &[u8]) -> CheckResult {
+    if output == answer {
+        return CheckResult::new(Verdict::Accepted, String::new());
+    }
+    if tokens(output) == tokens(answer) {
+        return CheckResult::new(
+            Verdict::PresentationError,
+            "Outputs differ only in whitespace".to_owned(),
+        );
+    }
+
+    let output_lines: Vec<&[u8]> = output.split(|&byte| byte == b'\n').collect();
+    let answer_lines: Vec<&[u8]> = answer.split(|&byte| byte == b'\n').collect();
+    let line = output_lines
+        .iter()
+        .zip(&answer_lines)
+        .position(|(output_line, answer_line)| output_line != answer_line)
+        .unwrap_or_else(|| output_lines.len().min(answer_lines.len()));
+    CheckResult::new(Verdict::WrongAnswer, format!("Line {} differs", line + 1))
+}
+
+fn compare_lines(output: &[u8], answer: &[u8]) -> CheckResult {
+    let output_lines = lines(output);
+    let answer_lines = lines(answer);
+    if output_lines == answer_lines {
+        return CheckResult::new(Verdict::Accepted, format!("{} lines", answer_lines.len()));
+    }
+    if tokens(output) == tokens(answer) {
+        return CheckResult::new(
+            Verdict::PresentationError,
+            "Outputs differ only in line breaks".to_owned(),
+        );
+    }
+
+    let message = match output_lines
+        .iter()
+        .zip(&answer_lines)
+        .position(|(output_line, answer_line)| output_line != answer_line)
+    {
+        Some(line) => format!(
+            "Line {} differs: expected `{}`, found `{}`",
+            line + 1,
+            shorten(&answer_lines[line].join(&b' ')),
+            shorten(&output_lines[line].join(&b' '))
+        ),
+        None => format!(
+            "Expected {} lines, found {}",
+            answer_lines.len(),
+            output_lines.len()
+        ),
+    };
+    CheckResult::new(Verdict::WrongAnswer, message)
+}
+
+fn compare_tokens(output: &[u8], answer: &[u8], epsilon: Option<f64>) -> CheckResult {
+    let output_tokens = tokens(output);
+    let answer_tokens = tokens(answer);
+
+    for (index, (output_token, answer_token)) in
+        output_tokens.iter().zip(&answer_tokens).enumerate()
+    {
+        if !tokens_equal(output_token, answer_token, epsilon) {
+            return CheckResult::new(
+                Verdict::WrongAnswer,
+                format!(
+                    "Token {} differs: expected `{}`, found `{}`",
+                    index + 1,
+                    shorten(answer_token),
+                    shorten(output_token)
+                ),
+            );
+        }
+    }
+    if output_tokens.len() != answer_tokens.len() {
+        return CheckResult::new(
+            Verdict::WrongAnswer,
+            format!(
+                "Expected {} tokens, found {}",
+                answer_tokens.len(),
+                output_tokens.len()
+            ),
+        );
+    }
+    CheckResult::new(Verdict::Accepted, format!("{} tokens", answer_tokens.len()))
+}
+
+fn tokens_equal(output: &[u8], answer: &[u8], epsilon: Option<f64>) -> bool {
+    if output == answer {
+        return true;
+    }
+    let parse = |token: &[u8]| -> Option<f64> { String::from_utf8_lossy(token).parse().ok() };
+    match (epsilon, parse(output), parse(answer)) {
+        (Some(epsilon), Some(output), Some(answer)) => {
+            let error = (output - answer).abs();
+            error <= epsilon || error <= epsilon * answer.abs()
+        }
+        _ => false,
+    }
+}
+
+fn run_custom(config: &Config, input: &Path, output: &Path, answer: &Path) -> Result<CheckResult> {
+    // Created here (exclusively, with a random name), the jail only opens it
+    let message_file = tempfile::Builder::new()
+        .prefix("ia-sandbox-checker-")
+        .tempfile()
+        .map_err(|err| CheckerError::CreateFileError {
+            path: env::temp_dir(),
+            errno: Errno::from(&err),
+        })?;
+    let message_path = message_file.path().to_path_buf();
+
+    let mut args: Vec<OsString> = config.args().into_iter().map(OsStr::to_os_string).collect();
+    let mut mounts = config.mounts().to_vec();
+    for (name, path) in &[("input", input), ("output", output), ("answer", answer)] {
+        if config.new_root().is_some() {
+            let destination = Path::new(CHECKER_FILES_DIR).join(name);
+            args.push(destination.clone().into_os_string());
+            mounts.push(Mount::new(
+                path.to_path_buf(),
+                destination,
+                MountOptions::default(),
+            ));
+        } else {
+            args.push(path.as_os_str().to_os_string());
+        }
+    }
+
+    let checker_config = config
+        .clone()
+        .with_command(config.command().to_path_buf(), args)
+        .with_redirect_stderr(Some(message_path.clone()))
+        .with_mounts(mounts);
+
+    let run_info = ::spawn_jail(&checker_config).and_then(JailHandle::wait);
+    let message = fs::read(&message_path)
+        .map(|message| String::from_utf8_lossy(&message).trim().to_owned())
+        .unwrap_or_default();
+    drop(message_file);
+
+    let verdict = match *run_info?.result() {
+        RunInfoResult::Success(()) => Verdict::Accepted,
+        RunInfoResult::NonZeroExitStatus(1) => Verdict::WrongAnswer,
+        RunInfoResult::NonZeroExitStatus(2) => Verdict::PresentationError,
+        RunInfoResult::NonZeroExitStatus(exit_code) => {
+            return Err(CheckerError::CheckerFailed { exit_code, message }.into());
+        }
+        ref result => {
+            return Err(CheckerError::CheckerNotFinished {
+                result: result.to_string(),
+            }
+            .into())
+        }
+    };
+    Ok(CheckResult::new(verdict, message))
+}
@@ -1,56 +1,48 @@
 use std::ffi::{OsStr, OsString};
 use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::io;
+use std::os::unix::fs::MetadataExt;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::time::Duration;
 
-#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[derive(Debug, Default, Eq, PartialEq, Copy, Clone)]
 pub enum ShareNet {
     Share,
+    #[default]
     Unshare,
 }
 
-impl Default for ShareNet {
-    fn default() -> Self {
-        Self::Unshare
-    }
-}
-
-#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[derive(Debug, Default, Eq, PartialEq, Copy, Clone)]
 pub enum SwapRedirects {
     Yes,
+    #[default]
     No,
 }
 
-impl Default for SwapRedirects {
-    fn default() -> Self {
-        Self::No
-    }
-}
-
-#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[derive(Debug, Default, Eq, PartialEq, Copy, Clone)]
 pub enum ClearUsage {
+    #[default]
     Yes,
     No,
 }
 
-impl Default for ClearUsage {
-    fn default() -> Self {
-        Self::Yes
-    }
+/// Whether to also record the usage of every process and thread in the jail
+#[derive(Debug, Default, Eq, PartialEq, Copy, Clone)]
+pub enum DetailedUsage {
+    Yes,
+    #[default]
+    No,
 }
 
-#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[derive(Debug, Default, Eq, PartialEq, Copy, Clone)]
 pub enum Interactive {
     Yes,
+    #[default]
     No,
 }
 
-impl Default for Interactive {
-    fn default() -> Self {
-        Self::No
-    }
-}
-
 #[derive(Debug, Eq, PartialEq, Copy, Clone, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct SpaceUsage(u64);
 
@@ -112,6 +104,199 @@ impl Display for SpaceUsage {
     }
 }
 
+/// Soft limits for user and wall time. When one of them is exceeded the process is sent
+/// `signal`, and has until the matching hard limit in [`Limits`] to shut down gracefully.
+///
+/// The jailed process is the init of its pid namespace, so the signal only has an effect if the
+/// process handles it.
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct SoftLimits {
+    wall_time: Option<Duration>,
+    user_time: Option<Duration>,
+    signal: libc::c_int,
+}
+
+impl SoftLimits {
+    pub fn new(
+        wall_time: Option<Duration>,
+        user_time: Option<Duration>,
+        signal: libc::c_int,
+    ) -> Self {
+        Self {
+            wall_time,
+            user_time,
+            signal,
+        }
+    }
+
+    pub fn wall_time(&self) -> Option<Duration> {
+        self.wall_time
+    }
+
+    pub fn user_time(&self) -> Option<Duration> {
+        self.user_time
+    }
+
+    pub fn signal(&self) -> libc::c_int {
+        self.signal
+    }
+}
+
+impl Default for SoftLimits {
+    fn default() -> Self {
+        Self::new(None, None, libc::SIGTERM)
+    }
+}
+
+/// How much cpu time a jail gets every period, across all its threads
+///
+/// A quota of 150ms every 100ms period lets a multi-threaded program use at most 1.5 cores,
+/// after that it is throttled until the next period.
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct CpuQuota {
+    quota: Duration,
+    period: Duration,
+}
+
+impl CpuQuota {
+    pub fn new(quota: Duration, period: Duration) -> Self {
+        Self { quota, period }
+    }
+
+    /// A quota of `millicores` thousandths of a core, with the default period of 100ms.
+    pub fn from_millicores(millicores: u64) -> Self {
+        Self::new(
+            Duration::from_micros(millicores * 100),
+            Duration::from_millis(100),
+        )
+    }
+
+    pub fn quota(&self) -> Duration {
+        self.quota
+    }
+
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+}
+
+/// A block device, as `major:minor`
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct IoDevice {
+    major: u32,
+    minor: u32,
+}
+
+impl IoDevice {
+    pub fn new(major: u32, minor: u32) -> Self {
+        Self { major, minor }
+    }
+
+    /// The disk holding `path`. I/O limits only work on whole disks, so partitions are
+    /// resolved to their disk.
+    pub fn containing<T: AsRef<Path>>(path: T) -> io::Result<Self> {
+        let dev = fs::metadata(path)?.dev();
+        let device = Self::new(libc::major(dev), libc::minor(dev));
+
+        let sys_path = PathBuf::from(format!("/sys/dev/block/{}", device));
+        if !sys_path.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} is not a block device", device),
+            ));
+        }
+        if !sys_path.join("partition").exists() {
+            return Ok(device);
+        }
+
+        fs::read_to_string(sys_path.join("../dev"))?
+            .trim()
+            .parse()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    pub fn major(self) -> u32 {
+        self.major
+    }
+
+    pub fn minor(self) -> u32 {
+        self.minor
+    }
+}
+
+impl Display for IoDevice {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        write!(fmt, "{}:{}", self.major, self.minor)
+    }
+}
+
+impl FromStr for IoDevice {
+    type Err = String;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid device {}, expected major:minor", string);
+        let mut parts = string.splitn(2, ':');
+        let major = parts.next().and_then(|major| major.parse().ok());
+        let minor = parts.next().and_then(|minor| minor.parse().ok());
+        match (major, minor) {
+            (Some(major), Some(minor)) => Ok(Self::new(major, minor)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// Bandwidth and operations per second limits for reading and writing a device
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct IoLimits {
+    device: IoDevice,
+    read_bps: Option<u64>,
+    write_bps: Option<u64>,
+    read_iops: Option<u64>,
+    write_iops: Option<u64>,
+}
+
+impl IoLimits {
+    pub fn new(
+        device: IoDevice,
+        read_bps: Option<u64>,
+        write_bps: Option<u64>,
+        read_iops: Option<u64>,
+        write_iops: Option<u64>,
+    ) -> Self {
+        Self {
+            device,
+            read_bps,
+            write_bps,
+            read_iops,
+            write_iops,
+        }
+    }
+
+    pub fn device(&self) -> IoDevice {
+        self.device
+    }
+
+    /// Bytes read per second
+    pub fn read_bps(&self) -> Option<u64> {
+        self.read_bps
+    }
+
+    /// Bytes written per second
+    pub fn write_bps(&self) -> Option<u64> {
+        self.write_bps
+    }
+
+    /// Read operations per second
+    pub fn read_iops(&self) -> Option<u64> {
+        self.read_iops
+    }
+
+    /// Write operations per second
+    pub fn write_iops(&self) -> Option<u64> {
+        self.write_iops
+    }
+}
+
 /// Limits for memory/time
 #[derive(Debug, Eq, PartialEq, Copy, Clone)]
 pub struct Limits {
@@ -120,15 +305,24 @@ pub struct Limits {
     memory: Option<SpaceUsage>,
     stack: Option<SpaceUsage>,
     pids: Option<usize>,
+    idle_time: Option<Duration>,
+    soft: SoftLimits,
+    cpu_quota: Option<CpuQuota>,
+    io: Option<IoLimits>,
 }
 
 impl Limits {
+    #![allow(clippy::too_many_arguments)]
     pub fn new(
         wall_time: Option<Duration>,
         user_time: Option<Duration>,
         memory: Option<SpaceUsage>,
         stack: Option<SpaceUsage>,
         pids: Option<usize>,
+        idle_time: Option<Duration>,
+        soft: SoftLimits,
+        cpu_quota: Option<CpuQuota>,
+        io: Option<IoLimits>,
     ) -> Self {
         Self {
             wall_time,
@@ -136,6 +330,10 @@ impl Limits {
             memory,
             stack,
             pids,
+            idle_time,
+            soft,
+            cpu_quota,
+            io,
         }
     }
 
@@ -158,11 +356,41 @@ impl Limits {
     pub fn pids(&self) -> Option<usize> {
         self.pids
     }
+
+    /// How long the process may go without using the CPU (e.g. blocked reading or deadlocked)
+    pub fn idle_time(&self) -> Option<Duration> {
+        self.idle_time
+    }
+
+    /// The soft limits, the user and wall time limits above are the matching hard limits
+    pub fn soft(&self) -> SoftLimits {
+        self.soft
+    }
+
+    /// Throttles the process instead of killing it, see [`CpuQuota`]
+    pub fn cpu_quota(&self) -> Option<CpuQuota> {
+        self.cpu_quota
+    }
+
+    /// Throttles reading and writing a device, see [`IoLimits`]
+    pub fn io(&self) -> Option<IoLimits> {
+        self.io
+    }
 }
 
 impl Default for Limits {
     fn default() -> Self {
-        Self::new(None, None, None, None, None)
+        Self::new(
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            SoftLimits::default(),
+            None,
+            None,
+        )
     }
 }
 
@@ -171,33 +399,59 @@ pub struct ControllerPath {
     cpuacct: Option<PathBuf>,
     memory: Option<PathBuf>,
     pids: Option<PathBuf>,
+    cpuset: Option<PathBuf>,
+    cpu: Option<PathBuf>,
+    blkio: Option<PathBuf>,
 }
 
 impl ControllerPath {
-    pub fn new(cpuacct: Option<PathBuf>, memory: Option<PathBuf>, pids: Option<PathBuf>) -> Self {
+    pub fn new(
+        cpuacct: Option<PathBuf>,
+        memory: Option<PathBuf>,
+        pids: Option<PathBuf>,
+        cpuset: Option<PathBuf>,
+        cpu: Option<PathBuf>,
+        blkio: Option<PathBuf>,
+    ) -> Self {
         Self {
             cpuacct,
             memory,
             pids,
+            cpuset,
+            cpu,
+            blkio,
         }
     }
 
     pub fn cpuacct(&self) -> Option<&Path> {
-        self.cpuacct.as_ref().map(PathBuf::as_path)
+        self.cpuacct.as_deref()
     }
 
     pub fn memory(&self) -> Option<&Path> {
-        self.memory.as_ref().map(PathBuf::as_path)
+        self.memory.as_deref()
     }
 
     pub fn pids(&self) -> Option<&Path> {
-        self.pids.as_ref().map(PathBuf::as_path)
+        self.pids.as_deref()
+    }
+
+    pub fn cpuset(&self) -> Option<&Path> {
+        self.cpuset.as_deref()
+    }
+
+    pub fn cpu(&self) -> Option<&Path> {
+        self.cpu.as_deref()
+    }
+
+    /// The blkio (or v2 io) controller
+    pub fn blkio(&self) -> Option<&Path> {
+        self.blkio.as_deref()
     }
 }
 
 impl Default for ControllerPath {
     fn default() -> Self {
-        Self::new(None, None, None)
+        Self::new(None, None, None, None, None, None)
     }
 }
 
@@ -206,6 +460,7 @@ pub struct MountOptions {
     read_only: bool,
     dev: bool,
     exec: bool,
+    size_limit: Option<SpaceUsage>,
 }
 
 impl MountOptions {
@@ -221,6 +476,14 @@ impl MountOptions {
         self.exec
     }
 
+    /// The most a writable directory mount can hold
+    ///
+    /// The jail works on a size limited copy of the directory, which is copied back once it is
+    /// done. Writing past the limit fails with `ENOSPC`.
+    pub fn size_limit(self) -> Option<SpaceUsage> {
+        self.size_limit
+    }
+
     pub fn set_read_only(&mut self, value: bool) {
         self.read_only = value;
     }
@@ -232,6 +495,10 @@ impl MountOptions {
     pub fn set_exec(&mut self, value: bool) {
         self.exec = value;
     }
+
+    pub fn set_size_limit(&mut self, value: Option<SpaceUsage>) {
+        self.size_limit = value;
+    }
 }
 
 impl Default for MountOptions {
@@ -240,6 +507,7 @@ impl Default for MountOptions {
             read_only: true,
             dev: false,
             exec: false,
+            size_limit: None,
         }
     }
 }
@@ -285,7 +553,63 @@ impl Default for Environment {
     }
 }
 
-#[derive(Debug, Eq, PartialEq)]
+/// Filesystem access rules enforced with Landlock right before exec
+///
+/// Paths are resolved inside the jail (after pivoting to the new root, if any). Anything not
+/// covered by one of the rules can not be read, written or executed by the sandboxed program.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct LandlockRules {
+    read: Vec<PathBuf>,
+    write: Vec<PathBuf>,
+    exec: Vec<PathBuf>,
+}
+
+impl LandlockRules {
+    pub fn new(read: Vec<PathBuf>, write: Vec<PathBuf>, exec: Vec<PathBuf>) -> Self {
+        Self { read, write, exec }
+    }
+
+    /// Paths where files can be read and directories listed
+    pub fn read(&self) -> &[PathBuf] {
+        self.read.as_ref()
+    }
+
+    /// Paths where files can be written, created and removed
+    pub fn write(&self) -> &[PathBuf] {
+        self.write.as_ref()
+    }
+
+    /// Paths where files can be executed (implies read, shared libraries must be read to load)
+    pub fn exec(&self) -> &[PathBuf] {
+        self.exec.as_ref()
+    }
+}
+
+/// The cpus and memory nodes a jail is restricted to
+///
+/// Without memory nodes the ones of the parent cgroup are used. Without the cpuset controller
+/// only the cpus are enforced (through the cpu affinity of the process).
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct CpuSet {
+    cpus: Vec<usize>,
+    mems: Vec<usize>,
+}
+
+impl CpuSet {
+    pub fn new(cpus: Vec<usize>, mems: Vec<usize>) -> Self {
+        Self { cpus, mems }
+    }
+
+    pub fn cpus(&self) -> &[usize] {
+        self.cpus.as_ref()
+    }
+
+    pub fn mems(&self) -> &[usize] {
+        self.mems.as_ref()
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
 pub struct Config {
     command: PathBuf,
     args: Vec<OsString>,
@@ -302,6 +626,10 @@ pub struct Config {
     clear_usage: ClearUsage,
     interactive: Interactive,
     environment: Environment,
+    landlock: Option<LandlockRules>,
+    cpuset: Option<CpuSet>,
+    detailed_usage: DetailedUsage,
+    memory_baseline: Option<SpaceUsage>,
 }
 
 impl Config {
@@ -322,6 +650,10 @@ impl Config {
         clear_usage: ClearUsage,
         interactive: Interactive,
         environment: Environment,
+        landlock: Option<LandlockRules>,
+        cpuset: Option<CpuSet>,
+        detailed_usage: DetailedUsage,
+        memory_baseline: Option<SpaceUsage>,
     ) -> Self {
         Self {
             command,
@@ -339,6 +671,145 @@ impl Config {
             clear_usage,
             interactive,
             environment,
+            landlock,
+            cpuset,
+            detailed_usage,
+            memory_baseline,
+        }
+    }
+
+    /// Runs `command` with `args`, everything else is left to the defaults. The rest is set
+    /// with the `with_*` methods, which also make modified copies of configurations.
+    pub fn for_command(command: PathBuf, args: Vec<OsString>) -> Self {
+        Self::new(
+            command,
+            args,
+            None,
+            ShareNet::default(),
+            None,
+            None,
+            None,
+            Limits::default(),
+            None,
+            ControllerPath::default(),
+            Vec::new(),
+            SwapRedirects::default(),
+            ClearUsage::default(),
+            Interactive::default(),
+            Environment::default(),
+            None,
+            None,
+            DetailedUsage::default(),
+            None,
+        )
+    }
+
+    pub fn with_command(self, command: PathBuf, args: Vec<OsString>) -> Self {
+        Self {
+            command,
+            args,
+            ..self
+        }
+    }
+
+    pub fn with_new_root(self, new_root: Option<PathBuf>) -> Self {
+        Self { new_root, ..self }
+    }
+
+    pub fn with_share_net(self, share_net: ShareNet) -> Self {
+        Self { share_net, ..self }
+    }
+
+    pub fn with_redirect_stdin(self, redirect_stdin: Option<PathBuf>) -> Self {
+        Self {
+            redirect_stdin,
+            ..self
+        }
+    }
+
+    pub fn with_redirect_stdout(self, redirect_stdout: Option<PathBuf>) -> Self {
+        Self {
+            redirect_stdout,
+            ..self
+        }
+    }
+
+    pub fn with_redirect_stderr(self, redirect_stderr: Option<PathBuf>) -> Self {
+        Self {
+            redirect_stderr,
+            ..self
+        }
+    }
+
+    pub fn with_limits(self, limits: Limits) -> Self {
+        Self { limits, ..self }
+    }
+
+    pub fn with_instance_name(self, instance_name: Option<OsString>) -> Self {
+        Self {
+            instance_name,
+            ..self
+        }
+    }
+
+    pub fn with_controller_path(self, controller_path: ControllerPath) -> Self {
+        Self {
+            controller_path,
+            ..self
+        }
+    }
+
+    pub fn with_mounts(self, mounts: Vec<Mount>) -> Self {
+        Self { mounts, ..self }
+    }
+
+    pub fn with_swap_redirects(self, swap_redirects: SwapRedirects) -> Self {
+        Self {
+            swap_redirects,
+            ..self
+        }
+    }
+
+    pub fn with_clear_usage(self, clear_usage: ClearUsage) -> Self {
+        Self {
+            clear_usage,
+            ..self
+        }
+    }
+
+    pub fn with_interactive(self, interactive: Interactive) -> Self {
+        Self {
+            interactive,
+            ..self
+        }
+    }
+
+    pub fn with_environment(self, environment: Environment) -> Self {
+        Self {
+            environment,
+            ..self
+        }
+    }
+
+    pub fn with_landlock(self, landlock: Option<LandlockRules>) -> Self {
+        Self { landlock, ..self }
+    }
+
+    pub fn with_cpuset(self, cpuset: Option<CpuSet>) -> Self {
+        Self { cpuset, ..self }
+    }
+
+    pub fn with_detailed_usage(self, detailed_usage: DetailedUsage) -> Self {
+        Self {
+            detailed_usage,
+            ..self
+        }
+    }
+
+    pub fn with_memory_baseline(self, memory_baseline: Option<SpaceUsage>) -> Self {
+        Self {
+            memory_baseline,
+            ..self
         }
     }
 
@@ -351,7 +822,7 @@ impl Config {
     }
 
     pub fn new_root(&self) -> Option<&Path> {
-        self.new_root.as_ref().map(PathBuf::as_path)
+        self.new_root.as_deref()
     }
 
     pub fn share_net(&self) -> ShareNet {
@@ -359,15 +830,15 @@ impl Config {
     }
 
     pub fn redirect_stdin(&self) -> Option<&Path> {
-        self.redirect_stdin.as_ref().map(PathBuf::as_path)
+        self.redirect_stdin.as_deref()
     }
 
     pub fn redirect_stdout(&self) -> Option<&Path> {
-        self.redirect_stdout.as_ref().map(PathBuf::as_path)
+        self.redirect_stdout.as_deref()
     }
 
     pub fn redirect_stderr(&self) -> Option<&Path> {
-        self.redirect_stderr.as_ref().map(PathBuf::as_path)
+        self.redirect_stderr.as_deref()
     }
 
     pub fn limits(&self) -> Limits {
@@ -375,7 +846,7 @@ impl Config {
     }
 
     pub fn instance_name(&self) -> Option<&OsStr> {
-        self.instance_name.as_ref().map(OsString::as_os_str)
+        self.instance_name.as_deref()
     }
 
     pub fn controller_path(&self) -> &ControllerPath {
@@ -401,4 +872,22 @@ impl Config {
     pub fn environment(&self) -> &Environment {
         &self.environment
     }
+
+    pub fn landlock(&self) -> Option<&LandlockRules> {
+        self.landlock.as_ref()
+    }
+
+    pub fn cpuset(&self) -> Option<&CpuSet> {
+        self.cpuset.as_ref()
+    }
+
+    pub fn detailed_usage(&self) -> DetailedUsage {
+        self.detailed_usage
+    }
+
+    /// The memory the runtime takes by itself (like the JVM), not counted in the usage. The
+    /// memory limit enforced is raised by it.
+    pub fn memory_baseline(&self) -> Option<SpaceUsage> {
+        self.memory_baseline
+    }
 }
@@ -0,0 +1,116 @@
+//! Handing out exclusive cpus to jails running at the same time.
+//!
+//! Jails sharing a core disturb each other's timing. [`CpuAllocator`] gives every jail its own
+//! cores, coordinating through lock files so that separate sandbox processes (e.g. several
+//! judges on the same machine) agree on who uses which core.
+
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
+
+use config::CpuSet;
+use errors::{CpuSetError, Errno, Result};
+use ffi;
+use libc;
+
+const DEFAULT_LOCK_DIR_NAME: &str = "ia-sandbox-cpus";
+
+#[derive(Debug, Clone)]
+pub struct CpuAllocator {
+    lock_dir: PathBuf,
+}
+
+impl CpuAllocator {
+    /// All the allocators sharing `lock_dir` hand out different cpus.
+    pub fn new(lock_dir: PathBuf) -> Self {
+        Self { lock_dir }
+    }
+
+    pub fn lock_dir(&self) -> &Path {
+        &self.lock_dir
+    }
+
+    /// Locks `count` of the cpus this process may run on, which no one else holds.
+    ///
+    /// The cpus are held until the returned [`CpuLease`] is dropped (or the process dies).
+    pub fn allocate(&self, count: usize) -> Result<CpuLease> {
+        fs::create_dir_all(&self.lock_dir).map_err(|err| CpuSetError::CreateDirError {
+            path: self.lock_dir.clone(),
+            errno: Errno::from(&err),
+        })?;
+
+        let mut cpus = Vec::new();
+        let mut locks = Vec::new();
+        for cpu in ffi::get_cpu_affinity()? {
+            if cpus.len() == count {
+                break;
+            }
+
+            let path = self.lock_dir.join(format!("cpu{}.lock", cpu));
+            let file = OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(&path)
+                .map_err(|err| CpuSetError::OpenLockError {
+                    path: path.clone(),
+                    errno: Errno::from(&err),
+                })?;
+
+            if ffi::try_lock_exclusive(&file, &path)? {
+                cpus.push(cpu);
+                locks.push(file);
+            }
+        }
+
+        if cpus.len() < count {
+            return Err(CpuSetError::NotEnoughCpus {
+                requested: count,
+                available: cpus.len(),
+            }
+            .into());
+        }
+        Ok(CpuLease {
+            cpus,
+            _locks: locks,
+        })
+    }
+}
+
+impl Default for CpuAllocator {
+    fn default() -> Self {
+        Self::new(env::temp_dir().join(DEFAULT_LOCK_DIR_NAME))
+    }
+}
+
+/// Checks that every cpu fits in a cpu affinity mask (`CPU_SETSIZE`), the kernel does not know
+/// of any cpu beyond that.
+pub fn check_cpus(cpus: &[usize]) -> Result<()> {
+    match cpus.iter().find(|&&cpu| cpu >= libc::CPU_SETSIZE as usize) {
+        Some(&cpu) => Err(CpuSetError::CpuOutOfRange {
+            cpu,
+            max: libc::CPU_SETSIZE as usize - 1,
+        }
+        .into()),
+        None => Ok(()),
+    }
+}
+
+/// Exclusive cpus, released on drop.
+#[derive(Debug)]
+pub struct CpuLease {
+    cpus: Vec<usize>,
+    // Closing the files releases the locks
+    _locks: Vec<File>,
+}
+
+impl CpuLease {
+    pub fn cpus(&self) -> &[usize] {
+        &self.cpus
+    }
+
+    /// A cpuset restricting a jail to the leased cpus.
+    pub fn cpuset(&self, mems: Vec<usize>) -> CpuSet {
+        CpuSet::new(self.cpus.clone(), mems)
+    }
+}
@@ -0,0 +1,333 @@
+//! Diagnosing why the sandbox can not run on this machine.
+//!
+//! Every prerequisite of the jail is probed the same way [`spawn_jail`](../fn.spawn_jail.html)
+//! uses it (cloning into new namespaces, mapping the user, creating cgroup instances) and
+//! failures come with an explanation of how to fix them.
+
+use std::fmt;
+use std::path::Path;
+use std::result;
+
+use cgroups;
+use config::{ControllerPath, Limits, ShareNet};
+use errors::{FFIError, Result};
+use ffi;
+use run_info::{RunInfoResult, RunUsage};
+use setup::{self, CGroupVersion};
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub enum Status {
+    Ok,
+    /// The sandbox works, but some feature is unavailable
+    Warning,
+    /// The sandbox will not work
+    Failed,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::Ok => write!(f, "ok"),
+            Self::Warning => write!(f, "warning"),
+            Self::Failed => write!(f, "failed"),
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Check {
+    name: String,
+    status: Status,
+    error: Option<String>,
+    explanation: Option<String>,
+}
+
+impl Check {
+    fn ok<T: Into<String>>(name: T) -> Self {
+        Self {
+            name: name.into(),
+            status: Status::Ok,
+            error: None,
+            explanation: None,
+        }
+    }
+
+    fn not_ok<T1: Into<String>, T2: Into<String>>(
+        name: T1,
+        status: Status,
+        error: String,
+        explanation: T2,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            status,
+            error: Some(error),
+            explanation: Some(explanation.into()),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn status(&self) -> Status {
+        self.status
+    }
+
+    /// What went wrong while probing.
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    /// How to fix it.
+    pub fn explanation(&self) -> Option<&str> {
+        self.explanation.as_deref()
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{}] {}", self.status, self.name)?;
+        if let Some(ref error) = self.error {
+            write!(f, "\n    {}", error)?;
+        }
+        if let Some(ref explanation) = self.explanation {
+            for line in explanation.lines() {
+                write!(f, "\n    {}", line)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Report {
+    checks: Vec<Check>,
+}
+
+impl Report {
+    pub fn checks(&self) -> &[Check] {
+        &self.checks
+    }
+
+    /// Whether the sandbox can run (warnings are fine).
+    pub fn is_ok(&self) -> bool {
+        self.checks
+            .iter()
+            .all(|check| check.status() != Status::Failed)
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for check in &self.checks {
+            writeln!(f, "{}", check)?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs `f` in a child cloned like the supervisor of a jail.
+fn probe<F>(share_net: ShareNet, f: F) -> result::Result<(), String>
+where
+    F: FnOnce() -> result::Result<(), FFIError> + Send,
+{
+    let run_info = ffi::clone(share_net, false, f)
+        .map_err(|err| err.to_string())?
+        .wait(Limits::default(), |_| Ok(RunUsage::default()))
+        .map_err(|err| err.to_string())?;
+
+    match *run_info.result() {
+        RunInfoResult::Success(Some(Ok(()))) => Ok(()),
+        RunInfoResult::Success(Some(Err(ref err))) => Err(err.to_string()),
+        RunInfoResult::Success(None) => Err("Probe process did not report back".into()),
+        ref result => Err(format!("Probe process failed: {}", result)),
+    }
+}
+
+fn user_namespaces_explanation() -> String {
+    let mut explanation = String::from("Unprivileged user namespaces are not available.");
+    if setup::read_sysctl("/proc/sys/user/max_user_namespaces") == Some(0) {
+        explanation.push_str("\nEnable them with `sysctl -w user.max_user_namespaces=15000`.");
+    }
+    if setup::read_sysctl("/proc/sys/kernel/unprivileged_userns_clone") == Some(0) {
+        explanation.push_str("\nEnable them with `sysctl -w kernel.unprivileged_userns_clone=1`.");
+    }
+    if setup::read_sysctl("/proc/sys/kernel/apparmor_restrict_unprivileged_userns") == Some(1) {
+        explanation.push_str(
+            "\nAppArmor restricts them, allow them with \
+             `sysctl -w kernel.apparmor_restrict_unprivileged_userns=0`.",
+        );
+    }
+    explanation.push_str("\nInside a container, the container runtime may forbid them too.");
+    explanation
+}
+
+fn check_namespaces() -> Check {
+    const NAME: &str = "user, pid, ipc, uts and mount namespaces";
+    match probe(ShareNet::Share, || Ok(())) {
+        Ok(()) => Check::ok(NAME),
+        Err(error) => Check::not_ok(NAME, Status::Failed, error, user_namespaces_explanation()),
+    }
+}
+
+fn check_net_namespace() -> Check {
+    const NAME: &str = "network namespace";
+    match probe(ShareNet::Unshare, || Ok(())) {
+        Ok(()) => Check::ok(NAME),
+        Err(error) => Check::not_ok(
+            NAME,
+            Status::Warning,
+            error,
+            "A new network namespace can not be created, use --share-net.",
+        ),
+    }
+}
+
+fn check_uid_gid_maps() -> Check {
+    const NAME: &str = "uid/gid maps";
+    let user_group_id = ffi::get_user_group_id();
+    match probe(ShareNet::Share, || ffi::set_uid_gid_maps(user_group_id)) {
+        Ok(()) => Check::ok(NAME),
+        Err(error) => Check::not_ok(
+            NAME,
+            Status::Failed,
+            error,
+            "The user can not be mapped inside the user namespace.\n\
+             Usually a security module (AppArmor, SELinux) or the container runtime forbids\n\
+             writing /proc/self/uid_map or /proc/self/setgroups.",
+        ),
+    }
+}
+
+fn check_proc_mount() -> Check {
+    const NAME: &str = "/proc mount";
+    match probe(ShareNet::Share, ffi::mount_proc) {
+        Ok(()) => Check::ok(NAME),
+        Err(error) => Check::not_ok(
+            NAME,
+            Status::Failed,
+            error,
+            "A new /proc can not be mounted inside the pid namespace.\n\
+             This happens inside containers where parts of /proc are masked, run the\n\
+             container with an unmasked /proc (e.g. docker --security-opt systempaths=unconfined).",
+        ),
+    }
+}
+
+fn check_cgroup_namespace() -> Check {
+    const NAME: &str = "cgroup namespace";
+    match probe(ShareNet::Share, ffi::unshare_cgroup) {
+        Ok(()) => Check::ok(NAME),
+        Err(error) => Check::not_ok(
+            NAME,
+            Status::Failed,
+            error,
+            "cgroup namespaces need Linux 4.6 or newer, built with CONFIG_CGROUPS.",
+        ),
+    }
+}
+
+fn check_controller(name: &str, path: &Path) -> Check {
+    let check_name = format!("{} cgroup at {}", name, path.display());
+    match cgroups::probe_controller(path) {
+        Ok(()) => Check::ok(check_name),
+        Err(error) => {
+            let mut explanation = format!(
+                "The {} hierarchy must exist and be writable by this user.\n\
+                 Create it with `sudo ia-sandbox setup --user $USER`",
+                name
+            );
+            if CGroupVersion::detect().ok() == Some(CGroupVersion::V2) {
+                explanation.push_str(
+                    ",\nbut note the cgroup v1 controllers are needed for running jails.",
+                );
+            } else {
+                explanation.push('.');
+            }
+            Check::not_ok(check_name, Status::Failed, error.to_string(), explanation)
+        }
+    }
+}
+
+fn check_swap_accounting(memory_path: &Path) -> Check {
+    const NAME: &str = "swap accounting";
+    if memory_path.join("memory.memsw.limit_in_bytes").exists() {
+        Check::ok(NAME)
+    } else {
+        Check::not_ok(
+            NAME,
+            Status::Warning,
+            format!("No memory.memsw files under {}", memory_path.display()),
+            "Memory limits do not cover swap, a jail could use more memory by swapping.\n\
+             Boot the kernel with `swapaccount=1` (or disable swap).",
+        )
+    }
+}
+
+fn check_optional_controller(name: &str, path: &Path) -> Check {
+    let check_name = format!("{} cgroup at {}", name, path.display());
+    match cgroups::probe_controller(path) {
+        Ok(()) => Check::ok(check_name),
+        Err(error) => {
+            let consequence = match name {
+                "cpuset" => {
+                    "Jails with --cpus or --exclusive-cpus fall back to the cpu affinity, which\n\
+                     the program can change, and --mems is not enforced."
+                }
+                "cpu" => "Jails with --cpu-quota can not run.",
+                "blkio" => "Jails with --io-* limits can not run, and I/O is not measured.",
+                _ => "Some jails can not run.",
+            };
+            Check::not_ok(
+                check_name,
+                Status::Warning,
+                error.to_string(),
+                format!(
+                    "{}\nCreate it with `sudo ia-sandbox setup --user $USER`.",
+                    consequence
+                ),
+            )
+        }
+    }
+}
+
+fn check_landlock() -> Check {
+    const NAME: &str = "landlock";
+    match ffi::landlock_abi() {
+        Ok(_) => Check::ok(NAME),
+        Err(error) => Check::not_ok(
+            NAME,
+            Status::Warning,
+            error.to_string(),
+            "Landlock rules (--landlock-*) can not be used.\n\
+             They need Linux 5.13 or newer with `landlock` in the `lsm=` boot parameter.",
+        ),
+    }
+}
+
+/// Probes everything a jail needs, for the given controller paths.
+pub fn diagnose(controller_path: &ControllerPath) -> Result<Report> {
+    ffi::set_sig_alarm_handler()?;
+
+    let mut checks = vec![
+        check_namespaces(),
+        check_net_namespace(),
+        check_uid_gid_maps(),
+        check_proc_mount(),
+        check_cgroup_namespace(),
+    ];
+
+    for &(name, path) in &cgroups::controllers(controller_path) {
+        checks.push(check_controller(name, path));
+        if name == "memory" {
+            checks.push(check_swap_accounting(path));
+        }
+    }
+    for &(name, path) in &cgroups::optional_controllers(controller_path) {
+        checks.push(check_optional_controller(name, path));
+    }
+    checks.push(check_landlock());
+
+    Ok(Report { checks })
+}
@@ -1,106 +1,257 @@
-use std::ffi::OsString;
+use std::ffi::CStr;
+use std::fmt::{self, Display, Formatter};
+use std::io;
 use std::path::PathBuf;
 use std::result::Result as StdResult;
 
-#[derive(Fail, Debug, Serialize, Deserialize)]
+use libc;
+
+use doctor::{Check, Status};
+
+/// The error number (`errno`) a system call failed with, like `libc::EPERM`.
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Serialize, Deserialize)]
+pub struct Errno(i32);
+
+impl Errno {
+    pub fn new(code: i32) -> Self {
+        Self(code)
+    }
+
+    /// The errno of the last failed system call of this thread.
+    pub(crate) fn last() -> Self {
+        Self(unsafe { *libc::__errno_location() })
+    }
+
+    pub fn code(self) -> i32 {
+        self.0
+    }
+}
+
+impl<'a> From<&'a io::Error> for Errno {
+    /// Errors not coming from the operating system are reported as `EIO`.
+    fn from(err: &'a io::Error) -> Self {
+        Self(err.raw_os_error().unwrap_or(libc::EIO))
+    }
+}
+
+impl Display for Errno {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let buffer: &mut [libc::c_char] = &mut [0; 256];
+        // The XSI variant, which returns an error number on failure (older glibc returns -1 and
+        // sets errno instead)
+        if unsafe { libc::strerror_r(self.0, buffer.as_mut_ptr(), buffer.len()) } != 0 {
+            return write!(f, "Unknown error {}", self.0);
+        }
+        let message = unsafe { CStr::from_ptr(buffer.as_ptr()) };
+        write!(f, "{}", message.to_string_lossy())
+    }
+}
+
+/// What `pivot_root` failing with `errno` means, see `pivot_root(2)`.
+fn pivot_root_reason(errno: &Errno) -> String {
+    match errno.code() {
+        libc::EBUSY => "new_root or put_old are on the current root filesystem, or a \
+                        filesystem is already mounted on put_old"
+            .into(),
+        libc::EINVAL => "put_old is not underneath new_root".into(),
+        libc::ENOTDIR => "new_root or put_old is not a directory".into(),
+        libc::EPERM => "The calling process does not have the CAP_SYS_ADMIN capability".into(),
+        _ => errno.to_string(),
+    }
+}
+
+#[derive(Error, Debug, Serialize, Deserialize)]
 pub enum FFIError {
-    #[fail(display = "Could not chdir to {:?}: {}", path, error)]
-    ChdirError { path: PathBuf, error: String },
-    #[fail(display = "Could not chroot to {:?}: {}", path, error)]
-    ChrootError { path: PathBuf, error: String },
-    #[fail(display = "Could not clone process: {}", _0)]
-    CloneError(String),
-    #[fail(display = "Could not dup file descriptor {}({}): {}", name, fd, error)]
-    DupFdError {
-        fd: i32,
-        name: String,
-        error: String,
-    },
-    #[fail(display = "Could not create directory {:?}: {}", path, error)]
-    CreateDirError { path: PathBuf, error: String },
-    #[fail(
-        display = "Could not exec {:?} (arguments: {:?}): {}",
-        command, arguments, error
-    )]
+    #[error("Could not chdir to {:?}: {}", .path, .errno)]
+    ChdirError { path: PathBuf, errno: Errno },
+    #[error("Could not chown {:?}: {}", .path, .errno)]
+    ChownError { path: PathBuf, errno: Errno },
+    #[error("Could not chroot to {:?}: {}", .path, .errno)]
+    ChrootError { path: PathBuf, errno: Errno },
+    #[error("Could not clone process: {}", .errno)]
+    CloneError { errno: Errno },
+    #[error("Could not dup file descriptor {}({}): {}", .name, .fd, .errno)]
+    DupFdError { fd: i32, name: String, errno: Errno },
+    #[error("Could not create directory {:?}: {}", .path, .errno)]
+    CreateDirError { path: PathBuf, errno: Errno },
+    #[error("Could not exec {:?} (arguments: {:?}): {}", .command, .arguments, .errno)]
     ExecError {
         command: PathBuf,
-        arguments: Vec<OsString>,
-        error: String,
+        arguments: Vec<String>,
+        errno: Errno,
     },
-    #[fail(display = "Could not mount path: {:?}: {}", path, error)]
-    MountError { path: PathBuf, error: String },
-    #[fail(display = "Could not open file descriptor {}({}): {}", name, fd, error)]
-    OpenFdError {
-        fd: i32,
-        name: String,
-        error: String,
-    },
-    #[fail(display = "Could not create pipe: {}", _0)]
-    Pipe2Error(String),
-    #[fail(
-        display = "Could not pivot_root to {:?} with old root at {:?}: {}",
-        new_root, old_root, error
+    #[error("Could not lock {:?}: {}", .path, .errno)]
+    FlockError { path: PathBuf, errno: Errno },
+    #[error("Could not add Landlock rule for {:?}: {}", .path, .errno)]
+    LandlockAddRuleError { path: PathBuf, errno: Errno },
+    #[error("Could not create Landlock ruleset: {}", .errno)]
+    LandlockCreateRulesetError { errno: Errno },
+    #[error("Could not enforce Landlock ruleset: {}", .errno)]
+    LandlockRestrictSelfError { errno: Errno },
+    #[error("Invalid message: {}", .error)]
+    MessageError { error: String },
+    #[error("Could not mount path: {:?}: {}", .path, .errno)]
+    MountError { path: PathBuf, errno: Errno },
+    #[error("Could not open file descriptor {}({}): {}", .name, .fd, .errno)]
+    OpenFdError { fd: i32, name: String, errno: Errno },
+    #[error("Could not create pipe: {}", .errno)]
+    Pipe2Error { errno: Errno },
+    #[error(
+        "Could not pivot_root to {:?} with old root at {:?}: {}",
+        .new_root, .old_root, pivot_root_reason(.errno)
     )]
     PivotRootError {
         new_root: PathBuf,
         old_root: PathBuf,
-        error: String,
+        errno: Errno,
     },
-    #[fail(display = "Could not set process to die when parent dies: {}", _0)]
-    PrSetPDeathSigError(String),
-    #[fail(display = "Could not set interval timer alarm: {}", _0)]
-    SetITimerError(String),
-    #[fail(
-        display = "Could not set process group id of {} to {}: {}",
-        pid, pgid, error
-    )]
-    SetpgidError { pid: i32, pgid: i32, error: String },
-    #[fail(display = "Could not set resource limit: {}", _0)]
-    SetRLimitError(String),
-    #[fail(display = "Could not set a signal handler for {}: {}", signal, error)]
-    SigActionError { signal: String, error: String },
-    #[fail(display = "Could not umount path: {:?}: {}", path, error)]
-    UMountError { path: PathBuf, error: String },
-    #[fail(display = "Could not unshare cgroup namespace: {}", _0)]
-    UnshareCGroupError(String),
-    #[fail(display = "Could not usleep for {} microseconds: {}", time, error)]
-    UsleepError { time: u32, error: String },
-    #[fail(display = "Could not write /proc/self/uid_map file: {}", _0)]
-    WriteUidError(String),
-    #[fail(display = "Could not write /proc/self/uid_map file: {}", _0)]
-    WriteGidError(String),
-    #[fail(display = "Could not wait for process: {}", _0)]
-    WaitPidError(String),
-    #[fail(display = "Could not write /proc/self/setgroups file: {}", _0)]
-    WriteSetGroupsError(String),
-}
-
-#[derive(Fail, Debug, Serialize, Deserialize)]
+    #[error("Could not set no new privileges flag: {}", .errno)]
+    PrSetNoNewPrivsError { errno: Errno },
+    #[error("Could not set process to die when parent dies: {}", .errno)]
+    PrSetPDeathSigError { errno: Errno },
+    #[error("Could not receive message: {}", .errno)]
+    RecvMsgError { errno: Errno },
+    #[error("Could not get cpu affinity: {}", .errno)]
+    SchedGetAffinityError { errno: Errno },
+    #[error("Could not set cpu affinity: {}", .errno)]
+    SchedSetAffinityError { errno: Errno },
+    #[error("Could not send message: {}", .errno)]
+    SendMsgError { errno: Errno },
+    #[error("Could not set interval timer alarm: {}", .errno)]
+    SetITimerError { errno: Errno },
+    #[error("Could not set process group id of {} to {}: {}", .pid, .pgid, .errno)]
+    SetpgidError { pid: i32, pgid: i32, errno: Errno },
+    #[error("Could not set resource limit: {}", .errno)]
+    SetRLimitError { errno: Errno },
+    #[error("Could not set a signal handler for {}: {}", .signal, .errno)]
+    SigActionError { signal: String, errno: Errno },
+    #[error("Could not create socket pair: {}", .errno)]
+    SocketPairError { errno: Errno },
+    #[error("Could not get filesystem statistics for {:?}: {}", .path, .errno)]
+    StatvfsError { path: PathBuf, errno: Errno },
+    #[error("Could not umount path: {:?}: {}", .path, .errno)]
+    UMountError { path: PathBuf, errno: Errno },
+    #[error("Could not unshare cgroup namespace: {}", .errno)]
+    UnshareCGroupError { errno: Errno },
+    #[error("Could not usleep for {} microseconds: {}", .time, .errno)]
+    UsleepError { time: u32, errno: Errno },
+    #[error("Could not write /proc/self/uid_map file: {}", .errno)]
+    WriteUidError { errno: Errno },
+    #[error("Could not write /proc/self/uid_map file: {}", .errno)]
+    WriteGidError { errno: Errno },
+    #[error("Could not wait for process: {}", .errno)]
+    WaitPidError { errno: Errno },
+    #[error("Could not write /proc/self/setgroups file: {}", .errno)]
+    WriteSetGroupsError { errno: Errno },
+}
+
+impl FFIError {
+    /// A stable identifier of the error, `ffi.` followed by what failed.
+    pub fn code(&self) -> &'static str {
+        match *self {
+            Self::ChdirError { .. } => "ffi.chdir",
+            Self::ChownError { .. } => "ffi.chown",
+            Self::ChrootError { .. } => "ffi.chroot",
+            Self::CloneError { .. } => "ffi.clone",
+            Self::DupFdError { .. } => "ffi.dup_fd",
+            Self::CreateDirError { .. } => "ffi.create_dir",
+            Self::ExecError { .. } => "ffi.exec",
+            Self::FlockError { .. } => "ffi.flock",
+            Self::LandlockAddRuleError { .. } => "ffi.landlock_add_rule",
+            Self::LandlockCreateRulesetError { .. } => "ffi.landlock_create_ruleset",
+            Self::LandlockRestrictSelfError { .. } => "ffi.landlock_restrict_self",
+            Self::MessageError { .. } => "ffi.message",
+            Self::MountError { .. } => "ffi.mount",
+            Self::OpenFdError { .. } => "ffi.open_fd",
+            Self::Pipe2Error { .. } => "ffi.pipe2",
+            Self::PivotRootError { .. } => "ffi.pivot_root",
+            Self::PrSetNoNewPrivsError { .. } => "ffi.pr_set_no_new_privs",
+            Self::PrSetPDeathSigError { .. } => "ffi.pr_set_pdeath_sig",
+            Self::RecvMsgError { .. } => "ffi.recv_msg",
+            Self::SchedGetAffinityError { .. } => "ffi.sched_get_affinity",
+            Self::SchedSetAffinityError { .. } => "ffi.sched_set_affinity",
+            Self::SendMsgError { .. } => "ffi.send_msg",
+            Self::SetITimerError { .. } => "ffi.set_itimer",
+            Self::SetpgidError { .. } => "ffi.setpgid",
+            Self::SetRLimitError { .. } => "ffi.set_rlimit",
+            Self::SigActionError { .. } => "ffi.sig_action",
+            Self::SocketPairError { .. } => "ffi.socket_pair",
+            Self::StatvfsError { .. } => "ffi.statvfs",
+            Self::UMountError { .. } => "ffi.umount",
+            Self::UnshareCGroupError { .. } => "ffi.unshare_cgroup",
+            Self::UsleepError { .. } => "ffi.usleep",
+            Self::WriteUidError { .. } => "ffi.write_uid",
+            Self::WriteGidError { .. } => "ffi.write_gid",
+            Self::WaitPidError { .. } => "ffi.wait_pid",
+            Self::WriteSetGroupsError { .. } => "ffi.write_set_groups",
+        }
+    }
+
+    /// The errno of the failed system call, if the error comes from one.
+    pub fn errno(&self) -> Option<Errno> {
+        match *self {
+            Self::ChdirError { errno, .. }
+            | Self::ChownError { errno, .. }
+            | Self::ChrootError { errno, .. }
+            | Self::CloneError { errno, .. }
+            | Self::DupFdError { errno, .. }
+            | Self::CreateDirError { errno, .. }
+            | Self::ExecError { errno, .. }
+            | Self::FlockError { errno, .. }
+            | Self::LandlockAddRuleError { errno, .. }
+            | Self::LandlockCreateRulesetError { errno, .. }
+            | Self::LandlockRestrictSelfError { errno, .. }
+            | Self::MountError { errno, .. }
+            | Self::OpenFdError { errno, .. }
+            | Self::Pipe2Error { errno, .. }
+            | Self::PivotRootError { errno, .. }
+            | Self::PrSetNoNewPrivsError { errno, .. }
+            | Self::PrSetPDeathSigError { errno, .. }
+            | Self::RecvMsgError { errno, .. }
+            | Self::SchedGetAffinityError { errno, .. }
+            | Self::SchedSetAffinityError { errno, .. }
+            | Self::SendMsgError { errno, .. }
+            | Self::SetITimerError { errno, .. }
+            | Self::SetpgidError { errno, .. }
+            | Self::SetRLimitError { errno, .. }
+            | Self::SigActionError { errno, .. }
+            | Self::SocketPairError { errno, .. }
+            | Self::StatvfsError { errno, .. }
+            | Self::UMountError { errno, .. }
+            | Self::UnshareCGroupError { errno, .. }
+            | Self::UsleepError { errno, .. }
+            | Self::WriteUidError { errno, .. }
+            | Self::WriteGidError { errno, .. }
+            | Self::WaitPidError { errno, .. }
+            | Self::WriteSetGroupsError { errno, .. } => Some(errno),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Error, Debug, Serialize, Deserialize)]
 pub enum CGroupError {
-    #[fail(display = "Cgroup controller missing: {:?}", _0)]
-    ControllerMissing(PathBuf),
-    #[fail(
-        display = "Could not create instance controller under {:?} for {:?}: {}",
-        controller_path, instance_name, error
-    )]
+    #[error("Cgroup controller missing: {:?}", .controller_path)]
+    ControllerMissing { controller_path: PathBuf },
+    #[error("Could not create instance controller under {:?} for {:?}: {}", .controller_path, .instance_name, .errno)]
     InstanceControllerCreateError {
         controller_path: PathBuf,
-        instance_name: OsString,
-        error: String,
+        instance_name: String,
+        errno: Errno,
     },
-    #[fail(
-        display = "Could not open {:?} for controller {:?}: {}",
-        file, controller_path, error
-    )]
+    #[error("Could not remove instance controller under {:?} for {:?}: {}", .controller_path, .instance_name, .errno)]
+    InstanceControllerRemoveError {
+        controller_path: PathBuf,
+        instance_name: String,
+        errno: Errno,
+    },
+    #[error("Could not open {:?} for controller {:?}: {}", .file, .controller_path, .errno)]
     OpenCGroupFileError {
         controller_path: PathBuf,
         file: PathBuf,
-        error: String,
+        errno: Errno,
     },
-    #[fail(
-        display = "Could not parse `{}` from {:?} for controller {:?}: {}",
-        buffer, file, controller_path, error
-    )]
+    #[error("Could not parse `{}` from {:?} for controller {:?}: {}", .buffer, .file, .controller_path, .error)]
     ParseCGroupFileError {
         controller_path: PathBuf,
         file: PathBuf,
@@ -108,32 +259,324 @@ pub enum CGroupError {
         error: String,
     },
 
-    #[fail(
-        display = "Could not read from {:?} for controller {:?}: {}",
-        file, controller_path, error
-    )]
+    #[error("Could not read from {:?} for controller {:?}: {}", .file, .controller_path, .errno)]
     ReadCGroupFileError {
         controller_path: PathBuf,
         file: PathBuf,
-        error: String,
+        errno: Errno,
     },
-    #[fail(
-        display = "Could not write to {:?} for controller {:?}: {}",
-        file, controller_path, error
-    )]
+    #[error("Could not write to {:?} for controller {:?}: {}", .file, .controller_path, .errno)]
     WriteCGroupFileError {
         controller_path: PathBuf,
         file: PathBuf,
-        error: String,
+        errno: Errno,
     },
 }
 
-#[derive(Fail, Debug, Serialize, Deserialize)]
+impl CGroupError {
+    /// A stable identifier of the error, `cgroup.` followed by what failed.
+    pub fn code(&self) -> &'static str {
+        match *self {
+            Self::ControllerMissing { .. } => "cgroup.controller_missing",
+            Self::InstanceControllerCreateError { .. } => "cgroup.instance_controller_create",
+            Self::InstanceControllerRemoveError { .. } => "cgroup.instance_controller_remove",
+            Self::OpenCGroupFileError { .. } => "cgroup.open_file",
+            Self::ParseCGroupFileError { .. } => "cgroup.parse_file",
+            Self::ReadCGroupFileError { .. } => "cgroup.read_file",
+            Self::WriteCGroupFileError { .. } => "cgroup.write_file",
+        }
+    }
+
+    /// The errno of the failed system call, if the error comes from one.
+    pub fn errno(&self) -> Option<Errno> {
+        match *self {
+            Self::InstanceControllerCreateError { errno, .. }
+            | Self::InstanceControllerRemoveError { errno, .. }
+            | Self::OpenCGroupFileError { errno, .. }
+            | Self::ReadCGroupFileError { errno, .. }
+            | Self::WriteCGroupFileError { errno, .. } => Some(errno),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Error, Debug, Serialize, Deserialize)]
+pub enum RootfsError {
+    #[error("Could not copy {:?} to {:?}: {}", .from, .to, .errno)]
+    CopyError {
+        from: PathBuf,
+        to: PathBuf,
+        errno: Errno,
+    },
+    #[error("Could not create directory {:?}: {}", .path, .errno)]
+    CreateDirError { path: PathBuf, errno: Errno },
+    #[error("Invalid ELF file {:?}: {}", .path, .error)]
+    InvalidElfError { path: PathBuf, error: String },
+    #[error("Could not find library {} needed by {:?}", .library, .needed_by)]
+    LibraryNotFound { library: String, needed_by: PathBuf },
+    #[error("Could not open ELF file {:?}: {}", .path, .errno)]
+    OpenElfError { path: PathBuf, errno: Errno },
+    #[error("Could not read ELF file {:?}: {}", .path, .errno)]
+    ReadElfError { path: PathBuf, errno: Errno },
+}
+
+impl RootfsError {
+    /// A stable identifier of the error, `rootfs.` followed by what failed.
+    pub fn code(&self) -> &'static str {
+        match *self {
+            Self::CopyError { .. } => "rootfs.copy",
+            Self::CreateDirError { .. } => "rootfs.create_dir",
+            Self::InvalidElfError { .. } => "rootfs.invalid_elf",
+            Self::LibraryNotFound { .. } => "rootfs.library_not_found",
+            Self::OpenElfError { .. } => "rootfs.open_elf",
+            Self::ReadElfError { .. } => "rootfs.read_elf",
+        }
+    }
+
+    /// The errno of the failed system call, if the error comes from one.
+    pub fn errno(&self) -> Option<Errno> {
+        match *self {
+            Self::CopyError { errno, .. }
+            | Self::CreateDirError { errno, .. }
+            | Self::OpenElfError { errno, .. }
+            | Self::ReadElfError { errno, .. } => Some(errno),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Error, Debug, Serialize, Deserialize)]
+pub enum SetupError {
+    #[error("Cgroup controller {} is not available at {:?}", .controller, .path)]
+    ControllerNotAvailable { controller: String, path: PathBuf },
+    #[error("Could not create directory {:?}: {}", .path, .errno)]
+    CreateDirError { path: PathBuf, errno: Errno },
+    #[error("Could not detect the cgroup version, nothing mounted at {:?}", .path)]
+    UnknownCGroupVersion { path: PathBuf },
+    #[error("Could not find user {}", .user)]
+    UserNotFound { user: String },
+    #[error("Could not write to {:?}: {}", .path, .errno)]
+    WriteError { path: PathBuf, errno: Errno },
+}
+
+impl SetupError {
+    /// A stable identifier of the error, `setup.` followed by what failed.
+    pub fn code(&self) -> &'static str {
+        match *self {
+            Self::ControllerNotAvailable { .. } => "setup.controller_not_available",
+            Self::CreateDirError { .. } => "setup.create_dir",
+            Self::UnknownCGroupVersion { .. } => "setup.unknown_cgroup_version",
+            Self::UserNotFound { .. } => "setup.user_not_found",
+            Self::WriteError { .. } => "setup.write",
+        }
+    }
+
+    /// The errno of the failed system call, if the error comes from one.
+    pub fn errno(&self) -> Option<Errno> {
+        match *self {
+            Self::CreateDirError { errno, .. } | Self::WriteError { errno, .. } => Some(errno),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Error, Debug, Serialize, Deserialize)]
+pub enum CpuSetError {
+    #[error("Cpu {} is out of range, the highest cpu is {}", .cpu, .max)]
+    CpuOutOfRange { cpu: usize, max: usize },
+    #[error("Could not create lock directory {:?}: {}", .path, .errno)]
+    CreateDirError { path: PathBuf, errno: Errno },
+    #[error("Could not allocate {} exclusive cpus, only {} are free", .requested, .available)]
+    NotEnoughCpus { requested: usize, available: usize },
+    #[error("Could not open lock file {:?}: {}", .path, .errno)]
+    OpenLockError { path: PathBuf, errno: Errno },
+}
+
+impl CpuSetError {
+    /// A stable identifier of the error, `cpuset.` followed by what failed.
+    pub fn code(&self) -> &'static str {
+        match *self {
+            Self::CpuOutOfRange { .. } => "cpuset.cpu_out_of_range",
+            Self::CreateDirError { .. } => "cpuset.create_dir",
+            Self::NotEnoughCpus { .. } => "cpuset.not_enough_cpus",
+            Self::OpenLockError { .. } => "cpuset.open_lock",
+        }
+    }
+
+    /// The errno of the failed system call, if the error comes from one.
+    pub fn errno(&self) -> Option<Errno> {
+        match *self {
+            Self::CreateDirError { errno, .. } | Self::OpenLockError { errno, .. } => Some(errno),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Error, Debug, Serialize, Deserialize)]
+pub enum DiskQuotaError {
+    #[error("Could not copy {:?} to {:?}: {}", .from, .to, .errno)]
+    CopyError {
+        from: PathBuf,
+        to: PathBuf,
+        errno: Errno,
+    },
+    #[error("Could not create mount point {:?}: {}", .path, .errno)]
+    CreateDirError { path: PathBuf, errno: Errno },
+    #[error("Size limited mount source {:?} is not a directory", .path)]
+    NotADirectory { path: PathBuf },
+    #[error("Could not empty mount point {:?}: {}", .path, .errno)]
+    RemoveError { path: PathBuf, errno: Errno },
+}
+
+impl DiskQuotaError {
+    /// A stable identifier of the error, `disk_quota.` followed by what failed.
+    pub fn code(&self) -> &'static str {
+        match *self {
+            Self::CopyError { .. } => "disk_quota.copy",
+            Self::CreateDirError { .. } => "disk_quota.create_dir",
+            Self::NotADirectory { .. } => "disk_quota.not_a_directory",
+            Self::RemoveError { .. } => "disk_quota.remove",
+        }
+    }
+
+    /// The errno of the failed system call, if the error comes from one.
+    pub fn errno(&self) -> Option<Errno> {
+        match *self {
+            Self::CopyError { errno, .. }
+            | Self::CreateDirError { errno, .. }
+            | Self::RemoveError { errno, .. } => Some(errno),
+            Self::NotADirectory { .. } => None,
+        }
+    }
+}
+
+#[derive(Error, Debug, Serialize, Deserialize)]
+pub enum CheckerError {
+    #[error("Checker failed with exit code {}: {}", .exit_code, .message)]
+    CheckerFailed { exit_code: u32, message: String },
+    #[error("Checker did not finish: {}", .result)]
+    CheckerNotFinished { result: String },
+    #[error("Could not create checker file {:?}: {}", .path, .errno)]
+    CreateFileError { path: PathBuf, errno: Errno },
+    #[error("Could not read {:?}: {}", .path, .errno)]
+    ReadError { path: PathBuf, errno: Errno },
+}
+
+impl CheckerError {
+    /// A stable identifier of the error, `checker.` followed by what failed.
+    pub fn code(&self) -> &'static str {
+        match *self {
+            Self::CheckerFailed { .. } => "checker.failed",
+            Self::CheckerNotFinished { .. } => "checker.not_finished",
+            Self::CreateFileError { .. } => "checker.create_file",
+            Self::ReadError { .. } => "checker.read",
+        }
+    }
+
+    /// The errno of the failed system call, if the error comes from one.
+    pub fn errno(&self) -> Option<Errno> {
+        match *self {
+            Self::CreateFileError { errno, .. } | Self::ReadError { errno, .. } => Some(errno),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Error, Debug, Serialize, Deserialize)]
+pub enum JobError {
+    #[error("The compile stage did not produce the artifact {:?}", .path)]
+    ArtifactMissing { path: PathBuf },
+    #[error("Could not copy artifact {:?} to {:?}: {}", .from, .to, .errno)]
+    CopyArtifactError {
+        from: PathBuf,
+        to: PathBuf,
+        errno: Errno,
+    },
+    #[error("Could not read the {} log: {}", .name, .errno)]
+    ReadLogError { name: String, errno: Errno },
+}
+
+impl JobError {
+    /// A stable identifier of the error, `job.` followed by what failed.
+    pub fn code(&self) -> &'static str {
+        match *self {
+            Self::ArtifactMissing { .. } => "job.artifact_missing",
+            Self::CopyArtifactError { .. } => "job.copy_artifact",
+            Self::ReadLogError { .. } => "job.read_log",
+        }
+    }
+
+    /// The errno of the failed system call, if the error comes from one.
+    pub fn errno(&self) -> Option<Errno> {
+        match *self {
+            Self::CopyArtifactError { errno, .. } | Self::ReadLogError { errno, .. } => Some(errno),
+            Self::ArtifactMissing { .. } => None,
+        }
+    }
+}
+
+#[derive(Error, Debug, Serialize, Deserialize)]
+pub enum DoctorError {
+    /// Every check of the report, not only the failed ones
+    #[error("ia-sandbox can not run on this machine, {} checks failed", failed_count(.checks))]
+    ChecksFailed { checks: Vec<Check> },
+}
+
+fn failed_count(checks: &[Check]) -> usize {
+    checks
+        .iter()
+        .filter(|check| check.status() == Status::Failed)
+        .count()
+}
+
+impl DoctorError {
+    /// A stable identifier of the error, `doctor.` followed by what failed.
+    pub fn code(&self) -> &'static str {
+        match *self {
+            Self::ChecksFailed { .. } => "doctor.checks_failed",
+        }
+    }
+}
+
+#[derive(Error, Debug, Serialize, Deserialize)]
+pub enum PresetError {
+    #[error("Measuring the memory baseline failed: {}", .result)]
+    BaselineRunFailed { result: String },
+}
+
+impl PresetError {
+    /// A stable identifier of the error, `preset.` followed by what failed.
+    pub fn code(&self) -> &'static str {
+        match *self {
+            Self::BaselineRunFailed { .. } => "preset.baseline_run_failed",
+        }
+    }
+}
+
+#[derive(Error, Debug, Serialize, Deserialize)]
 pub enum ChildError {
-    #[fail(display = "Cgroup error occurred.")]
-    CGroupError(#[cause] CGroupError),
-    #[fail(display = "FFI Error occurred.")]
-    FFIError(#[cause] FFIError),
+    #[error("Cgroup error occurred.")]
+    CGroupError(#[source] CGroupError),
+    #[error("FFI Error occurred.")]
+    FFIError(#[source] FFIError),
+}
+
+impl ChildError {
+    /// The identifier of the underlying error.
+    pub fn code(&self) -> &'static str {
+        match *self {
+            Self::CGroupError(ref err) => err.code(),
+            Self::FFIError(ref err) => err.code(),
+        }
+    }
+
+    /// The errno of the underlying error.
+    pub fn errno(&self) -> Option<Errno> {
+        match *self {
+            Self::CGroupError(ref err) => err.errno(),
+            Self::FFIError(ref err) => err.errno(),
+        }
+    }
 }
 
 impl From<CGroupError> for ChildError {
@@ -148,40 +591,145 @@ impl From<FFIError> for ChildError {
     }
 }
 
-#[derive(Fail, Debug, Serialize, Deserialize)]
+#[derive(Error, Debug, Serialize, Deserialize)]
 pub enum Error {
-    #[fail(display = "Cgroup error occurred.")]
-    CGroupError(#[cause] CGroupError),
-    #[fail(display = "Child process error occurred.")]
-    ChildError(#[cause] ChildError),
-    #[fail(display = "Child process successfully completed even though it used exec")]
-    ContinuedPastExecError(String),
-    #[fail(display = "Could not deserialize process result: {}", _0)]
-    DeserializeError(String),
-    #[fail(display = "FFI Error occurred.")]
-    FFIError(#[cause] FFIError),
-    #[fail(display = "Child process stopped/continued unexpected")]
+    #[error("Cgroup error occurred.")]
+    CGroupError(#[source] CGroupError),
+    #[error("Checker error occurred.")]
+    CheckerError(#[source] CheckerError),
+    #[error("Child process error occurred.")]
+    ChildError(#[source] ChildError),
+    #[error("Child process successfully completed even though it used exec")]
+    ContinuedPastExecError { error: String },
+    #[error("Cpuset error occurred.")]
+    CpuSetError(#[source] CpuSetError),
+    #[error("Could not deserialize process result: {}", .error)]
+    DeserializeError { error: String },
+    #[error("Disk quota error occurred.")]
+    DiskQuotaError(#[source] DiskQuotaError),
+    #[error("Doctor error occurred.")]
+    DoctorError(#[source] DoctorError),
+    #[error("FFI Error occurred.")]
+    FFIError(#[source] FFIError),
+    #[error("Job error occurred.")]
+    JobError(#[source] JobError),
+    #[error("Preset error occurred.")]
+    PresetError(#[source] PresetError),
+    #[error("Rootfs error occurred.")]
+    RootfsError(#[source] RootfsError),
+    #[error("Setup error occurred.")]
+    SetupError(#[source] SetupError),
+    #[error("Child process stopped/continued unexpected")]
     StoppedContinuedError,
-    #[fail(display = "Supervisor process died and could not collect execution information")]
+    #[error("Supervisor process died and could not collect execution information")]
     SupervisorProcessDiedError,
 }
 
+impl Error {
+    /// A stable identifier of the error, meant for programs handling it (the messages can
+    /// change). The wrapping variants have the identifier of the underlying error.
+    pub fn code(&self) -> &'static str {
+        match *self {
+            Self::CGroupError(ref err) => err.code(),
+            Self::CheckerError(ref err) => err.code(),
+            Self::ChildError(ref err) => err.code(),
+            Self::ContinuedPastExecError { .. } => "supervisor.continued_past_exec",
+            Self::CpuSetError(ref err) => err.code(),
+            Self::DeserializeError { .. } => "supervisor.deserialize",
+            Self::DiskQuotaError(ref err) => err.code(),
+            Self::DoctorError(ref err) => err.code(),
+            Self::FFIError(ref err) => err.code(),
+            Self::JobError(ref err) => err.code(),
+            Self::PresetError(ref err) => err.code(),
+            Self::RootfsError(ref err) => err.code(),
+            Self::SetupError(ref err) => err.code(),
+            Self::StoppedContinuedError => "supervisor.stopped_continued",
+            Self::SupervisorProcessDiedError => "supervisor.died",
+        }
+    }
+
+    /// The errno of the failed system call behind the error, if any. Lets callers tell apart
+    /// (and work around) failures like `EPERM` when creating namespaces.
+    pub fn errno(&self) -> Option<Errno> {
+        match *self {
+            Self::CGroupError(ref err) => err.errno(),
+            Self::CheckerError(ref err) => err.errno(),
+            Self::ChildError(ref err) => err.errno(),
+            Self::CpuSetError(ref err) => err.errno(),
+            Self::DiskQuotaError(ref err) => err.errno(),
+            Self::FFIError(ref err) => err.errno(),
+            Self::JobError(ref err) => err.errno(),
+            Self::RootfsError(ref err) => err.errno(),
+            Self::SetupError(ref err) => err.errno(),
+            _ => None,
+        }
+    }
+}
+
 impl From<CGroupError> for Error {
     fn from(err: CGroupError) -> Self {
         Self::CGroupError(err)
     }
 }
 
+impl From<CheckerError> for Error {
+    fn from(err: CheckerError) -> Self {
+        Self::CheckerError(err)
+    }
+}
+
 impl From<ChildError> for Error {
     fn from(err: ChildError) -> Self {
         Self::ChildError(err)
     }
 }
 
+impl From<CpuSetError> for Error {
+    fn from(err: CpuSetError) -> Self {
+        Self::CpuSetError(err)
+    }
+}
+
+impl From<DiskQuotaError> for Error {
+    fn from(err: DiskQuotaError) -> Self {
+        Self::DiskQuotaError(err)
+    }
+}
+
 impl From<FFIError> for Error {
     fn from(err: FFIError) -> Self {
         Self::FFIError(err)
     }
 }
 
+impl From<JobError> for Error {
+    fn from(err: JobError) -> Self {
+        Self::JobError(err)
+    }
+}
+
+impl From<DoctorError> for Error {
+    fn from(err: DoctorError) -> Self {
+        Self::DoctorError(err)
+    }
+}
+
+impl From<PresetError> for Error {
+    fn from(err: PresetError) -> Self {
+        Self::PresetError(err)
+    }
+}
+
+impl From<RootfsError> for Error {
+    fn from(err: RootfsError) -> Self {
+        Self::RootfsError(err)
+    }
+}
+
+impl From<SetupError> for Error {
+    fn from(err: SetupError) -> Self {
+        Self::SetupError(err)
+    }
+}
+
 pub type Result<T> = StdResult<T, Error>;
@@ -0,0 +1,123 @@
+use ia_sandbox::errors::{ChildError, FFIError};
+use ia_sandbox::job::JobReport;
+use ia_sandbox::run_info::{RunInfo, RunInfoResult, Verdict};
+use ia_sandbox::Error;
+
+use anyhow;
+
+/// Exit codes of `--exit-codes detailed`, for the verdict of the run.
+const SUCCESS: i32 = 0;
+const NON_ZERO_EXIT_STATUS: i32 = 1;
+const KILLED_BY_SIGNAL: i32 = 2;
+const MEMORY_LIMIT_EXCEEDED: i32 = 3;
+const TIME_LIMIT_EXCEEDED: i32 = 4;
+const WALL_TIME_LIMIT_EXCEEDED: i32 = 5;
+const IDLE: i32 = 6;
+const WRONG_ANSWER: i32 = 7;
+const PRESENTATION_ERROR: i32 = 8;
+const COMPILE_ERROR: i32 = 9;
+
+/// Exit codes of `--exit-codes detailed`, for the sandbox failing. Kept away from the verdicts
+/// and from the codes shells use for commands killed by a signal (128 and above).
+const INVALID_ARGUMENTS: i32 = 100;
+const SUPERVISOR_ERROR: i32 = 101;
+const FFI_ERROR: i32 = 102;
+const EXEC_ERROR: i32 = 103;
+const CGROUP_ERROR: i32 = 104;
+const CPUSET_ERROR: i32 = 105;
+const DISK_QUOTA_ERROR: i32 = 106;
+const ROOTFS_ERROR: i32 = 107;
+const SETUP_ERROR: i32 = 108;
+const CHECKER_ERROR: i32 = 109;
+const JOB_ERROR: i32 = 110;
+const PRESET_ERROR: i32 = 111;
+const DOCTOR_ERROR: i32 = 112;
+
+/// The exit code of `ia-sandbox` when the sandbox fails with `--exit-codes simple`.
+const FAILURE: i32 = 1;
+
+/// How `ia-sandbox` chooses its exit code.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ExitCodes {
+    /// 0 whenever the jail ran, whatever the verdict, 1 if the sandbox failed
+    Simple,
+    /// A code for every verdict, and one for every part of the sandbox that can fail
+    Detailed,
+}
+
+impl ExitCodes {
+    pub(crate) fn for_run_info(self, run_info: &RunInfo<()>) -> i32 {
+        if self == Self::Simple {
+            return SUCCESS;
+        }
+        match *run_info.result() {
+            RunInfoResult::Success(()) => match run_info.check().map(|check| check.verdict()) {
+                None | Some(Verdict::Accepted) => SUCCESS,
+                Some(Verdict::WrongAnswer) => WRONG_ANSWER,
+                Some(Verdict::PresentationError) => PRESENTATION_ERROR,
+            },
+            RunInfoResult::NonZeroExitStatus(_) => NON_ZERO_EXIT_STATUS,
+            RunInfoResult::KilledBySignal(_) => KILLED_BY_SIGNAL,
+            RunInfoResult::MemoryLimitExceeded(_) => MEMORY_LIMIT_EXCEEDED,
+            RunInfoResult::TimeLimitExceeded(_) => TIME_LIMIT_EXCEEDED,
+            RunInfoResult::WallTimeLimitExceeded(_) => WALL_TIME_LIMIT_EXCEEDED,
+            RunInfoResult::Idle(_) => IDLE,
+        }
+    }
+
+    /// The code of the first case that was not successful, or could not run.
+    pub(crate) fn for_batch(self, results: &[Result<RunInfo<()>, Error>]) -> i32 {
+        results
+            .iter()
+            .map(|result| match *result {
+                Ok(ref run_info) => self.for_run_info(run_info),
+                Err(ref err) => self.for_sandbox_error(err),
+            })
+            .find(|&code| code != SUCCESS)
+            .unwrap_or(SUCCESS)
+    }
+
+    /// Any verdict of the compile stage other than success is a compile error.
+    pub(crate) fn for_job(self, report: &JobReport) -> i32 {
+        match report.run() {
+            Some(run) => self.for_run_info(run.run_info()),
+            None if self == Self::Simple => SUCCESS,
+            None => COMPILE_ERROR,
+        }
+    }
+
+    pub(crate) fn for_error(self, err: &anyhow::Error) -> i32 {
+        if self == Self::Simple {
+            return FAILURE;
+        }
+        // Anything not coming from the sandbox is a problem with the arguments
+        match err.chain().find_map(|cause| cause.downcast_ref::<Error>()) {
+            Some(err) => self.for_sandbox_error(err),
+            None => INVALID_ARGUMENTS,
+        }
+    }
+
+    fn for_sandbox_error(self, err: &Error) -> i32 {
+        if self == Self::Simple {
+            return FAILURE;
+        }
+        match *err {
+            Error::FFIError(FFIError::ExecError { .. })
+            | Error::ChildError(ChildError::FFIError(FFIError::ExecError { .. })) => EXEC_ERROR,
+            Error::FFIError(_) | Error::ChildError(ChildError::FFIError(_)) => FFI_ERROR,
+            Error::CGroupError(_) | Error::ChildError(ChildError::CGroupError(_)) => CGROUP_ERROR,
+            Error::CpuSetError(_) => CPUSET_ERROR,
+            Error::DiskQuotaError(_) => DISK_QUOTA_ERROR,
+            Error::RootfsError(_) => ROOTFS_ERROR,
+            Error::SetupError(_) => SETUP_ERROR,
+            Error::CheckerError(_) => CHECKER_ERROR,
+            Error::JobError(_) => JOB_ERROR,
+            Error::PresetError(_) => PRESET_ERROR,
+            Error::DoctorError(_) => DOCTOR_ERROR,
+            Error::ContinuedPastExecError { .. }
+            | Error::DeserializeError { .. }
+            | Error::StoppedContinuedError
+            | Error::SupervisorProcessDiedError => SUPERVISOR_ERROR,
+        }
+    }
+}
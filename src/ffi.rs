@@ -1,13 +1,13 @@
-use std::error::Error as ErrorExt;
+use std::cmp;
 use std::ffi::{CString, OsStr};
 use std::fmt::Debug;
 use std::fs::{self, File, OpenOptions};
-use std::io::{Read, Write};
+use std::io::{self, Read, Write};
 use std::iter;
 use std::marker::PhantomData;
-use std::mem::{self, MaybeUninit};
+use std::mem::{self, size_of, MaybeUninit};
 use std::os::unix::ffi::OsStrExt;
-use std::os::unix::io::FromRawFd;
+use std::os::unix::io::{AsRawFd, FromRawFd};
 use std::path::{Path, PathBuf};
 use std::ptr;
 use std::result::Result as StdResult;
@@ -21,9 +21,9 @@ use libc::{
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 
-use config::{Environment, Limits, Mount, ShareNet, SpaceUsage};
-use errors::{Error, FFIError};
-use run_info::{RunInfo, RunInfoResult, RunUsage};
+use config::{Environment, LandlockRules, Limits, Mount, ShareNet, SpaceUsage};
+use errors::{Errno, Error, FFIError};
+use run_info::{CpuThrottling, ExitStatus, RunInfo, RunInfoResult, RunUsage, Termination};
 
 type Result<T> = StdResult<T, FFIError>;
 
@@ -52,8 +52,110 @@ pub(crate) fn getpid() -> libc::c_int {
     unsafe { libc::getpid() }
 }
 
+pub(crate) fn process_exists(pid: libc::pid_t) -> bool {
+    match unsafe { libc::kill(pid, 0) } {
+        -1 => {
+            // EPERM means it exists, but belongs to someone else
+            Errno::last().code() == libc::EPERM
+        }
+        _ => true,
+    }
+}
+
+/// Sends `signal` to `pid`, which might be gone already.
+pub(crate) fn kill(pid: libc::pid_t, signal: libc::c_int) {
+    let _ = unsafe { libc::kill(pid, signal) };
+}
+
+/// The cpus the current process may run on.
+pub(crate) fn get_cpu_affinity() -> Result<Vec<usize>> {
+    let mut cpu_set: libc::cpu_set_t = unsafe { mem::zeroed() };
+    match unsafe { libc::sched_getaffinity(0, size_of::<libc::cpu_set_t>(), &mut cpu_set) } {
+        -1 => Err(FFIError::SchedGetAffinityError {
+            errno: Errno::last(),
+        }),
+        _ => Ok((0..libc::CPU_SETSIZE as usize)
+            .filter(|&cpu| unsafe { libc::CPU_ISSET(cpu, &cpu_set) })
+            .collect()),
+    }
+}
+
+pub(crate) fn set_cpu_affinity(cpus: &[usize]) -> Result<()> {
+    let mut cpu_set: libc::cpu_set_t = unsafe { mem::zeroed() };
+    for &cpu in cpus {
+        // CPU_SET panics on cpus outside the mask
+        if cpu >= libc::CPU_SETSIZE as usize {
+            return Err(FFIError::SchedSetAffinityError {
+                errno: Errno::new(libc::EINVAL),
+            });
+        }
+        unsafe { libc::CPU_SET(cpu, &mut cpu_set) };
+    }
+    match unsafe { libc::sched_setaffinity(0, size_of::<libc::cpu_set_t>(), &cpu_set) } {
+        -1 => Err(FFIError::SchedSetAffinityError {
+            errno: Errno::last(),
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Takes an exclusive lock on the file without blocking, false if someone else holds it.
+pub(crate) fn try_lock_exclusive(file: &File, path: &Path) -> Result<bool> {
+    match unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } {
+        -1 => {
+            let error = Errno::last();
+            if error.code() == libc::EWOULDBLOCK {
+                Ok(false)
+            } else {
+                Err(FFIError::FlockError {
+                    path: path.to_path_buf(),
+                    errno: error,
+                })
+            }
+        }
+        _ => Ok(true),
+    }
+}
+
+pub(crate) fn get_user_ids(name: &str) -> Option<(libc::uid_t, libc::gid_t)> {
+    let name = os_str_to_c_string(name);
+    let mut passwd = MaybeUninit::<libc::passwd>::uninit();
+    let mut result = ptr::null_mut();
+    let buffer: &mut [libc::c_char] = &mut [0; 16 * 1024];
+
+    let res = unsafe {
+        libc::getpwnam_r(
+            name.as_ptr(),
+            passwd.as_mut_ptr(),
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+
+    if res != 0 || result.is_null() {
+        None
+    } else {
+        let passwd = unsafe { passwd.assume_init() };
+        Some((passwd.pw_uid, passwd.pw_gid))
+    }
+}
+
+pub(crate) fn chown(path: &Path, uid: libc::uid_t, gid: libc::gid_t) -> Result<()> {
+    let c_path = os_str_to_c_string(path);
+    match unsafe { libc::chown(c_path.as_ptr(), uid, gid) } {
+        -1 => Err(FFIError::ChownError {
+            path: path.to_path_buf(),
+            errno: Errno::last(),
+        }),
+        _ => Ok(()),
+    }
+}
+
 pub(crate) fn set_uid_gid_maps((uid, gid): (UserId, GroupId)) -> Result<()> {
-    let uid_error = |_| FFIError::WriteUidError(last_error_string());
+    let uid_error = |err: io::Error| FFIError::WriteUidError {
+        errno: Errno::from(&err),
+    };
     let mut uid_map = OpenOptions::new()
         .write(true)
         .open("/proc/self/uid_map")
@@ -67,12 +169,18 @@ pub(crate) fn set_uid_gid_maps((uid, gid): (UserId, GroupId)) -> Result<()> {
     let mut setgroups = OpenOptions::new()
         .write(true)
         .open("/proc/self/setgroups")
-        .map_err(|_| FFIError::WriteSetGroupsError(last_error_string()))?;
+        .map_err(|err| FFIError::WriteSetGroupsError {
+            errno: Errno::from(&err),
+        })?;
     setgroups
         .write_all(b"deny")
-        .map_err(|_| FFIError::WriteSetGroupsError(last_error_string()))?;
+        .map_err(|err| FFIError::WriteSetGroupsError {
+            errno: Errno::from(&err),
+        })?;
 
-    let gid_error = |_| FFIError::WriteGidError(last_error_string());
+    let gid_error = |err: io::Error| FFIError::WriteGidError {
+        errno: Errno::from(&err),
+    };
     let mut gid_map = OpenOptions::new()
         .write(true)
         .open("/proc/self/gid_map")
@@ -103,7 +211,7 @@ pub(crate) fn set_sig_alarm_handler() -> Result<()> {
     if unsafe { libc::sigaction(libc::SIGALRM, &sigaction, ptr::null_mut()) } == -1 {
         Err(FFIError::SigActionError {
             signal: "SIGALRM".into(),
-            error: last_error_string(),
+            errno: Errno::last(),
         })
     } else {
         Ok(())
@@ -130,7 +238,9 @@ pub(crate) fn set_alarm_interval(interval: i64) -> Result<()> {
         )
     } == -1
     {
-        Err(FFIError::SetITimerError(last_error_string()))
+        Err(FFIError::SetITimerError {
+            errno: Errno::last(),
+        })
     } else {
         Ok(())
     }
@@ -193,7 +303,11 @@ where
             context.as_mut() as *mut _ as *mut _,
         )
     } {
-        -1 => return Err(FFIError::CloneError(last_error_string())),
+        -1 => {
+            return Err(FFIError::CloneError {
+                errno: Errno::last(),
+            })
+        }
         x => x,
     };
 
@@ -208,7 +322,9 @@ where
 
 pub(crate) fn unshare_cgroup() -> Result<()> {
     match unsafe { libc::unshare(CLONE_NEWCGROUP) } {
-        -1 => Err(FFIError::UnshareCGroupError(last_error_string())),
+        -1 => Err(FFIError::UnshareCGroupError {
+            errno: Errno::last(),
+        }),
         _ => Ok(()),
     }
 }
@@ -228,7 +344,7 @@ pub(crate) fn remount_private() -> Result<()> {
     if res == -1 {
         Err(FFIError::MountError {
             path: PathBuf::from("/"),
-            error: last_error_string(),
+            errno: Errno::last(),
         })
     } else {
         Ok(())
@@ -248,14 +364,14 @@ pub(crate) fn mount_inside(new_root: &Path, mount: &Mount) -> Result<()> {
         fs::create_dir_all(&inner_path)
             .map_err(|error| FFIError::CreateDirError {
                 path: inner_path.to_path_buf(),
-                error: error.description().into(),
+                errno: Errno::from(&error),
             })
             .unwrap_or(());
     } else {
         inner_path.parent().map_or(Ok(()), |pardir| {
             fs::create_dir_all(pardir).map_err(|error| FFIError::CreateDirError {
                 path: inner_path.to_path_buf(),
-                error: error.description().into(),
+                errno: Errno::from(&error),
             })
         })?;
         let _ = OpenOptions::new()
@@ -264,7 +380,7 @@ pub(crate) fn mount_inside(new_root: &Path, mount: &Mount) -> Result<()> {
             .open(&inner_path)
             .map_err(|error| FFIError::CreateDirError {
                 path: inner_path.to_path_buf(),
-                error: error.description().into(),
+                errno: Errno::from(&error),
             })?;
     }
 
@@ -300,7 +416,7 @@ pub(crate) fn mount_inside(new_root: &Path, mount: &Mount) -> Result<()> {
     if res == -1 {
         return Err(FFIError::MountError {
             path: mount.destination().to_path_buf(),
-            error: last_error_string(),
+            errno: Errno::last(),
         });
     }
 
@@ -317,7 +433,7 @@ pub(crate) fn mount_inside(new_root: &Path, mount: &Mount) -> Result<()> {
     if res == -1 {
         return Err(FFIError::MountError {
             path: mount.destination().to_path_buf(),
-            error: last_error_string(),
+            errno: Errno::last(),
         });
     }
 
@@ -335,7 +451,7 @@ where
     if !old_root.exists() {
         fs::create_dir(&old_root).map_err(|error| FFIError::CreateDirError {
             path: old_root.to_path_buf(),
-            error: error.description().into(),
+            errno: Errno::from(&error),
         })?;
     }
 
@@ -355,7 +471,7 @@ where
     if res == -1 {
         return Err(FFIError::MountError {
             path: new_root.to_path_buf(),
-            error: last_error_string(),
+            errno: Errno::last(),
         });
     }
 
@@ -363,7 +479,7 @@ where
     if unsafe { libc::chdir(new_root_c_string.as_ptr()) } == -1 {
         return Err(FFIError::ChdirError {
             path: new_root.to_path_buf(),
-            error: last_error_string(),
+            errno: Errno::last(),
         });
     }
 
@@ -375,7 +491,7 @@ where
     if unsafe { libc::chroot(root.as_ptr()) } == -1 {
         return Err(FFIError::ChrootError {
             path: ".".into(),
-            error: last_error_string(),
+            errno: Errno::last(),
         });
     }
 
@@ -387,7 +503,7 @@ where
     if unsafe { libc::umount2(old_root_c_string.as_ptr(), libc::MNT_DETACH) } == -1 {
         Err(FFIError::UMountError {
             path: old_root,
-            error: last_error_string(),
+            errno: Errno::last(),
         })
     } else {
         Ok(())
@@ -402,7 +518,7 @@ pub(crate) fn mount_proc() -> Result<()> {
     if !path.exists() {
         fs::create_dir(&path).map_err(|err| FFIError::CreateDirError {
             path: path.clone(),
-            error: err.description().into(),
+            errno: Errno::from(&err),
         })?;
     }
     let path_as_c_string = os_str_to_c_string(&path);
@@ -420,7 +536,243 @@ pub(crate) fn mount_proc() -> Result<()> {
     if res == -1 {
         Err(FFIError::MountError {
             path,
-            error: last_error_string(),
+            errno: Errno::last(),
+        })
+    } else {
+        Ok(())
+    }
+}
+
+/// The unit of the cpu times in `/proc`.
+pub(crate) fn clock_ticks_per_second() -> u64 {
+    match unsafe { libc::sysconf(libc::_SC_CLK_TCK) } {
+        ticks if ticks > 0 => ticks as u64,
+        _ => 100,
+    }
+}
+
+/// Mounts a tmpfs at `path` that can hold at most `size` bytes.
+pub(crate) fn mount_tmpfs(path: &Path, size: SpaceUsage) -> Result<()> {
+    let name = CString::new("tmpfs").unwrap();
+    let path_as_c_string = os_str_to_c_string(path);
+    let data = CString::new(format!("size={}", size.as_bytes())).unwrap();
+
+    let res = unsafe {
+        libc::mount(
+            name.as_ptr(),
+            path_as_c_string.as_ptr(),
+            name.as_ptr(),
+            libc::MS_NOSUID | libc::MS_NODEV,
+            data.as_ptr() as *const _,
+        )
+    };
+
+    if res == -1 {
+        Err(FFIError::MountError {
+            path: path.to_path_buf(),
+            errno: Errno::last(),
+        })
+    } else {
+        Ok(())
+    }
+}
+
+pub(crate) fn umount(path: &Path) -> Result<()> {
+    let path_as_c_string = os_str_to_c_string(path);
+    if unsafe { libc::umount2(path_as_c_string.as_ptr(), libc::MNT_DETACH) } == -1 {
+        Err(FFIError::UMountError {
+            path: path.to_path_buf(),
+            errno: Errno::last(),
+        })
+    } else {
+        Ok(())
+    }
+}
+
+/// The space taken on the filesystem mounted at `path`.
+pub(crate) fn used_space(path: &Path) -> Result<SpaceUsage> {
+    let path_as_c_string = os_str_to_c_string(path);
+    let mut stat = MaybeUninit::<libc::statvfs>::uninit();
+    if unsafe { libc::statvfs(path_as_c_string.as_ptr(), stat.as_mut_ptr()) } == -1 {
+        return Err(FFIError::StatvfsError {
+            path: path.to_path_buf(),
+            errno: Errno::last(),
+        });
+    }
+
+    let stat = unsafe { stat.assume_init() };
+    Ok(SpaceUsage::from_bytes(
+        (stat.f_blocks - stat.f_bfree) * stat.f_frsize,
+    ))
+}
+
+const LANDLOCK_CREATE_RULESET_VERSION: libc::c_uint = 1;
+const LANDLOCK_RULE_PATH_BENEATH: libc::c_int = 1;
+
+const LANDLOCK_ACCESS_FS_EXECUTE: u64 = 1 << 0;
+const LANDLOCK_ACCESS_FS_WRITE_FILE: u64 = 1 << 1;
+const LANDLOCK_ACCESS_FS_READ_FILE: u64 = 1 << 2;
+const LANDLOCK_ACCESS_FS_READ_DIR: u64 = 1 << 3;
+const LANDLOCK_ACCESS_FS_REMOVE_DIR: u64 = 1 << 4;
+const LANDLOCK_ACCESS_FS_REMOVE_FILE: u64 = 1 << 5;
+const LANDLOCK_ACCESS_FS_MAKE_CHAR: u64 = 1 << 6;
+const LANDLOCK_ACCESS_FS_MAKE_DIR: u64 = 1 << 7;
+const LANDLOCK_ACCESS_FS_MAKE_REG: u64 = 1 << 8;
+const LANDLOCK_ACCESS_FS_MAKE_SOCK: u64 = 1 << 9;
+const LANDLOCK_ACCESS_FS_MAKE_FIFO: u64 = 1 << 10;
+const LANDLOCK_ACCESS_FS_MAKE_BLOCK: u64 = 1 << 11;
+const LANDLOCK_ACCESS_FS_MAKE_SYM: u64 = 1 << 12;
+// Only available starting with the third version of the Landlock ABI
+const LANDLOCK_ACCESS_FS_TRUNCATE: u64 = 1 << 14;
+
+const LANDLOCK_ACCESS_FS_V1: u64 = LANDLOCK_ACCESS_FS_EXECUTE
+    | LANDLOCK_ACCESS_FS_WRITE_FILE
+    | LANDLOCK_ACCESS_FS_READ_FILE
+    | LANDLOCK_ACCESS_FS_READ_DIR
+    | LANDLOCK_ACCESS_FS_REMOVE_DIR
+    | LANDLOCK_ACCESS_FS_REMOVE_FILE
+    | LANDLOCK_ACCESS_FS_MAKE_CHAR
+    | LANDLOCK_ACCESS_FS_MAKE_DIR
+    | LANDLOCK_ACCESS_FS_MAKE_REG
+    | LANDLOCK_ACCESS_FS_MAKE_SOCK
+    | LANDLOCK_ACCESS_FS_MAKE_FIFO
+    | LANDLOCK_ACCESS_FS_MAKE_BLOCK
+    | LANDLOCK_ACCESS_FS_MAKE_SYM;
+// Rights that make sense on a file (as opposed to a directory), others are rejected by the kernel
+const LANDLOCK_ACCESS_FS_FILE: u64 = LANDLOCK_ACCESS_FS_EXECUTE
+    | LANDLOCK_ACCESS_FS_WRITE_FILE
+    | LANDLOCK_ACCESS_FS_READ_FILE
+    | LANDLOCK_ACCESS_FS_TRUNCATE;
+
+const LANDLOCK_READ_ACCESS: u64 = LANDLOCK_ACCESS_FS_READ_FILE | LANDLOCK_ACCESS_FS_READ_DIR;
+// Creating devices is never granted, not even on writable paths
+const LANDLOCK_WRITE_ACCESS: u64 = LANDLOCK_ACCESS_FS_WRITE_FILE
+    | LANDLOCK_ACCESS_FS_REMOVE_DIR
+    | LANDLOCK_ACCESS_FS_REMOVE_FILE
+    | LANDLOCK_ACCESS_FS_MAKE_DIR
+    | LANDLOCK_ACCESS_FS_MAKE_REG
+    | LANDLOCK_ACCESS_FS_MAKE_SOCK
+    | LANDLOCK_ACCESS_FS_MAKE_FIFO
+    | LANDLOCK_ACCESS_FS_MAKE_SYM
+    | LANDLOCK_ACCESS_FS_TRUNCATE;
+const LANDLOCK_EXEC_ACCESS: u64 = LANDLOCK_ACCESS_FS_EXECUTE | LANDLOCK_READ_ACCESS;
+
+#[repr(C)]
+struct LandlockRulesetAttr {
+    handled_access_fs: u64,
+}
+
+#[repr(C, packed)]
+struct LandlockPathBeneathAttr {
+    allowed_access: u64,
+    parent_fd: i32,
+}
+
+fn landlock_add_rule(ruleset: &File, path: &Path, access: u64, handled: u64) -> Result<()> {
+    let path_as_c_string = os_str_to_c_string(path);
+    let fd = match unsafe { libc::open(path_as_c_string.as_ptr(), libc::O_PATH | libc::O_CLOEXEC) }
+    {
+        -1 => {
+            return Err(FFIError::LandlockAddRuleError {
+                path: path.to_path_buf(),
+                errno: Errno::last(),
+            })
+        }
+        fd => unsafe { File::from_raw_fd(fd) },
+    };
+
+    let mut allowed_access = access & handled;
+    if !path.is_dir() {
+        allowed_access &= LANDLOCK_ACCESS_FS_FILE;
+    }
+
+    let path_beneath = LandlockPathBeneathAttr {
+        allowed_access,
+        parent_fd: fd.as_raw_fd(),
+    };
+
+    if unsafe {
+        libc::syscall(
+            libc::SYS_landlock_add_rule,
+            ruleset.as_raw_fd(),
+            LANDLOCK_RULE_PATH_BENEATH,
+            &path_beneath,
+            0,
+        )
+    } == -1
+    {
+        Err(FFIError::LandlockAddRuleError {
+            path: path.to_path_buf(),
+            errno: Errno::last(),
+        })
+    } else {
+        Ok(())
+    }
+}
+
+pub(crate) fn landlock_abi() -> Result<i64> {
+    match unsafe {
+        libc::syscall(
+            libc::SYS_landlock_create_ruleset,
+            ptr::null::<LandlockRulesetAttr>(),
+            0,
+            LANDLOCK_CREATE_RULESET_VERSION,
+        )
+    } {
+        -1 => Err(FFIError::LandlockCreateRulesetError {
+            errno: Errno::last(),
+        }),
+        abi => Ok(abi),
+    }
+}
+
+pub(crate) fn landlock_restrict(rules: &LandlockRules) -> Result<()> {
+    let abi = landlock_abi()?;
+
+    let mut handled = LANDLOCK_ACCESS_FS_V1;
+    if abi >= 3 {
+        handled |= LANDLOCK_ACCESS_FS_TRUNCATE;
+    }
+
+    let ruleset_attr = LandlockRulesetAttr {
+        handled_access_fs: handled,
+    };
+    let ruleset = match unsafe {
+        libc::syscall(
+            libc::SYS_landlock_create_ruleset,
+            &ruleset_attr,
+            size_of::<LandlockRulesetAttr>(),
+            0,
+        )
+    } {
+        -1 => {
+            return Err(FFIError::LandlockCreateRulesetError {
+                errno: Errno::last(),
+            })
+        }
+        fd => unsafe { File::from_raw_fd(fd as libc::c_int) },
+    };
+
+    for path in rules.read() {
+        landlock_add_rule(&ruleset, path, LANDLOCK_READ_ACCESS, handled)?;
+    }
+    for path in rules.write() {
+        landlock_add_rule(&ruleset, path, LANDLOCK_WRITE_ACCESS, handled)?;
+    }
+    for path in rules.exec() {
+        landlock_add_rule(&ruleset, path, LANDLOCK_EXEC_ACCESS, handled)?;
+    }
+
+    // Required for restricting ourselves without CAP_SYS_ADMIN in the parent user namespace
+    if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } == -1 {
+        return Err(FFIError::PrSetNoNewPrivsError {
+            errno: Errno::last(),
+        });
+    }
+
+    if unsafe { libc::syscall(libc::SYS_landlock_restrict_self, ruleset.as_raw_fd(), 0) } == -1 {
+        Err(FFIError::LandlockRestrictSelfError {
+            errno: Errno::last(),
         })
     } else {
         Ok(())
@@ -474,19 +826,22 @@ pub(crate) fn exec_command(
         };
 
         if res == -1 {
-            let error = errno::Errno::last_error();
-            if error.error_code() != libc::ETXTBSY || retry == EXEC_RETRIES - 1 {
+            let error = Errno::last();
+            if error.code() != libc::ETXTBSY || retry == EXEC_RETRIES - 1 {
                 return Err(FFIError::ExecError {
                     command: command.to_path_buf(),
-                    arguments: arguments.iter().map(|&os_str| os_str.to_owned()).collect(),
-                    error: error.error_string(),
+                    arguments: arguments
+                        .iter()
+                        .map(|os_str| os_str.to_string_lossy().into_owned())
+                        .collect(),
+                    errno: error,
                 });
             }
             let res = unsafe { libc::usleep(RETRY_DELAY) };
             if res == -1 {
                 return Err(FFIError::UsleepError {
                     time: RETRY_DELAY,
-                    error: last_error_string(),
+                    errno: Errno::last(),
                 });
             }
         } else {
@@ -518,14 +873,14 @@ pub(crate) fn redirect_fd(fd: &Fd, path: &Path) -> Result<()> {
         -1 => Err(FFIError::OpenFdError {
             fd: fd.0,
             name: fd.1.into(),
-            error: last_error_string(),
+            errno: Errno::last(),
         }),
         x => {
             if x != fd.0 && unsafe { libc::dup2(x, fd.0) } == -1 {
                 Err(FFIError::DupFdError {
                     fd: fd.0,
                     name: fd.1.into(),
-                    error: last_error_string(),
+                    errno: Errno::last(),
                 })
             } else {
                 Ok(())
@@ -534,12 +889,143 @@ pub(crate) fn redirect_fd(fd: &Fd, path: &Path) -> Result<()> {
     }
 }
 
+/// Makes `fd` refer to the same file as `file`.
+pub(crate) fn redirect_fd_to_file(fd: &Fd, file: &File) -> Result<()> {
+    if unsafe { libc::dup2(file.as_raw_fd(), fd.0) } == -1 {
+        Err(FFIError::DupFdError {
+            fd: fd.0,
+            name: fd.1.into(),
+            errno: Errno::last(),
+        })
+    } else {
+        Ok(())
+    }
+}
+
+/// The most file descriptors sent along with a message.
+const MAX_MESSAGE_FDS: usize = 8;
+/// The largest message that can be received.
+const MAX_MESSAGE_SIZE: usize = 1 << 16;
+
+/// Connected sockets keeping message boundaries, the other end closing shows up as an empty
+/// message.
+pub(crate) fn socket_pair() -> Result<(File, File)> {
+    let mut fds = [0; 2];
+    if unsafe {
+        libc::socketpair(
+            libc::AF_UNIX,
+            libc::SOCK_SEQPACKET | libc::SOCK_CLOEXEC,
+            0,
+            fds.as_mut_ptr(),
+        )
+    } == -1
+    {
+        Err(FFIError::SocketPairError {
+            errno: Errno::last(),
+        })
+    } else {
+        Ok(unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) })
+    }
+}
+
+/// Sends `data` in a single message, passing along `files` (as `SCM_RIGHTS`).
+pub(crate) fn send_with_files(socket: &File, data: &[u8], files: &[&File]) -> Result<()> {
+    assert!(files.len() <= MAX_MESSAGE_FDS);
+    let mut iov = libc::iovec {
+        iov_base: data.as_ptr() as *mut libc::c_void,
+        iov_len: data.len(),
+    };
+    let fds: Vec<libc::c_int> = files.iter().map(|file| file.as_raw_fd()).collect();
+    let fds_size = (fds.len() * size_of::<libc::c_int>()) as libc::c_uint;
+    let mut control = vec![0u8; unsafe { libc::CMSG_SPACE(fds_size) } as usize];
+
+    let mut message: libc::msghdr = unsafe { mem::zeroed() };
+    message.msg_iov = &mut iov;
+    message.msg_iovlen = 1;
+    if !fds.is_empty() {
+        message.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        message.msg_controllen = control.len();
+        unsafe {
+            let header = libc::CMSG_FIRSTHDR(&message);
+            (*header).cmsg_level = libc::SOL_SOCKET;
+            (*header).cmsg_type = libc::SCM_RIGHTS;
+            (*header).cmsg_len = libc::CMSG_LEN(fds_size) as _;
+            ptr::copy_nonoverlapping(
+                fds.as_ptr(),
+                libc::CMSG_DATA(header) as *mut libc::c_int,
+                fds.len(),
+            );
+        }
+    }
+
+    loop {
+        if unsafe { libc::sendmsg(socket.as_raw_fd(), &message, libc::MSG_NOSIGNAL) } != -1 {
+            return Ok(());
+        }
+        let error = Errno::last();
+        if error.code() != libc::EINTR {
+            return Err(FFIError::SendMsgError { errno: error });
+        }
+    }
+}
+
+/// Waits for a message sent with [`send_with_files`].
+pub(crate) fn receive_with_files(socket: &File) -> Result<(Vec<u8>, Vec<File>)> {
+    let mut data = vec![0u8; MAX_MESSAGE_SIZE];
+    let mut iov = libc::iovec {
+        iov_base: data.as_mut_ptr() as *mut libc::c_void,
+        iov_len: data.len(),
+    };
+    let fds_size = (MAX_MESSAGE_FDS * size_of::<libc::c_int>()) as libc::c_uint;
+    let mut control = vec![0u8; unsafe { libc::CMSG_SPACE(fds_size) } as usize];
+
+    let mut message: libc::msghdr = unsafe { mem::zeroed() };
+    message.msg_iov = &mut iov;
+    message.msg_iovlen = 1;
+    message.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    message.msg_controllen = control.len();
+
+    let size = loop {
+        match unsafe { libc::recvmsg(socket.as_raw_fd(), &mut message, libc::MSG_CMSG_CLOEXEC) } {
+            -1 => {
+                let error = Errno::last();
+                if error.code() != libc::EINTR {
+                    return Err(FFIError::RecvMsgError { errno: error });
+                }
+            }
+            size => break size as usize,
+        }
+    };
+    if message.msg_flags & (libc::MSG_TRUNC | libc::MSG_CTRUNC) != 0 {
+        return Err(FFIError::MessageError {
+            error: "message too large".into(),
+        });
+    }
+    data.truncate(size);
+
+    let mut files = Vec::new();
+    unsafe {
+        let mut header = libc::CMSG_FIRSTHDR(&message);
+        while !header.is_null() {
+            if (*header).cmsg_level == libc::SOL_SOCKET && (*header).cmsg_type == libc::SCM_RIGHTS {
+                let data_size = (*header).cmsg_len - libc::CMSG_LEN(0) as usize;
+                let fds = libc::CMSG_DATA(header) as *const libc::c_int;
+                for index in 0..data_size / size_of::<libc::c_int>() {
+                    files.push(File::from_raw_fd(ptr::read_unaligned(fds.add(index))));
+                }
+            }
+            header = libc::CMSG_NXTHDR(&message, header);
+        }
+    }
+    Ok((data, files))
+}
+
 pub(crate) fn move_to_different_process_group() -> Result<()> {
     if unsafe { libc::setpgid(0, 0) } == -1 {
         Err(FFIError::SetpgidError {
             pid: 0,
             pgid: 0,
-            error: last_error_string(),
+            errno: Errno::last(),
         })
     } else {
         Ok(())
@@ -556,34 +1042,20 @@ fn sys_pivot_root(new_root: &Path, old_root: &Path) -> Result<()> {
             old_root_c_string.as_ptr(),
         )
     } {
-        -1 => {
-            let errno = errno::Errno::last_error();
-            let error = match errno.error_code() {
-                libc::EBUSY => "new_root or put_old are on the current root\
-                                filesystem, or a filesystem is already\
-                                mounted on put_old."
-                    .into(),
-                libc::EINVAL => "put_old is not underneath new_root".into(),
-                libc::ENOTDIR => "new_root or put_old is not a directory".into(),
-                libc::EPERM => "The calling process does not have the\
-                                CAP_SYS_ADMIN capability"
-                    .into(),
-                _ => errno.error_string(),
-            };
-
-            Err(FFIError::PivotRootError {
-                new_root: new_root.to_path_buf(),
-                old_root: old_root.to_path_buf(),
-                error,
-            })
-        }
+        -1 => Err(FFIError::PivotRootError {
+            new_root: new_root.to_path_buf(),
+            old_root: old_root.to_path_buf(),
+            errno: Errno::last(),
+        }),
         _ => Ok(()),
     }
 }
 
 pub(crate) fn kill_on_parent_death() -> Result<()> {
     if unsafe { libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL) } == -1 {
-        Err(FFIError::PrSetPDeathSigError(last_error_string()))
+        Err(FFIError::PrSetPDeathSigError {
+            errno: Errno::last(),
+        })
     } else {
         Ok(())
     }
@@ -603,51 +1075,27 @@ pub(crate) fn set_stack_limit(stack: Option<SpaceUsage>) -> Result<()> {
     };
 
     if unsafe { libc::setrlimit(libc::RLIMIT_STACK, &rlimit) } == -1 {
-        Err(FFIError::SetRLimitError(last_error_string()))
+        Err(FFIError::SetRLimitError {
+            errno: Errno::last(),
+        })
     } else {
         Ok(())
     }
 }
 
-mod errno {
-    use libc;
-    use std::ffi::CStr;
-
-    #[derive(Debug)]
-    pub(crate) struct Errno(libc::c_int);
-
-    impl Errno {
-        pub(crate) fn last_error() -> Self {
-            unsafe { Self(*libc::__errno_location()) }
-        }
-
-        pub(crate) fn error_code(&self) -> libc::c_int {
-            self.0
-        }
-
-        pub(crate) fn error_string(&self) -> String {
-            let buffer = &mut [0i8; 256];
-            if unsafe { libc::strerror_r(self.0, buffer.as_mut_ptr(), buffer.len()) } == -1 {
-                return "unexpected strerror_r error".into();
-            }
-            unsafe {
-                CStr::from_ptr(buffer.as_ptr())
-                    .to_string_lossy()
-                    .into_owned()
-            }
-        }
-    }
+/// The size of a memory page, in bytes.
+pub(crate) fn page_size() -> u64 {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as u64 }
 }
 
-fn last_error_string() -> String {
-    errno::Errno::last_error().error_string()
-}
-
-fn make_pipe() -> Result<(File, File)> {
+/// A pipe, as its (read, write) ends. Both are closed on exec.
+pub(crate) fn make_pipe() -> Result<(File, File)> {
     unsafe {
         let fd = &mut [0; 2];
         if libc::pipe2(fd.as_mut_ptr(), libc::O_CLOEXEC) == -1 {
-            Err(FFIError::Pipe2Error(last_error_string()))
+            Err(FFIError::Pipe2Error {
+                errno: Errno::last(),
+            })
         } else {
             Ok((File::from_raw_fd(fd[0]), File::from_raw_fd(fd[1])))
         }
@@ -669,64 +1117,140 @@ impl<T: DeserializeOwned> CloneHandle<T> {
     pub(crate) fn wait<F: Fn(Duration) -> StdResult<RunUsage, Error>>(
         mut self,
         limits: Limits,
-        usage: F,
+        get_usage: F,
     ) -> StdResult<RunInfo<Option<T>>, Error> {
         let timer = Instant::now();
         let mut data = Vec::new();
-        let _ = self
-            .read_error_pipe
-            .read_to_end(&mut data)
-            .map_err(|err| Error::DeserializeError(err.description().into()))?;
+        let _ =
+            self.read_error_pipe
+                .read_to_end(&mut data)
+                .map_err(|err| Error::DeserializeError {
+                    error: err.to_string(),
+                })?;
         let result = if !data.is_empty() {
             Some(
-                bincode::deserialize(&data)
-                    .map_err(|err| Error::DeserializeError(err.description().into()))?,
+                bincode::deserialize(&data).map_err(|err| Error::DeserializeError {
+                    error: err.to_string(),
+                })?,
             )
         } else {
             None
         };
 
+        let mut idle = IdleTracker::new();
+        let mut soft_limit_signaled = false;
         loop {
-            let wall_time = timer.elapsed();
-            let usage = usage(wall_time)?;
+            let usage = idle.track(get_usage(timer.elapsed())?);
 
-            if let Some(run_info) = usage.check_limits(limits) {
-                return Ok(run_info);
+            if usage.exceeds_limits(limits) {
+                let termination = self.kill_and_wait(soft_limit_signaled)?;
+                if let Some(run_info) = usage.check_limits(limits, termination) {
+                    return Ok(run_info);
+                }
+            }
+
+            if !soft_limit_signaled && usage.exceeds_soft_limits(limits.soft()) {
+                // The grace period starts, the hard limits still apply
+                let _ = unsafe { libc::kill(self.pid, limits.soft().signal()) };
+                soft_limit_signaled = true;
             }
 
             // Check if something killed us
             let mut status: libc::c_int = 0;
             if unsafe { libc::waitpid(self.pid, &mut status, 0) } == -1 {
-                let error = errno::Errno::last_error();
-                if error.error_code() == libc::EINTR {
+                let error = Errno::last();
+                if error.code() == libc::EINTR {
                     continue; // interrupted by some signal
                 }
-                return Err(Error::FFIError(FFIError::WaitPidError(
-                    error.error_string(),
-                )));
-            } else {
-                if unsafe { libc::WIFEXITED(status) } {
-                    let exit_code = unsafe { libc::WEXITSTATUS(status) } as u32;
-                    if exit_code == 0 {
-                        return Ok(RunInfo::new(RunInfoResult::Success(result), usage));
-                    } else {
-                        return Ok(RunInfo::new(
-                            RunInfoResult::NonZeroExitStatus(exit_code),
-                            usage,
-                        ));
-                    }
-                }
+                return Err(Error::FFIError(FFIError::WaitPidError { errno: error }));
+            }
 
-                if unsafe { libc::WIFSIGNALED(status) } {
-                    let signal = unsafe { libc::WTERMSIG(status) } as u32;
-                    return Ok(RunInfo::new(RunInfoResult::KilledBySignal(signal), usage));
-                }
+            let exit_status = to_exit_status(status)?;
+            // Limits might have been exceeded since they were last checked
+            let usage = idle.track(get_usage(timer.elapsed())?);
+            let termination = Termination::new(exit_status, false, soft_limit_signaled);
+            if let Some(run_info) = usage.check_limits(limits, termination) {
+                return Ok(run_info);
+            }
 
-                if unsafe { libc::WIFSTOPPED(status) || libc::WIFCONTINUED(status) } {
-                    return Err(Error::StoppedContinuedError);
-                }
+            let result = match exit_status {
+                ExitStatus::Exited(0) => RunInfoResult::Success(result),
+                ExitStatus::Exited(exit_code) => RunInfoResult::NonZeroExitStatus(exit_code),
+                ExitStatus::Signaled(signal) => RunInfoResult::KilledBySignal(signal),
+            };
+            return Ok(RunInfo::new(result, usage));
+        }
+    }
+
+    /// Kills the process because it exceeded a limit and reaps it.
+    fn kill_and_wait(&self, soft_limit_signaled: bool) -> StdResult<Termination, Error> {
+        let _ = unsafe { libc::kill(self.pid, libc::SIGKILL) };
+
+        let mut status: libc::c_int = 0;
+        while unsafe { libc::waitpid(self.pid, &mut status, 0) } == -1 {
+            let error = Errno::last();
+            if error.code() != libc::EINTR {
+                return Err(Error::FFIError(FFIError::WaitPidError { errno: error }));
             }
         }
+
+        // It might have ended on its own just before the kill
+        let exit_status = to_exit_status(status)?;
+        Ok(Termination::new(
+            exit_status,
+            exit_status == ExitStatus::Signaled(libc::SIGKILL as u32),
+            soft_limit_signaled,
+        ))
+    }
+}
+
+/// Keeps track of the longest time the cpu usage did not increase between samples.
+struct IdleTracker {
+    user_time: Duration,
+    throttling: CpuThrottling,
+    since: Duration,
+    longest: Duration,
+}
+
+impl IdleTracker {
+    fn new() -> Self {
+        Self {
+            user_time: Duration::from_secs(0),
+            throttling: CpuThrottling::default(),
+            since: Duration::from_secs(0),
+            longest: Duration::from_secs(0),
+        }
+    }
+
+    fn track(&mut self, usage: RunUsage) -> RunUsage {
+        // Being throttled by the cpu quota is not idling, though a throttled period still
+        // counts until it ends
+        if usage.user_time() > self.user_time || usage.throttling() != self.throttling {
+            self.user_time = usage.user_time();
+            self.throttling = usage.throttling();
+            self.since = usage.wall_time();
+        }
+        self.longest = cmp::max(self.longest, usage.wall_time() - self.since);
+
+        RunUsage::new(
+            usage.user_time(),
+            usage.wall_time(),
+            usage.memory(),
+            self.longest,
+            usage.throttling(),
+            usage.io(),
+            usage.disk_usage(),
+        )
+    }
+}
+
+pub(crate) fn to_exit_status(status: libc::c_int) -> StdResult<ExitStatus, Error> {
+    if libc::WIFEXITED(status) {
+        Ok(ExitStatus::Exited(libc::WEXITSTATUS(status) as u32))
+    } else if libc::WIFSIGNALED(status) {
+        Ok(ExitStatus::Signaled(libc::WTERMSIG(status) as u32))
+    } else {
+        Err(Error::StoppedContinuedError)
     }
 }
 
@@ -734,5 +1258,14 @@ impl<T> Drop for CloneHandle<T> {
     fn drop(&mut self) {
         let _ = unsafe { libc::kill(self.pid, libc::SIGKILL) };
         let _ = unsafe { libc::kill(-self.pid, libc::SIGKILL) };
+
+        // Reap it (if not already), so by the time we are done it no longer holds any resources
+        // (like cgroups). Retry if interrupted by the alarm.
+        loop {
+            let res = unsafe { libc::waitpid(self.pid, ptr::null_mut(), 0) };
+            if res != -1 || Errno::last().code() != libc::EINTR {
+                break;
+            }
+        }
     }
 }
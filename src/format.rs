@@ -0,0 +1,276 @@
+//! The run information in the formats other programs can consume: templates, `key=value`
+//! pairs and CSV.
+//!
+//! All of them are made of the same [`FIELDS`], taken from the verdict and the usage of the run
+//! as plain values: times in milliseconds, space in kibibytes and flags as `true` or `false`.
+//! Fields that do not apply to the run (like the limit of a successful one) are empty.
+//!
+//! [`FIELDS`]: constant.FIELDS.html
+
+use std::fmt::Write;
+use std::str::FromStr;
+use std::time::Duration;
+
+use config::SpaceUsage;
+use run_info::{ExitStatus, RunInfo, RunInfoResult, Verdict};
+use utils::DurationExt;
+
+/// The names of the fields, in the order of the CSV columns.
+pub const FIELDS: &[&str] = &[
+    "verdict",
+    "result",
+    "exit_code",
+    "signal",
+    "killed_by_sandbox",
+    "soft_limit_signaled",
+    "limit_ms",
+    "limit_kib",
+    "limit_usage_ms",
+    "limit_usage_kib",
+    "user_time_ms",
+    "wall_time_ms",
+    "memory_kib",
+    "idle_time_ms",
+    "nr_throttled",
+    "throttled_time_ms",
+    "io_read_bytes",
+    "io_write_bytes",
+    "disk_usage_kib",
+    "check_verdict",
+    "check_message",
+];
+
+fn milliseconds(duration: Duration) -> String {
+    duration.as_milliseconds().to_string()
+}
+
+fn kibibytes(space: SpaceUsage) -> String {
+    (space.as_bytes() / 1024).to_string()
+}
+
+/// The values of the [`FIELDS`], in the same order.
+///
+/// [`FIELDS`]: constant.FIELDS.html
+fn values<T>(run_info: &RunInfo<T>) -> Vec<String> {
+    let result = run_info.result();
+    let verdict = match *result {
+        RunInfoResult::Success(_) => "success",
+        RunInfoResult::NonZeroExitStatus(_) => "non_zero_exit_status",
+        RunInfoResult::KilledBySignal(_) => "killed_by_signal",
+        RunInfoResult::MemoryLimitExceeded(_) => "memory_limit_exceeded",
+        RunInfoResult::TimeLimitExceeded(_) => "time_limit_exceeded",
+        RunInfoResult::WallTimeLimitExceeded(_) => "wall_time_limit_exceeded",
+        RunInfoResult::Idle(_) => "idle",
+    };
+    let (exit_code, signal) = match result.exit_status() {
+        ExitStatus::Exited(exit_code) => (exit_code.to_string(), String::new()),
+        ExitStatus::Signaled(signal) => (String::new(), signal.to_string()),
+    };
+    let (termination, limit_ms, limit_usage_ms) = match *result {
+        RunInfoResult::TimeLimitExceeded(ref exceeded)
+        | RunInfoResult::WallTimeLimitExceeded(ref exceeded)
+        | RunInfoResult::Idle(ref exceeded) => (
+            Some(exceeded.termination()),
+            milliseconds(exceeded.limit()),
+            milliseconds(exceeded.usage()),
+        ),
+        _ => (None, String::new(), String::new()),
+    };
+    let (termination, limit_kib, limit_usage_kib) = match *result {
+        RunInfoResult::MemoryLimitExceeded(ref exceeded) => (
+            Some(exceeded.termination()),
+            kibibytes(exceeded.limit()),
+            kibibytes(exceeded.usage()),
+        ),
+        _ => (termination, String::new(), String::new()),
+    };
+    let (killed_by_sandbox, soft_limit_signaled) = match termination {
+        Some(termination) => (
+            termination.killed_by_sandbox(),
+            termination.soft_limit_signaled(),
+        ),
+        None => (false, false),
+    };
+    let (check_verdict, check_message) = match run_info.check() {
+        Some(check) => {
+            let check_verdict = match check.verdict() {
+                Verdict::Accepted => "accepted",
+                Verdict::WrongAnswer => "wrong_answer",
+                Verdict::PresentationError => "presentation_error",
+            };
+            (check_verdict.to_string(), check.message().to_string())
+        }
+        None => (String::new(), String::new()),
+    };
+
+    let usage = run_info.usage();
+    vec![
+        verdict.to_string(),
+        result.to_string(),
+        exit_code,
+        signal,
+        killed_by_sandbox.to_string(),
+        soft_limit_signaled.to_string(),
+        limit_ms,
+        limit_kib,
+        limit_usage_ms,
+        limit_usage_kib,
+        milliseconds(usage.user_time()),
+        milliseconds(usage.wall_time()),
+        kibibytes(usage.memory()),
+        milliseconds(usage.idle_time()),
+        usage.throttling().nr_throttled().to_string(),
+        milliseconds(usage.throttling().throttled_time()),
+        usage.io().read_bytes().to_string(),
+        usage.io().write_bytes().to_string(),
+        kibibytes(usage.disk_usage()),
+        check_verdict,
+        check_message,
+    ]
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+enum Part {
+    Text(String),
+    /// The index of the field in `FIELDS`
+    Field(usize),
+}
+
+/// A line of text with fields in braces, like `{verdict} {user_time_ms} {memory_kib}`. Braces
+/// themselves are written `{{` and `}}`. Backslashes and control characters in the values are
+/// escaped (`\\`, `\n`, ...), so a run is always a single line.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+impl Template {
+    pub fn render<T>(&self, run_info: &RunInfo<T>) -> String {
+        let values = values(run_info);
+        let mut line = String::new();
+        for part in &self.parts {
+            match *part {
+                Part::Text(ref text) => line.push_str(text),
+                Part::Field(index) => escape(&values[index], false, &mut line),
+            }
+        }
+        line
+    }
+}
+
+impl FromStr for Template {
+    type Err = String;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        let mut parts = vec![];
+        let mut text = String::new();
+        let mut chars = string.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.as_str().starts_with('{') => {
+                    let _ = chars.next();
+                    text.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    let _ = chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let rest = chars.as_str();
+                    let end = rest
+                        .find('}')
+                        .ok_or_else(|| format!("Unclosed {{ in template: {}", string))?;
+                    let name = &rest[..end];
+                    let unknown = || {
+                        format!(
+                            "Unknown field {{{}}} in template, expected one of: {}",
+                            name,
+                            FIELDS.join(", ")
+                        )
+                    };
+                    let index = FIELDS
+                        .iter()
+                        .position(|&field| field == name)
+                        .ok_or_else(unknown)?;
+                    if !text.is_empty() {
+                        parts.push(Part::Text(text.split_off(0)));
+                    }
+                    parts.push(Part::Field(index));
+                    chars = rest[end + 1..].chars();
+                }
+                '}' => return Err(format!("Unmatched }} in template: {}", string)),
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+        Ok(Self { parts })
+    }
+}
+
+/// Writes `value` to `line` with backslashes and control characters escaped: `\\`, `\n`,
+/// `\r`, `\t` and `\u00XX` for the others. Quotes are escaped as well (`\"`) with `quoted`.
+fn escape(value: &str, quoted: bool, line: &mut String) {
+    for c in value.chars() {
+        match c {
+            '\\' => line.push_str("\\\\"),
+            '"' if quoted => line.push_str("\\\""),
+            '\n' => line.push_str("\\n"),
+            '\r' => line.push_str("\\r"),
+            '\t' => line.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(line, "\\u{:04x}", u32::from(c));
+            }
+            c => line.push(c),
+        }
+    }
+}
+
+/// All the fields on a single line, like `verdict=success user_time_ms=12 ...`. Values with
+/// spaces, quotes, backslashes, `=` or control characters are quoted as in logfmt: `"` and `\`
+/// are escaped with a backslash, control characters are written `\n`, `\t`, ...
+pub fn key_value<T>(run_info: &RunInfo<T>) -> String {
+    let mut line = String::new();
+    for (field, value) in FIELDS.iter().zip(values(run_info)) {
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        let needs_quotes = value
+            .chars()
+            .any(|c| c.is_whitespace() || c.is_control() || c == '"' || c == '=' || c == '\\');
+        let _ = write!(line, "{}=", field);
+        if needs_quotes {
+            line.push('"');
+            escape(&value, true, &mut line);
+            line.push('"');
+        } else {
+            line.push_str(&value);
+        }
+    }
+    line
+}
+
+fn csv_line<I: IntoIterator<Item = String>>(values: I) -> String {
+    values
+        .into_iter()
+        .map(|value| {
+            if value.contains(&[',', '"', '\n', '\r'][..]) {
+                format!("\"{}\"", value.replace('"', "\"\""))
+            } else {
+                value
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// The names of the fields, as the first line of a CSV file.
+pub fn csv_header() -> String {
+    csv_line(FIELDS.iter().map(|field| field.to_string()))
+}
+
+/// The values of the fields as a CSV record, quoted as in RFC 4180.
+pub fn csv_record<T>(run_info: &RunInfo<T>) -> String {
+    csv_line(values(run_info))
+}
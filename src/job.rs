@@ -0,0 +1,349 @@
+//! Compiling a program in one sandbox and running what it produced in another.
+//!
+//! The compile stage has a configuration of its own (the compiler, its limits and mounts). The
+//! artifact it leaves behind is copied or bind mounted into the run stage. The standard output
+//! and error of both stages are captured through pipes, unless their configuration already
+//! redirects them.
+
+use std::fmt::{self, Display, Formatter};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::thread::{self, JoinHandle};
+
+use libc;
+
+use config::{Config, Mount, MountOptions};
+use errors::{Errno, JobError, Result};
+use ffi;
+use run_info::{CheckResult, RunInfo};
+
+/// Only the beginning of longer logs is kept.
+const LOG_MAX_LENGTH: u64 = 64 * 1024;
+
+/// How the artifact gets into the run stage.
+#[derive(Debug, Default, Eq, PartialEq, Copy, Clone)]
+pub enum ArtifactMode {
+    /// Copied, inside the new root if the run stage has one
+    #[default]
+    Copy,
+    /// Bind mounted (read only) into the new root. Copied if the run stage has no new root
+    Mount,
+}
+
+/// What the compile stage produces for the run stage.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Artifact {
+    source: PathBuf,
+    destination: PathBuf,
+    mode: ArtifactMode,
+}
+
+impl Artifact {
+    /// `source` is where the compile stage leaves the artifact, outside of the jails (usually
+    /// inside a writable mount). `destination` is where the run stage finds it.
+    pub fn new(source: PathBuf, destination: PathBuf, mode: ArtifactMode) -> Self {
+        Self {
+            source,
+            destination,
+            mode,
+        }
+    }
+
+    pub fn source(&self) -> &Path {
+        &self.source
+    }
+
+    pub fn destination(&self) -> &Path {
+        &self.destination
+    }
+
+    pub fn mode(&self) -> ArtifactMode {
+        self.mode
+    }
+}
+
+/// A compile stage followed by a run stage.
+#[derive(Debug)]
+pub struct CompileJob {
+    compile: Config,
+    run: Config,
+    artifact: Artifact,
+}
+
+impl CompileJob {
+    pub fn new(compile: Config, run: Config, artifact: Artifact) -> Self {
+        Self {
+            compile,
+            run,
+            artifact,
+        }
+    }
+
+    pub fn compile(&self) -> &Config {
+        &self.compile
+    }
+
+    pub fn run(&self) -> &Config {
+        &self.run
+    }
+
+    pub fn artifact(&self) -> &Artifact {
+        &self.artifact
+    }
+}
+
+/// How one of the stages went, along with what it printed.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct StageReport {
+    run_info: RunInfo<()>,
+    stdout: String,
+    stderr: String,
+}
+
+impl StageReport {
+    pub fn new(run_info: RunInfo<()>, stdout: String, stderr: String) -> Self {
+        Self {
+            run_info,
+            stdout,
+            stderr,
+        }
+    }
+
+    pub fn run_info(&self) -> &RunInfo<()> {
+        &self.run_info
+    }
+
+    /// The captured standard output, empty if the configuration redirected it
+    pub fn stdout(&self) -> &str {
+        &self.stdout
+    }
+
+    /// The captured standard error (the compiler errors), empty if the configuration redirected
+    /// it
+    pub fn stderr(&self) -> &str {
+        &self.stderr
+    }
+}
+
+impl Display for StageReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.run_info)?;
+        if !self.stdout.is_empty() {
+            write!(f, "\nStdout:\n{}", self.stdout.trim_end())?;
+        }
+        if !self.stderr.is_empty() {
+            write!(f, "\nStderr:\n{}", self.stderr.trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct JobReport {
+    compile: StageReport,
+    run: Option<StageReport>,
+}
+
+impl JobReport {
+    pub fn new(compile: StageReport, run: Option<StageReport>) -> Self {
+        Self { compile, run }
+    }
+
+    pub fn compile(&self) -> &StageReport {
+        &self.compile
+    }
+
+    /// Missing if the compile stage was not successful
+    pub fn run(&self) -> Option<&StageReport> {
+        self.run.as_ref()
+    }
+
+    /// Both stages were successful, and the run was accepted if its output was checked
+    pub fn is_success(&self) -> bool {
+        match self.run {
+            Some(ref run) => {
+                self.compile.run_info().is_success()
+                    && run.run_info().is_success()
+                    && run
+                        .run_info()
+                        .check()
+                        .into_iter()
+                        .all(CheckResult::is_accepted)
+            }
+            None => false,
+        }
+    }
+}
+
+impl Display for JobReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "Compile:\n{}", self.compile)?;
+        match self.run {
+            Some(ref run) => write!(f, "\nRun:\n{}", run),
+            None => write!(f, "\nRun: skipped, the compile stage failed"),
+        }
+    }
+}
+
+/// Runs the compile stage and, if it was successful, the run stage with its artifact.
+pub fn run(job: &CompileJob) -> Result<JobReport> {
+    let compile = run_stage(job.compile(), Vec::new())?;
+    if !compile.run_info().is_success() {
+        return Ok(JobReport::new(compile, None));
+    }
+
+    let artifact = job.artifact();
+    if !artifact.source().is_file() {
+        return Err(JobError::ArtifactMissing {
+            path: artifact.source().to_path_buf(),
+        }
+        .into());
+    }
+    let mut mounts = Vec::new();
+    match (job.run().new_root(), artifact.mode()) {
+        (Some(_), ArtifactMode::Mount) => {
+            let mut mount_options = MountOptions::default();
+            mount_options.set_exec(true);
+            mounts.push(Mount::new(
+                artifact.source().to_path_buf(),
+                artifact.destination().to_path_buf(),
+                mount_options,
+            ));
+        }
+        (new_root, _) => copy_artifact(artifact, new_root)?,
+    }
+
+    let run = run_stage(job.run(), mounts)?;
+    Ok(JobReport::new(compile, Some(run)))
+}
+
+fn copy_artifact(artifact: &Artifact, new_root: Option<&Path>) -> Result<()> {
+    let destination = match new_root {
+        Some(new_root) => new_root.join(
+            artifact
+                .destination()
+                .strip_prefix("/")
+                .unwrap_or_else(|_| artifact.destination()),
+        ),
+        None => artifact.destination().to_path_buf(),
+    };
+
+    let copy_error = |err: io::Error| JobError::CopyArtifactError {
+        from: artifact.source().to_path_buf(),
+        to: destination.clone(),
+        errno: Errno::from(&err),
+    };
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent).map_err(copy_error)?;
+    }
+    let _ = fs::copy(artifact.source(), &destination).map_err(copy_error)?;
+    Ok(())
+}
+
+/// Runs `config` with the extra `mounts`, capturing the streams it does not redirect.
+fn run_stage(config: &Config, extra_mounts: Vec<Mount>) -> Result<StageReport> {
+    let stdout_log = match config.redirect_stdout() {
+        Some(_) => None,
+        None => Some(Log::new("stdout")?),
+    };
+    let stderr_log = match config.redirect_stderr() {
+        Some(_) => None,
+        None => Some(Log::new("stderr")?),
+    };
+
+    // After the configured ones, so they are not hidden by them
+    let mut mounts = config.mounts().to_vec();
+    mounts.extend(extra_mounts);
+    let stage_config = config
+        .clone()
+        .with_redirect_stdout(
+            config
+                .redirect_stdout()
+                .map(Path::to_path_buf)
+                .or_else(|| stdout_log.as_ref().map(Log::path)),
+        )
+        .with_redirect_stderr(
+            config
+                .redirect_stderr()
+                .map(Path::to_path_buf)
+                .or_else(|| stderr_log.as_ref().map(Log::path)),
+        )
+        .with_mounts(mounts);
+
+    let handle = ::spawn_jail(&stage_config)?;
+    // Only once the supervisor is cloned, it must not be cloned while another thread holds a lock
+    let stdout_reader = stdout_log.map(Log::read);
+    let stderr_reader = stderr_log.map(Log::read);
+    let run_info = handle.wait();
+    let stdout = LogReader::finish(stdout_reader);
+    let stderr = LogReader::finish(stderr_reader);
+    Ok(StageReport::new(run_info?, stdout?, stderr?))
+}
+
+/// A captured stream, the pipe the jail writes it to.
+struct Log {
+    name: &'static str,
+    read_end: File,
+    write_end: File,
+}
+
+impl Log {
+    fn new(name: &'static str) -> Result<Self> {
+        let (read_end, write_end) = ffi::make_pipe()?;
+        Ok(Self {
+            name,
+            read_end,
+            write_end,
+        })
+    }
+
+    /// Where the jail opens the pipe, through the copy of the descriptor it starts with.
+    fn path(&self) -> PathBuf {
+        PathBuf::from(format!("/proc/self/fd/{}", self.write_end.as_raw_fd()))
+    }
+
+    /// Closes the write end (the jail has its own) and reads the pipe until the jail is done.
+    /// The rest of longer logs is read as well, so the jail never blocks on a full pipe.
+    fn read(self) -> LogReader {
+        let Self {
+            name,
+            read_end,
+            write_end,
+        } = self;
+        drop(write_end);
+        let thread = thread::spawn(move || {
+            let mut read_end = read_end;
+            let mut log = Vec::new();
+            let _ = (&mut read_end).take(LOG_MAX_LENGTH).read_to_end(&mut log)?;
+            let _ = io::copy(&mut read_end, &mut io::sink())?;
+            Ok(log)
+        });
+        LogReader { name, thread }
+    }
+}
+
+struct LogReader {
+    name: &'static str,
+    thread: JoinHandle<io::Result<Vec<u8>>>,
+}
+
+impl LogReader {
+    fn finish(reader: Option<Self>) -> Result<String> {
+        let Self { name, thread } = match reader {
+            Some(reader) => reader,
+            None => return Ok(String::new()),
+        };
+        let log_error = |errno| JobError::ReadLogError {
+            name: name.to_owned(),
+            errno,
+        };
+        match thread.join() {
+            Ok(Ok(log)) => Ok(String::from_utf8_lossy(&log).into_owned()),
+            Ok(Err(err)) => Err(log_error(Errno::from(&err)).into()),
+            // Like the other errors not coming from the operating system
+            Err(_) => Err(log_error(Errno::new(libc::EIO)).into()),
+        }
+    }
+}
@@ -42,26 +42,55 @@
 
 extern crate bincode;
 #[macro_use]
-extern crate failure;
+extern crate thiserror;
 extern crate libc;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+extern crate tempfile;
 
+pub mod batch;
 mod cgroups;
+pub mod checker;
 pub mod config;
+pub mod cpuset;
+pub mod doctor;
 pub mod errors;
 mod ffi;
+pub mod format;
+pub mod job;
+pub mod pool;
+pub mod preset;
+mod processes;
+mod quota;
+pub mod rootfs;
 pub mod run_info;
+pub mod setup;
 pub mod utils;
 
-use config::{Config, Interactive, Limits, ShareNet, SwapRedirects};
+use std::ffi::OsStr;
+use std::fs::File;
+use std::path::Path;
+
+use config::{
+    ClearUsage, Config, DetailedUsage, Interactive, Limits, ShareNet, SpaceUsage, SwapRedirects,
+};
 pub use errors::*;
 use ffi::CloneHandle;
-use run_info::{RunInfo, RunUsage};
+use processes::ProcessTracker;
+use quota::QuotaMounts;
+use run_info::{ExitStatus, LimitExceeded, RunInfo, RunInfoResult, RunUsage, Termination};
 
 pub fn spawn_jail(config: &Config) -> Result<JailHandle> {
+    let instance = cgroups::Instance::new(config.controller_path(), config.instance_name());
+    spawn(config, instance, None)
+}
+
+/// Starts a jail. With `jobs` the jail is prepared up to the point of exec and waits for the job
+/// to run on that socket (see [`pool::Pool`]) instead of running the configured command.
+fn spawn(config: &Config, instance: cgroups::Instance, jobs: Option<File>) -> Result<JailHandle> {
     let user_group_id = ffi::get_user_group_id();
+    let instance_name = instance.name();
 
     ffi::set_sig_alarm_handler().map_err(Error::FFIError)?;
 
@@ -69,110 +98,357 @@ pub fn spawn_jail(config: &Config) -> Result<JailHandle> {
     // If by any chance the supervisor process dies, by rules of pid namespaces
     // all its descendant processes will die as well
     ffi::clone(ShareNet::Share, false, || {
-        ffi::kill_on_parent_death()?;
-        // Mount proc just for security
-        ffi::mount_proc()?;
-        // Without setting uid/gid maps user is not seen so it can not do anything
-        ffi::set_uid_gid_maps(user_group_id)?;
-
-        ffi::clone(config.share_net(), true, || {
-            if config.swap_redirects() == SwapRedirects::Yes {
-                if let Some(stdout) = config.redirect_stdout() {
-                    ffi::redirect_fd(ffi::STDOUT, stdout)?;
-                }
-            }
+        let mut quota_mounts = prepare_supervisor(config, user_group_id)?;
+        let run_info = supervise_jail(
+            config,
+            instance_name,
+            &JailRun::from_config(config),
+            &mut quota_mounts,
+            jobs.as_ref(),
+        )?;
+        quota_mounts.copy_back()?;
+        Ok(run_info)
+    })
+    .map(|handle| JailHandle::new(handle, instance))
+    .map_err(Error::from)
+}
 
-            if let Some(stdin) = config.redirect_stdin() {
-                ffi::redirect_fd(ffi::STDIN, stdin)?;
-            }
+/// What can differ between the jails started by the same supervisor.
+#[derive(Debug)]
+pub(crate) struct JailRun<'a> {
+    redirect_stdin: Option<&'a Path>,
+    redirect_stdout: Option<&'a Path>,
+    limits: Limits,
+    clear_usage: ClearUsage,
+}
 
-            if config.swap_redirects() == SwapRedirects::No {
-                if let Some(stdout) = config.redirect_stdout() {
-                    ffi::redirect_fd(ffi::STDOUT, stdout)?;
-                }
-            }
+impl<'a> JailRun<'a> {
+    pub(crate) fn new(
+        redirect_stdin: Option<&'a Path>,
+        redirect_stdout: Option<&'a Path>,
+        limits: Limits,
+        clear_usage: ClearUsage,
+    ) -> Self {
+        Self {
+            redirect_stdin,
+            redirect_stdout,
+            limits,
+            clear_usage,
+        }
+    }
 
-            if let Some(stderr) = config.redirect_stderr() {
-                ffi::redirect_fd(ffi::STDERR, stderr)?;
-            }
+    fn from_config(config: &'a Config) -> Self {
+        Self::new(
+            config.redirect_stdin(),
+            config.redirect_stdout(),
+            config.limits(),
+            config.clear_usage(),
+        )
+    }
+}
 
-            ffi::set_stack_limit(config.limits().stack())?;
-            // Enter cgroup before we pivot root, then it is too late
-            cgroups::enter_all_cgroups(
-                config.controller_path(),
-                config.instance_name(),
-                config.limits(),
-                config.clear_usage(),
-            )?;
-
-            ffi::unshare_cgroup()?;
-
-            // Remount everything privately
-            ffi::remount_private()?;
-
-            if let Some(new_root) = config.new_root() {
-                for mount in config.mounts() {
-                    ffi::mount_inside(new_root, mount)?;
-                }
-
-                ffi::pivot_root(new_root, || {
-                    // Mount proc (since we are in a new pid namespace)
-                    // Must be done after pivot_root so we mount this in the right location
-                    // but also before we unmount the old root because ... I don't know
-                    ffi::mount_proc()
-                })?;
-            } else {
-                ffi::mount_proc()?;
-            }
+/// Sets up what all the jails of a supervisor share. Must run in the supervisor.
+pub(crate) fn prepare_supervisor(
+    config: &Config,
+    user_group_id: (ffi::UserId, ffi::GroupId),
+) -> Result<QuotaMounts> {
+    ffi::kill_on_parent_death()?;
+    // Mount proc just for security
+    ffi::mount_proc()?;
+    // Without setting uid/gid maps user is not seen so it can not do anything
+    ffi::set_uid_gid_maps(user_group_id)?;
 
-            // Make sure we are root (we don't really need to,
-            // but this way the child process can do anything it likes
-            // inside its namespace and nothing outside)
-            // Must be done after mount_proc so we can properly read and write
-            // /proc/self/uid_map and /proc/self/gid_map
-            ffi::set_uid_gid_maps((ffi::UserId::ROOT, ffi::GroupId::ROOT))?;
-
-            if config.interactive() == Interactive::No {
-                // Move the process to a different process group (so it can't kill it's own
-                // father by sending signals to the whole process group)
-                // But for interactive applications (mostly to test stuff), leave it there
-                ffi::move_to_different_process_group()?;
-            }
+    // The mounts are only used with a new root
+    let new_root = match config.new_root() {
+        Some(new_root) => new_root,
+        None => return QuotaMounts::new(&[]),
+    };
+    let quota_mounts = QuotaMounts::new(config.mounts())?;
+    // Mounted once, every jail gets a copy of the supervisor's mount namespace
+    ffi::remount_private()?;
+    for mount in quota_mounts.mounts() {
+        ffi::mount_inside(new_root, mount)?;
+    }
+    Ok(quota_mounts)
+}
+
+/// Runs a single jail from the supervisor and waits for it. The size limited mounts start over
+/// from their sources and are not copied back.
+pub(crate) fn supervise_jail(
+    config: &Config,
+    instance_name: &OsStr,
+    jail_run: &JailRun,
+    quota_mounts: &mut QuotaMounts,
+    jobs: Option<&File>,
+) -> Result<RunInfo<()>> {
+    quota_mounts.reset()?;
+    let memory_events = cgroups::get_memory_events(config.controller_path(), instance_name);
+    let process_tracker = match config.detailed_usage() {
+        DetailedUsage::Yes => Some(ProcessTracker::new(config.controller_path(), instance_name)),
+        DetailedUsage::No => None,
+    };
+    // (supervisor end, jail end) for passing on the job
+    let job_sockets = match jobs {
+        Some(_) => Some(ffi::socket_pair()?),
+        None => None,
+    };
+
+    // A prepared jail does not hold the supervisor back, it still has to receive the job
+    let jail = ffi::clone(config.share_net(), jobs.is_none(), || {
+        // The streams of a job are redirected once it arrives
+        if jobs.is_none() {
+            redirect_std_fds(config, jail_run)?;
+        }
+
+        ffi::set_stack_limit(jail_run.limits.stack())?;
+        // Enter cgroup before we pivot root, then it is too late
+        cgroups::enter_all_cgroups(
+            config.controller_path(),
+            instance_name,
+            with_memory_baseline(jail_run.limits, config.memory_baseline()),
+            jail_run.clear_usage,
+            config.cpuset(),
+        )?;
+
+        ffi::unshare_cgroup()?;
+
+        // Remount everything privately
+        ffi::remount_private()?;
 
-            ffi::exec_command(config.command(), &config.args(), config.environment())?;
+        if let Some(new_root) = config.new_root() {
+            // The mounts inside it were done by the supervisor
+            ffi::pivot_root(new_root, || {
+                // Mount proc (since we are in a new pid namespace)
+                // Must be done after pivot_root so we mount this in the right location
+                // but also before we unmount the old root because ... I don't know
+                ffi::mount_proc()
+            })?;
+        } else {
+            ffi::mount_proc()?;
+        }
+
+        // Make sure we are root (we don't really need to,
+        // but this way the child process can do anything it likes
+        // inside its namespace and nothing outside)
+        // Must be done after mount_proc so we can properly read and write
+        // /proc/self/uid_map and /proc/self/gid_map
+        ffi::set_uid_gid_maps((ffi::UserId::ROOT, ffi::GroupId::ROOT))?;
+
+        if config.interactive() == Interactive::No {
+            // Move the process to a different process group (so it can't kill it's own
+            // father by sending signals to the whole process group)
+            // But for interactive applications (mostly to test stuff), leave it there
+            ffi::move_to_different_process_group()?;
+        }
+
+        let job = match job_sockets {
+            Some((_, ref socket)) => Some(pool::start_job(socket)?),
+            None => None,
+        };
+
+        if let Some(landlock) = config.landlock() {
+            // Enforce the filesystem rules last, so none of the setup above is affected
+            ffi::landlock_restrict(landlock)?;
+        }
+
+        match job {
+            Some(job) => ffi::exec_command(job.command(), &job.args(), config.environment())?,
+            None => ffi::exec_command(config.command(), &config.args(), config.environment())?,
+        }
+
+        Ok(())
+    })?;
+
+    if let (Some(jobs), Some((socket, jail_socket))) = (jobs, job_sockets) {
+        // Only the jail holds its end now, so it closes if the jail dies while being prepared
+        drop(jail_socket);
+        pool::forward_job(jobs, &socket, || {
+            // Preparing the jail is not part of the job
+            Ok(cgroups::reset_usage(
+                config.controller_path(),
+                instance_name,
+            )?)
+        })?;
+    }
 
-            Ok(())
-        })?
-        .wait(config.limits(), |wall_time| {
-            Ok(cgroups::get_usage(
+    jail.wait(jail_run.limits, |wall_time| {
+        if let Some(process_tracker) = &process_tracker {
+            process_tracker.sample();
+        }
+        quota_mounts
+            .record_usage(cgroups::get_usage(
                 config.controller_path(),
-                config.instance_name(),
+                instance_name,
                 wall_time,
             )?)
-        })
-        .and_then(|run_info| {
-            run_info.and_then(|option| match option {
-                None => Ok(()),
-                Some(result) => result.map_err(Error::ChildError),
-            })
+            .map(|usage| without_memory_baseline(usage, config.memory_baseline()))
+    })
+    .map(|run_info| {
+        memory_limit_verdict(
+            run_info,
+            &memory_events,
+            &cgroups::get_memory_events(config.controller_path(), instance_name),
+            jail_run.limits.memory(),
+        )
+    })
+    .and_then(|run_info| {
+        run_info.and_then(|option| match option {
+            None => Ok(()),
+            Some(result) => result.map_err(Error::ChildError),
         })
     })
-    .map(JailHandle::new)
-    .map_err(Error::from)
+    .map(|run_info| match process_tracker {
+        Some(process_tracker) => {
+            let exit_status = run_info.result().exit_status();
+            run_info.with_processes(process_tracker.into_tree(exit_status))
+        }
+        None => run_info,
+    })
+}
+
+fn redirect_std_fds(config: &Config, jail_run: &JailRun) -> std::result::Result<(), FFIError> {
+    if config.swap_redirects() == SwapRedirects::Yes {
+        if let Some(stdout) = jail_run.redirect_stdout {
+            ffi::redirect_fd(ffi::STDOUT, stdout)?;
+        }
+    }
+
+    if let Some(stdin) = jail_run.redirect_stdin {
+        ffi::redirect_fd(ffi::STDIN, stdin)?;
+    }
+
+    if config.swap_redirects() == SwapRedirects::No {
+        if let Some(stdout) = jail_run.redirect_stdout {
+            ffi::redirect_fd(ffi::STDOUT, stdout)?;
+        }
+    }
+
+    if let Some(stderr) = config.redirect_stderr() {
+        ffi::redirect_fd(ffi::STDERR, stderr)?;
+    }
+    Ok(())
+}
+
+/// The limits enforced by the cgroups, the memory limit raised by the baseline of the runtime.
+fn with_memory_baseline(limits: Limits, memory_baseline: Option<SpaceUsage>) -> Limits {
+    let memory = match (limits.memory(), memory_baseline) {
+        (Some(memory), Some(baseline)) => Some(SpaceUsage::from_bytes(
+            memory.as_bytes().saturating_add(baseline.as_bytes()),
+        )),
+        (memory, _) => memory,
+    };
+    Limits::new(
+        limits.wall_time(),
+        limits.user_time(),
+        memory,
+        limits.stack(),
+        limits.pids(),
+        limits.idle_time(),
+        limits.soft(),
+        limits.cpu_quota(),
+        limits.io(),
+    )
+}
+
+/// The usage of the program, without the memory taken by the runtime itself.
+fn without_memory_baseline(usage: RunUsage, memory_baseline: Option<SpaceUsage>) -> RunUsage {
+    let baseline = match memory_baseline {
+        Some(baseline) => baseline,
+        None => return usage,
+    };
+    RunUsage::new(
+        usage.user_time(),
+        usage.wall_time(),
+        SpaceUsage::from_bytes(
+            usage
+                .memory()
+                .as_bytes()
+                .saturating_sub(baseline.as_bytes()),
+        ),
+        usage.idle_time(),
+        usage.throttling(),
+        usage.io(),
+        usage.disk_usage(),
+    )
+}
+
+/// Whether the memory usage got to the limit, for when the cgroup does not count it. The kernel
+/// rounds the limit down to whole pages, so the usage can stay just below it.
+fn reached_memory_limit(usage: SpaceUsage, memory_limit: SpaceUsage) -> bool {
+    usage.as_bytes().saturating_add(ffi::page_size()) > memory_limit.as_bytes()
+}
+
+/// Finds out when the memory limit is what actually ended the program.
+///
+/// The OOM killer uses SIGKILL, so a process it killed looks like any other killed process.
+/// A program can also notice it could not allocate memory and exit with an error, which only
+/// counts if the limit was hit during the run.
+fn memory_limit_verdict<T>(
+    run_info: RunInfo<T>,
+    before: &cgroups::MemoryEvents,
+    after: &cgroups::MemoryEvents,
+    memory_limit: Option<SpaceUsage>,
+) -> RunInfo<T> {
+    let memory_limit = match memory_limit {
+        Some(memory_limit) => memory_limit,
+        None => return run_info,
+    };
+
+    let exceeded = |exit_status| {
+        RunInfo::new(
+            RunInfoResult::MemoryLimitExceeded(LimitExceeded::new(
+                memory_limit,
+                run_info.usage().memory(),
+                Termination::new(exit_status, false, false),
+            )),
+            *run_info.usage(),
+        )
+    };
+
+    match *run_info.result() {
+        RunInfoResult::KilledBySignal(signal) if signal == libc::SIGKILL as u32 => {
+            let oom_killed = after
+                .oom_killed_since(before)
+                // Without the counter, being killed while at the limit is the best guess
+                .unwrap_or_else(|| reached_memory_limit(run_info.usage().memory(), memory_limit));
+            if oom_killed {
+                return exceeded(ExitStatus::Signaled(signal));
+            }
+        }
+        RunInfoResult::NonZeroExitStatus(exit_code) => {
+            let limit_hit = after
+                .limit_hit_since(before)
+                .unwrap_or_else(|| reached_memory_limit(run_info.usage().memory(), memory_limit));
+            if limit_hit {
+                return exceeded(ExitStatus::Exited(exit_code));
+            }
+        }
+        _ => {}
+    }
+    run_info
 }
 
 #[allow(missing_debug_implementations)]
 pub struct JailHandle {
     handle: CloneHandle<Result<RunInfo<()>>>,
+    instance: cgroups::Instance,
 }
 
 impl JailHandle {
-    fn new(handle: CloneHandle<Result<RunInfo<()>>>) -> Self {
-        Self { handle }
+    fn new(handle: CloneHandle<Result<RunInfo<()>>>, instance: cgroups::Instance) -> Self {
+        Self { handle, instance }
+    }
+
+    /// The cgroup instance name used by the jail, generated if none was configured
+    ///
+    /// Generated instances are removed once the jail is finished.
+    pub fn instance_name(&self) -> &OsStr {
+        self.instance.name()
     }
 
     pub fn wait(self) -> Result<RunInfo<()>> {
-        self.handle
+        let Self { handle, instance } = self;
+        let result = handle
             .wait(Limits::default(), |_| Ok(RunUsage::default()))
             .and_then(|run_info| {
                 run_info
@@ -180,6 +456,10 @@ impl JailHandle {
                     .and_then(|x| x) // its an option inside an option, so flatten it
                     .ok_or(Error::SupervisorProcessDiedError)
                     .and_then(|x| x) // result in result, flatten it
-            })
+            });
+
+        // The supervisor was reaped, nothing is left inside the cgroups
+        drop(instance);
+        result
     }
 }
@@ -42,56 +42,306 @@
 #[macro_use]
 extern crate clap;
 #[macro_use]
-extern crate failure;
+extern crate anyhow;
 extern crate ia_sandbox;
+extern crate libc;
+#[macro_use]
 extern crate serde_json;
 
-use failure::Fail;
 use std::process;
 
+use std::error::Error;
 use std::io;
+use std::path::Path;
 
+use ia_sandbox::batch::{self, Case, OnFailure};
+use ia_sandbox::config::{Config, ControllerPath};
+use ia_sandbox::doctor;
+use ia_sandbox::errors::DoctorError;
+use ia_sandbox::format;
+use ia_sandbox::job::{self, CompileJob};
+use ia_sandbox::run_info::RunInfo;
+use ia_sandbox::setup::{self, SetupOptions};
 use ia_sandbox::utils::DurationExt;
 
 mod app;
 mod args;
-use args::OutputType;
+mod exit_code;
+use args::{Check, Command, OutputType};
+use exit_code::ExitCodes;
 
-fn main() {
-    match args::parse()
-        .and_then(|(args, output)| Ok((ia_sandbox::spawn_jail(&args)?.wait()?, output)))
-    {
-        Ok((run_info, output)) => {
-            match output {
-                OutputType::Human => println!("{}", run_info),
-                OutputType::Oneline => {
-                    if run_info.is_success() {
-                        print!("OK: ");
-                    } else {
-                        print!("FAIL: ");
-                    }
-                    println!(
-                        "time {}ms memory {}kb: {}",
-                        run_info.usage().user_time().as_milliseconds(),
-                        run_info.usage().memory().as_kilobytes(),
-                        run_info.result()
-                    );
+type Result<T> = std::result::Result<T, anyhow::Error>;
+
+fn print_run_info(run_info: &RunInfo<()>, output: &OutputType) {
+    match *output {
+        OutputType::Human => println!("{}", run_info),
+        OutputType::Oneline => {
+            // A wrong answer is a failure as well
+            let rejected = run_info.check().filter(|check| !check.is_accepted());
+            if run_info.is_success() && rejected.is_none() {
+                print!("OK: ");
+            } else {
+                print!("FAIL: ");
+            }
+            print!(
+                "time {}ms memory {}kb: {}",
+                run_info.usage().user_time().as_milliseconds(),
+                run_info.usage().memory().as_kilobytes(),
+                run_info.result()
+            );
+            if let Some(check) = run_info.check() {
+                println!(": {}", check);
+            } else {
+                println!();
+            }
+        }
+        OutputType::Json => {
+            let stdout = io::stdout();
+            serde_json::to_writer_pretty(stdout.lock(), run_info).unwrap();
+        }
+        OutputType::KeyValue | OutputType::Csv | OutputType::Template(_) => {
+            print_lines(&[run_info], None, output)
+        }
+    };
+}
+
+/// A line for every run, for the formats meant for other programs (key-value, csv, template).
+/// With `stages` the csv and key-value lines start with the stage of the run.
+fn print_lines(run_infos: &[&RunInfo<()>], stages: Option<&[&str]>, output: &OutputType) {
+    if *output == OutputType::Csv {
+        let stage_column = if stages.is_some() { "stage," } else { "" };
+        println!("{}{}", stage_column, format::csv_header());
+    }
+    for (index, run_info) in run_infos.iter().enumerate() {
+        let stage = stages.map(|stages| stages[index]);
+        match *output {
+            OutputType::Csv => match stage {
+                Some(stage) => println!("{},{}", stage, format::csv_record(run_info)),
+                None => println!("{}", format::csv_record(run_info)),
+            },
+            OutputType::Template(ref template) => println!("{}", template.render(run_info)),
+            _ => match stage {
+                Some(stage) => println!("stage={} {}", stage, format::key_value(run_info)),
+                None => println!("{}", format::key_value(run_info)),
+            },
+        }
+    }
+}
+
+fn run(
+    config: &Config,
+    output: &OutputType,
+    check: Option<&Check>,
+    exit_codes: ExitCodes,
+) -> Result<i32> {
+    let mut run_info = ia_sandbox::spawn_jail(config)?.wait()?;
+    if let Some(check) = check {
+        run_info = check.checker.check_run(
+            run_info,
+            config
+                .redirect_stdin()
+                .unwrap_or_else(|| Path::new("/dev/null")),
+            config
+                .redirect_stdout()
+                .expect("--answer requires --stdout"),
+            &check.answer,
+        )?;
+    }
+    print_run_info(&run_info, output);
+    Ok(exit_codes.for_run_info(&run_info))
+}
+
+fn run_batch(
+    config: &Config,
+    cases: &[Case],
+    on_failure: OnFailure,
+    output: &OutputType,
+    exit_codes: ExitCodes,
+) -> Result<i32> {
+    let results = batch::run(config, cases, on_failure)?;
+    match *output {
+        OutputType::Json => {
+            // The cases that could not run have an error object instead
+            let results: Vec<_> = results
+                .iter()
+                .map(|result| match *result {
+                    Ok(ref run_info) => serde_json::to_value(run_info).unwrap(),
+                    Err(ref err) => error_object(&error_messages(err), Some(err)),
+                })
+                .collect();
+            let stdout = io::stdout();
+            serde_json::to_writer_pretty(stdout.lock(), &results).unwrap();
+        }
+        OutputType::KeyValue | OutputType::Csv | OutputType::Template(_) => {
+            // Only the cases that ran have a line, the errors go to stderr
+            for (index, result) in results.iter().enumerate() {
+                if let Err(ref err) = *result {
+                    eprintln!("Case {}: {}", index + 1, error_messages(err).join(": "));
                 }
-                OutputType::Json => {
-                    let stdout = io::stdout();
-                    serde_json::to_writer_pretty(stdout.lock(), &run_info).unwrap();
+            }
+            let run_infos: Vec<_> = results
+                .iter()
+                .filter_map(|result| result.as_ref().ok())
+                .collect();
+            print_lines(&run_infos, None, output)
+        }
+        OutputType::Human | OutputType::Oneline => {
+            for (index, result) in results.iter().enumerate() {
+                println!("Case {}:", index + 1);
+                match *result {
+                    Ok(ref run_info) => print_run_info(run_info, output),
+                    Err(ref err) => println!("ERROR: {}", error_messages(err).join(": ")),
                 }
-            };
-            process::exit(0);
+            }
         }
-        Err(err) => {
-            let mut fail: &dyn Fail = err.as_fail();
-            eprintln!("{}", fail);
-            while let Some(cause) = fail.cause() {
-                eprintln!("{}", cause);
-                fail = cause;
+    }
+    Ok(exit_codes.for_batch(&results))
+}
+
+fn run_job(job: &CompileJob, output: &OutputType, exit_codes: ExitCodes) -> Result<i32> {
+    let report = job::run(job)?;
+    match *output {
+        OutputType::Json => {
+            let stdout = io::stdout();
+            serde_json::to_writer_pretty(stdout.lock(), &report).unwrap();
+        }
+        OutputType::Human => println!("{}", report),
+        OutputType::Oneline => {
+            print!("Compile: ");
+            print_run_info(report.compile().run_info(), output);
+            if let Some(run) = report.run() {
+                print!("Run: ");
+                print_run_info(run.run_info(), output);
             }
-            process::exit(1);
+        }
+        OutputType::KeyValue | OutputType::Csv | OutputType::Template(_) => {
+            let mut run_infos = vec![report.compile().run_info()];
+            run_infos.extend(report.run().map(|run| run.run_info()));
+            print_lines(&run_infos, Some(&["compile", "run"]), output);
+        }
+    }
+    Ok(exit_codes.for_job(&report))
+}
+
+fn setup(options: &SetupOptions) -> Result<i32> {
+    print!("{}", setup::setup(options)?);
+    Ok(0)
+}
+
+fn doctor(controller_path: &ControllerPath, output: &OutputType) -> Result<i32> {
+    let report = doctor::diagnose(controller_path)?;
+    match *output {
+        // A failed report is printed as the fields of the error
+        OutputType::Json if !report.is_ok() => {}
+        OutputType::Json => {
+            let stdout = io::stdout();
+            serde_json::to_writer_pretty(stdout.lock(), &report).unwrap();
+        }
+        _ => print!("{}", report),
+    }
+
+    if report.is_ok() {
+        Ok(0)
+    } else {
+        Err(ia_sandbox::Error::from(DoctorError::ChecksFailed {
+            checks: report.checks().to_vec(),
+        })
+        .into())
+    }
+}
+
+/// The fields of the innermost error, as they are serialized.
+fn error_fields(err: &ia_sandbox::Error) -> serde_json::Value {
+    let mut value = serde_json::to_value(err).unwrap_or_default();
+    loop {
+        // Variants are serialized as `{"Variant": fields}`, fields start with a lowercase letter
+        let inner = match value {
+            serde_json::Value::Object(ref mut object) if object.len() == 1 => {
+                match object.iter_mut().next() {
+                    Some((name, inner)) if name.starts_with(char::is_uppercase) => inner.take(),
+                    _ => break,
+                }
+            }
+            serde_json::Value::Object(_) => break,
+            // Variants without fields
+            _ => return json!({}),
+        };
+        value = inner;
+    }
+    value
+}
+
+/// The message of `err` followed by the ones of its sources.
+fn error_messages(err: &dyn Error) -> Vec<String> {
+    let mut messages = vec![err.to_string()];
+    let mut source = err.source();
+    while let Some(err) = source {
+        messages.push(err.to_string());
+        source = err.source();
+    }
+    messages
+}
+
+/// How errors are printed with `--output-format json`.
+fn error_object(
+    messages: &[String],
+    sandbox_error: Option<&ia_sandbox::Error>,
+) -> serde_json::Value {
+    // Anything not coming from the sandbox is a problem with the arguments
+    let (code, fields) = match sandbox_error {
+        Some(sandbox_error) => (sandbox_error.code(), error_fields(sandbox_error)),
+        None => ("cli.invalid_arguments", json!({})),
+    };
+    json!({
+        "error": {
+            "code": code,
+            // The most specific one
+            "message": messages.last(),
+            "causes": messages,
+            "fields": fields,
+        }
+    })
+}
+
+fn print_error(err: &anyhow::Error, output: &OutputType) {
+    let messages: Vec<String> = err.chain().map(ToString::to_string).collect();
+    let sandbox_error = err
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<ia_sandbox::Error>());
+
+    match *output {
+        OutputType::Json => {
+            let stdout = io::stdout();
+            serde_json::to_writer_pretty(stdout.lock(), &error_object(&messages, sandbox_error))
+                .unwrap();
+        }
+        _ => {
+            for message in messages {
+                eprintln!("{}", message);
+            }
+        }
+    }
+}
+
+fn main() {
+    let (command, output, exit_codes) = args::parse();
+    let result = command.and_then(|command| match command {
+        Command::Run(config, output, _lease, check) => {
+            run(&config, &output, check.as_ref(), exit_codes)
+        }
+        Command::Batch(config, cases, on_failure, output, _lease) => {
+            run_batch(&config, &cases, on_failure, &output, exit_codes)
+        }
+        Command::Job(job, output, _lease) => run_job(&job, &output, exit_codes),
+        Command::Setup(options) => setup(&options),
+        Command::Doctor(controller_path, output) => doctor(&controller_path, &output),
+    });
+
+    match result {
+        Ok(code) => process::exit(code),
+        Err(err) => {
+            print_error(&err, &output);
+            process::exit(exit_codes.for_error(&err));
         }
     }
 }
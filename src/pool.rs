@@ -0,0 +1,192 @@
+//! Jails prepared ahead of time.
+//!
+//! Setting up a jail (namespaces, mounts, cgroups, pivoting the root) takes far longer than
+//! running a small program. A [`Pool`] keeps jails ready up to the point of exec, each waiting
+//! for a [`Job`] to run. The usage a jail gathered while being prepared is cleared right before
+//! its job starts.
+
+use std::collections::VecDeque;
+use std::ffi::{OsStr, OsString};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::result::Result as StdResult;
+
+use bincode;
+
+use cgroups;
+use config::Config;
+use errors::{FFIError, Result};
+use ffi;
+use JailHandle;
+
+/// A program to run in one of the jails of a [`Pool`].
+#[derive(Debug)]
+pub struct Job {
+    command: PathBuf,
+    args: Vec<OsString>,
+    stdin: File,
+    stdout: File,
+    stderr: File,
+}
+
+impl Job {
+    /// The standard streams are files opened outside the jail (or pipes), so they do not have to
+    /// exist inside the new root.
+    pub fn new(
+        command: PathBuf,
+        args: Vec<OsString>,
+        stdin: File,
+        stdout: File,
+        stderr: File,
+    ) -> Self {
+        Self {
+            command,
+            args,
+            stdin,
+            stdout,
+            stderr,
+        }
+    }
+
+    pub fn command(&self) -> &Path {
+        &self.command
+    }
+
+    pub fn args(&self) -> Vec<&OsStr> {
+        self.args.iter().map(OsString::as_os_str).collect()
+    }
+}
+
+/// Sent by the jail once it only has to exec.
+const READY: &[u8] = b"ready";
+
+/// What is sent over to the jail, the standard streams follow as file descriptors.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct JobRequest {
+    command: PathBuf,
+    args: Vec<OsString>,
+}
+
+impl JobRequest {
+    pub(crate) fn command(&self) -> &Path {
+        &self.command
+    }
+
+    pub(crate) fn args(&self) -> Vec<&OsStr> {
+        self.args.iter().map(OsString::as_os_str).collect()
+    }
+}
+
+/// Jails sharing a configuration, prepared ahead of time.
+///
+/// The command, arguments and redirects of the configuration are replaced by the ones of each
+/// [`Job`], and its instance name is ignored: every jail gets its own cgroup instance.
+#[allow(missing_debug_implementations)]
+pub struct Pool {
+    config: Config,
+    size: usize,
+    jails: VecDeque<(JailHandle, File)>,
+}
+
+impl Pool {
+    /// Prepares `size` jails. The ones used are replaced on the next [`Pool::run`], or earlier
+    /// with [`Pool::fill`].
+    pub fn new(config: Config, size: usize) -> Result<Self> {
+        let mut pool = Self {
+            config,
+            size,
+            jails: VecDeque::with_capacity(size),
+        };
+        pool.fill()?;
+        Ok(pool)
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Runs `job` in one of the prepared jails, after replacing the ones used before.
+    pub fn run(&mut self, job: Job) -> Result<JailHandle> {
+        self.fill()?;
+        let (handle, socket) = match self.jails.pop_front() {
+            Some(jail) => jail,
+            None => self.prepare()?,
+        };
+
+        let request = JobRequest {
+            command: job.command,
+            args: job.args,
+        };
+        let data = bincode::serialize(&request).map_err(|err| FFIError::MessageError {
+            error: err.to_string(),
+        })?;
+        ffi::send_with_files(&socket, &data, &[&job.stdin, &job.stdout, &job.stderr])?;
+        Ok(handle)
+    }
+
+    /// Prepares jails until there are `size` of them, so the next [`Pool::run`] does not
+    /// have to. Failing here leaves the running jobs alone.
+    pub fn fill(&mut self) -> Result<()> {
+        while self.jails.len() < self.size {
+            let jail = self.prepare()?;
+            self.jails.push_back(jail);
+        }
+        Ok(())
+    }
+
+    fn prepare(&self) -> Result<(JailHandle, File)> {
+        let (socket, jail_socket) = ffi::socket_pair()?;
+        let instance = cgroups::Instance::new(self.config.controller_path(), None);
+        let handle = ::spawn(&self.config, instance, Some(jail_socket))?;
+        Ok((handle, socket))
+    }
+}
+
+/// Passes a job on to the jail once it is ready, as the supervisor stands between the pool and
+/// the jail. `before_start` runs after the jail is done with its setup but before the job starts.
+pub(crate) fn forward_job<F>(from: &File, to: &File, before_start: F) -> Result<()>
+where
+    F: FnOnce() -> Result<()>,
+{
+    let (data, files) = ffi::receive_with_files(from)?;
+    if data.is_empty() {
+        return Err(FFIError::MessageError {
+            error: "the pool is gone".into(),
+        }
+        .into());
+    }
+    let (ready, _) = ffi::receive_with_files(to)?;
+    if ready.is_empty() {
+        // The jail is gone, it left its error behind for the supervisor
+        return Ok(());
+    }
+
+    before_start()?;
+    ffi::send_with_files(to, &data, &files.iter().collect::<Vec<_>>())?;
+    Ok(())
+}
+
+/// Waits for a job inside the jail and redirects the standard streams to its files.
+pub(crate) fn start_job(socket: &File) -> StdResult<JobRequest, FFIError> {
+    ffi::send_with_files(socket, READY, &[])?;
+    let (data, files) = ffi::receive_with_files(socket)?;
+    let request = bincode::deserialize(&data).map_err(|err| FFIError::MessageError {
+        error: err.to_string(),
+    })?;
+
+    match *files.as_slice() {
+        [ref stdin, ref stdout, ref stderr] => {
+            ffi::redirect_fd_to_file(ffi::STDIN, stdin)?;
+            ffi::redirect_fd_to_file(ffi::STDOUT, stdout)?;
+            ffi::redirect_fd_to_file(ffi::STDERR, stderr)?;
+            Ok(request)
+        }
+        _ => Err(FFIError::MessageError {
+            error: format!("expected 3 file descriptors, got {}", files.len()),
+        }),
+    }
+}
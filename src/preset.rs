@@ -0,0 +1,347 @@
+//! What the runtimes of the usual languages need inside the jail.
+//!
+//! A [`Preset`] is a fragment of a configuration: the paths of the runtime mounted (read only)
+//! into the new root, its environment and a pids limit fitting its threads. It is merged into a
+//! configuration with [`Preset::apply`], where anything the configuration sets wins.
+//!
+//! Managed runtimes reserve memory before running anything (the JVM most of all), which the
+//! memory cgroup charges to the program. The preset passes the memory limit on to the runtime
+//! (`-Xmx`, `GOMEMLIMIT`) and can measure what an empty program takes, so that baseline is left
+//! out of the usage with [`Preset::with_memory_baseline`].
+
+use std::env;
+use std::ffi::OsString;
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use config::{
+    ClearUsage, Config, DetailedUsage, Environment, Interactive, Limits, Mount, MountOptions,
+    SpaceUsage, SwapRedirects,
+};
+use errors::{PresetError, Result};
+use run_info::RunInfoResult;
+use JailHandle;
+
+/// The shared libraries every dynamically linked program needs.
+const LIBRARY_PATHS: &[&str] = &["/lib", "/lib64", "/usr/lib", "/usr/lib64"];
+const PATH: &str = "/usr/bin:/bin";
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum Language {
+    C,
+    Cpp,
+    Java,
+    Python,
+    Rust,
+    Go,
+}
+
+impl Language {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::C => "c",
+            Self::Cpp => "cpp",
+            Self::Java => "java",
+            Self::Python => "python",
+            Self::Rust => "rust",
+            Self::Go => "go",
+        }
+    }
+}
+
+impl Display for Language {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Language {
+    type Err = String;
+
+    fn from_str(string: &str) -> std::result::Result<Self, Self::Err> {
+        match string {
+            "c" => Ok(Self::C),
+            "cpp" | "c++" => Ok(Self::Cpp),
+            "java" => Ok(Self::Java),
+            "python" => Ok(Self::Python),
+            "rust" => Ok(Self::Rust),
+            "go" => Ok(Self::Go),
+            _ => Err(format!("Unknown language: {}", string)),
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Preset {
+    language: Language,
+    mounts: Vec<Mount>,
+    environment: Vec<(String, String)>,
+    pids: usize,
+    memory_baseline: Option<SpaceUsage>,
+}
+
+impl Preset {
+    pub fn new(
+        language: Language,
+        mounts: Vec<Mount>,
+        environment: Vec<(String, String)>,
+        pids: usize,
+        memory_baseline: Option<SpaceUsage>,
+    ) -> Self {
+        Self {
+            language,
+            mounts,
+            environment,
+            pids,
+            memory_baseline,
+        }
+    }
+
+    /// The preset of `language`, with the runtime paths found on this machine.
+    pub fn for_language(language: Language) -> Self {
+        let mut paths: Vec<PathBuf> = LIBRARY_PATHS.iter().map(PathBuf::from).collect();
+        let mut environment = vec![("PATH".to_owned(), PATH.to_owned())];
+        let pids = match language {
+            Language::C | Language::Cpp | Language::Rust => 50,
+            Language::Python => {
+                // The interpreter, the standard library is in /usr/lib
+                paths.push(PathBuf::from("/usr/bin"));
+                paths.push(PathBuf::from("/usr/local/lib"));
+                environment.push(("PYTHONDONTWRITEBYTECODE".to_owned(), "1".to_owned()));
+                50
+            }
+            Language::Go => {
+                // One thread running goroutines, the runtime still starts a few more
+                environment.push(("GOMAXPROCS".to_owned(), "1".to_owned()));
+                64
+            }
+            Language::Java => {
+                // /usr/bin/java is linked to the JDK through /etc/alternatives
+                paths.push(PathBuf::from("/usr/bin"));
+                paths.push(PathBuf::from("/etc/alternatives"));
+                // The configuration of Debian's JDKs is linked from /etc
+                paths.extend(
+                    fs::read_dir("/etc")
+                        .into_iter()
+                        .flatten()
+                        .filter_map(|entry| entry.ok())
+                        .map(|entry| entry.path())
+                        .filter(|path| path.to_string_lossy().starts_with("/etc/java")),
+                );
+                if let Some(java_home) = java_home() {
+                    environment[0].1 = format!("{}/bin:{}", java_home.display(), PATH);
+                    environment.push((
+                        "JAVA_HOME".to_owned(),
+                        java_home.to_string_lossy().into_owned(),
+                    ));
+                    paths.push(java_home);
+                }
+                // No shared memory performance counters (which need a writable /tmp) and a
+                // single garbage collector thread
+                environment.push((
+                    "JAVA_TOOL_OPTIONS".to_owned(),
+                    "-XX:-UsePerfData -XX:+UseSerialGC".to_owned(),
+                ));
+                // The JVM starts plenty of threads (compiler, garbage collector, signals)
+                256
+            }
+        };
+
+        let mut mount_options = MountOptions::default();
+        mount_options.set_exec(true);
+        let mut mounts: Vec<Mount> = Vec::new();
+        for path in paths {
+            // Only what exists here, once, and not inside something mounted already
+            if !path.exists() || mounts.iter().any(|mount| path.starts_with(mount.source())) {
+                continue;
+            }
+            mounts.push(Mount::new(path.clone(), path, mount_options));
+        }
+
+        Self::new(language, mounts, environment, pids, None)
+    }
+
+    pub fn language(&self) -> Language {
+        self.language
+    }
+
+    pub fn mounts(&self) -> &[Mount] {
+        &self.mounts
+    }
+
+    pub fn environment(&self) -> &[(String, String)] {
+        &self.environment
+    }
+
+    pub fn pids(&self) -> usize {
+        self.pids
+    }
+
+    pub fn memory_baseline(&self) -> Option<SpaceUsage> {
+        self.memory_baseline
+    }
+
+    /// The same preset, leaving `memory_baseline` out of the memory usage of the configurations
+    /// it is applied to.
+    pub fn with_memory_baseline(self, memory_baseline: SpaceUsage) -> Self {
+        Self {
+            memory_baseline: Some(memory_baseline),
+            ..self
+        }
+    }
+
+    /// Measures the memory the runtime takes by itself, running `command` (an empty program)
+    /// with `args` instead of the command of `config`, with this preset applied.
+    ///
+    /// Everything else (the limits included, the flags of the runtime depend on them) is the
+    /// same as for `config`, so the baseline matches what the runtime reserves for it.
+    pub fn measure_memory_baseline(
+        &self,
+        config: &Config,
+        command: PathBuf,
+        args: Vec<OsString>,
+    ) -> Result<SpaceUsage> {
+        let empty_program = config
+            .clone()
+            .with_command(command, args)
+            .with_redirect_stdin(Some(PathBuf::from("/dev/null")))
+            .with_redirect_stdout(Some(PathBuf::from("/dev/null")))
+            .with_redirect_stderr(Some(PathBuf::from("/dev/null")))
+            .with_instance_name(None)
+            .with_swap_redirects(SwapRedirects::No)
+            .with_clear_usage(ClearUsage::Yes)
+            .with_interactive(Interactive::No)
+            .with_detailed_usage(DetailedUsage::No)
+            .with_memory_baseline(None);
+        let preset = Self {
+            memory_baseline: None,
+            ..self.clone()
+        };
+
+        let run_info = ::spawn_jail(&preset.apply(&empty_program)).and_then(JailHandle::wait)?;
+        match *run_info.result() {
+            RunInfoResult::Success(()) => Ok(run_info.usage().memory()),
+            ref result => Err(PresetError::BaselineRunFailed {
+                result: result.to_string(),
+            }
+            .into()),
+        }
+    }
+
+    /// The environment of the preset, with the flags passing the limits on to the runtime.
+    fn runtime_environment(&self, limits: Limits) -> Vec<(String, String)> {
+        let mut environment = self.environment.clone();
+        match self.language {
+            Language::Java => {
+                let mut flags = Vec::new();
+                if let Some(memory) = limits.memory() {
+                    // The baseline is not part of the limit, the heap can take all of it
+                    flags.push(format!("-Xmx{}k", memory.as_bytes() / 1024));
+                }
+                if let Some(stack) = limits.stack() {
+                    flags.push(format!("-Xss{}k", stack.as_bytes() / 1024));
+                }
+                for (name, value) in &mut environment {
+                    if name == "JAVA_TOOL_OPTIONS" {
+                        for flag in &flags {
+                            value.push(' ');
+                            value.push_str(flag);
+                        }
+                    }
+                }
+            }
+            Language::Go => {
+                if let Some(memory) = limits.memory() {
+                    environment.push(("GOMEMLIMIT".to_owned(), memory.as_bytes().to_string()));
+                }
+            }
+            Language::C | Language::Cpp | Language::Python | Language::Rust => {}
+        }
+        environment
+    }
+
+    /// Adds the preset to `config`. The mounts, environment variables (forwarded ones too),
+    /// pids limit and memory baseline set by `config` win over the ones of the preset.
+    ///
+    /// The memory and stack limits of `config` are passed on to managed runtimes, through
+    /// `JAVA_TOOL_OPTIONS` for Java and `GOMEMLIMIT` for Go.
+    pub fn apply(&self, config: &Config) -> Config {
+        let mut mounts: Vec<Mount> = self
+            .mounts
+            .iter()
+            .filter(|mount| {
+                config
+                    .mounts()
+                    .iter()
+                    .all(|other| other.destination() != mount.destination())
+            })
+            .cloned()
+            .collect();
+        mounts.extend_from_slice(config.mounts());
+
+        // The forwarded variables are set by the configuration just like a list of them, the
+        // ones that are not UTF-8 can not be part of that list
+        let variables: Vec<(String, String)> = match *config.environment() {
+            Environment::Forward => env::vars_os()
+                .filter_map(
+                    |(name, value)| match (name.into_string(), value.into_string()) {
+                        (Ok(name), Ok(value)) => Some((name, value)),
+                        _ => None,
+                    },
+                )
+                .collect(),
+            Environment::EnvList(ref variables) => variables.clone(),
+        };
+        let mut environment: Vec<(String, String)> = self
+            .runtime_environment(config.limits())
+            .into_iter()
+            .filter(|(name, _)| variables.iter().all(|(other, _)| other != name))
+            .collect();
+        environment.extend(variables);
+
+        let limits = config.limits();
+        let limits = Limits::new(
+            limits.wall_time(),
+            limits.user_time(),
+            limits.memory(),
+            limits.stack(),
+            limits.pids().or(Some(self.pids)),
+            limits.idle_time(),
+            limits.soft(),
+            limits.cpu_quota(),
+            limits.io(),
+        );
+
+        config
+            .clone()
+            .with_limits(limits)
+            .with_mounts(mounts)
+            .with_environment(Environment::EnvList(environment))
+            .with_memory_baseline(config.memory_baseline().or(self.memory_baseline))
+    }
+}
+
+/// The JDK from `JAVA_HOME`, or the default one of the system.
+fn java_home() -> Option<PathBuf> {
+    let has_java = |path: &Path| path.join("bin/java").is_file();
+    if let Some(java_home) = env::var_os("JAVA_HOME").map(PathBuf::from) {
+        if has_java(&java_home) {
+            return Some(java_home);
+        }
+    }
+
+    let default = Path::new("/usr/lib/jvm/default-java");
+    if has_java(default) {
+        return fs::canonicalize(default).ok();
+    }
+    let mut jdks: Vec<PathBuf> = fs::read_dir("/usr/lib/jvm")
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| has_java(path))
+        .collect();
+    jdks.sort();
+    jdks.into_iter().last()
+}
@@ -0,0 +1,256 @@
+//! Per process breakdown of the usage, sampled from `/proc`.
+//!
+//! The cgroups only give totals. Every time the usage is sampled the tracker also reads the
+//! processes in the jail's cgroup, keeping the last values it saw for each of them (and for
+//! each thread). Processes that already exited but were not reaped yet still have their exit
+//! status in `/proc`.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use cgroups;
+use config::{ControllerPath, SpaceUsage};
+use ffi;
+use libc;
+use run_info::{ExitStatus, ProcessUsage, ThreadUsage};
+
+#[derive(Debug)]
+struct ProcessSample {
+    // Keyed by the pid in the supervisor's namespace, reported with the one inside the jail
+    ns_pid: u32,
+    parent: libc::pid_t,
+    command_line: Vec<String>,
+    cpu_time: Duration,
+    peak_memory: SpaceUsage,
+    exit_status: Option<ExitStatus>,
+    // tid -> (ns tid, cpu time)
+    threads: BTreeMap<libc::pid_t, (u32, Duration)>,
+    gone: bool,
+}
+
+/// The fields of `/proc/<pid>/stat` used, see proc(5)
+#[derive(Debug)]
+struct Stat {
+    state: char,
+    parent: libc::pid_t,
+    cpu_time: Duration,
+    exit_code: Option<libc::c_int>,
+}
+
+#[derive(Debug)]
+pub(crate) struct ProcessTracker<'a> {
+    controller_path: &'a ControllerPath,
+    instance_name: &'a OsStr,
+    clock_ticks: u64,
+    processes: RefCell<BTreeMap<libc::pid_t, ProcessSample>>,
+}
+
+impl<'a> ProcessTracker<'a> {
+    pub(crate) fn new(controller_path: &'a ControllerPath, instance_name: &'a OsStr) -> Self {
+        Self {
+            controller_path,
+            instance_name,
+            clock_ticks: ffi::clock_ticks_per_second(),
+            processes: RefCell::new(BTreeMap::new()),
+        }
+    }
+
+    /// Records the current state of every process in the jail.
+    ///
+    /// Processes come and go while reading them, so what can not be read is skipped.
+    pub(crate) fn sample(&self) {
+        let mut processes = self.processes.borrow_mut();
+        let mut pids = cgroups::get_processes(self.controller_path, self.instance_name);
+        // Exited processes leave the cgroup, but might still be zombies
+        pids.extend(
+            processes
+                .iter()
+                .filter(|(_, process)| !process.gone && process.exit_status.is_none())
+                .map(|(&pid, _)| pid),
+        );
+        pids.sort();
+        pids.dedup();
+
+        for pid in pids {
+            let path = PathBuf::from(format!("/proc/{}", pid));
+            let stat = self.read_stat(&path.join("stat"));
+            if stat.is_none() {
+                if let Some(process) = processes.get_mut(&pid) {
+                    process.gone = true;
+                }
+            }
+            let stat = match stat {
+                Some(stat) => stat,
+                None => continue,
+            };
+            let status = fs::read_to_string(path.join("status")).unwrap_or_default();
+
+            let process = processes.entry(pid).or_insert_with(|| ProcessSample {
+                ns_pid: ns_pid(&status).unwrap_or(pid as u32),
+                parent: stat.parent,
+                command_line: Vec::new(),
+                cpu_time: Duration::from_secs(0),
+                peak_memory: SpaceUsage::from_bytes(0),
+                exit_status: None,
+                threads: BTreeMap::new(),
+                gone: false,
+            });
+
+            process.cpu_time = stat.cpu_time;
+            if stat.state == 'Z' {
+                process.exit_status = stat
+                    .exit_code
+                    .and_then(|exit_code| ffi::to_exit_status(exit_code).ok());
+                continue;
+            }
+
+            // Empty once the process starts exiting, keep the last one
+            let command_line = command_line(&path);
+            if !command_line.is_empty() {
+                process.command_line = command_line;
+            }
+            if let Some(peak_memory) = status_value(&status, "VmHWM") {
+                // In kibibytes
+                process.peak_memory = SpaceUsage::from_kibibytes(peak_memory);
+            }
+
+            let tasks = match fs::read_dir(path.join("task")) {
+                Ok(tasks) => tasks,
+                Err(_) => continue,
+            };
+            for task in tasks.filter_map(|task| task.ok()) {
+                let tid: libc::pid_t = match task.file_name().to_string_lossy().parse() {
+                    Ok(tid) => tid,
+                    Err(_) => continue,
+                };
+                let stat = match self.read_stat(&task.path().join("stat")) {
+                    Some(stat) => stat,
+                    None => continue,
+                };
+                let ns_tid = process.threads.get(&tid).map_or_else(
+                    || {
+                        let status = fs::read_to_string(task.path().join("status"));
+                        ns_pid(&status.unwrap_or_default()).unwrap_or(tid as u32)
+                    },
+                    |&(ns_tid, _)| ns_tid,
+                );
+                let _ = process.threads.insert(tid, (ns_tid, stat.cpu_time));
+            }
+        }
+    }
+
+    /// The recorded processes as a tree, rooted at the processes whose parent is not in the jail
+    /// (the sandboxed process, unless some of the samples missed its parent).
+    pub(crate) fn into_tree(self, exit_status: ExitStatus) -> Vec<ProcessUsage> {
+        let mut processes = self.processes.into_inner();
+        let supervisor = ffi::getpid();
+        for process in processes.values_mut() {
+            // The supervisor reaps the sandboxed process itself, it is never seen as a zombie
+            if process.parent == supervisor {
+                process.exit_status = Some(exit_status);
+            }
+        }
+
+        let roots: Vec<libc::pid_t> = processes
+            .iter()
+            .filter(|(_, process)| !processes.contains_key(&process.parent))
+            .map(|(&pid, _)| pid)
+            .collect();
+        let mut visited = BTreeSet::new();
+        roots
+            .into_iter()
+            .map(|pid| build_tree(&processes, pid, &mut visited))
+            .collect()
+    }
+
+    fn read_stat(&self, path: &Path) -> Option<Stat> {
+        let stat = fs::read_to_string(path).ok()?;
+        // The command name is in parentheses and can contain anything
+        let fields: Vec<&str> = stat[stat.rfind(')')? + 1..].split_whitespace().collect();
+        let field = |index: usize| -> Option<u64> { fields.get(index - 3)?.parse().ok() };
+
+        let ticks = field(14)? + field(15)?;
+        Some(Stat {
+            state: fields.first()?.chars().next()?,
+            parent: fields.get(1)?.parse().ok()?,
+            cpu_time: Duration::from_nanos(ticks * 1_000_000_000 / self.clock_ticks),
+            exit_code: fields.get(52 - 3).and_then(|code| code.parse().ok()),
+        })
+    }
+}
+
+/// A pid reused while sampling can make a process look like its own ancestor, so every process
+/// is only added to the tree once.
+fn build_tree(
+    processes: &BTreeMap<libc::pid_t, ProcessSample>,
+    pid: libc::pid_t,
+    visited: &mut BTreeSet<libc::pid_t>,
+) -> ProcessUsage {
+    let _ = visited.insert(pid);
+    let process = &processes[&pid];
+    let children: Vec<libc::pid_t> = processes
+        .iter()
+        .filter(|&(child, sample)| sample.parent == pid && !visited.contains(child))
+        .map(|(&child, _)| child)
+        .collect();
+    let mut child_usages = Vec::with_capacity(children.len());
+    for child in children {
+        // Already added below one of its siblings
+        if !visited.contains(&child) {
+            child_usages.push(build_tree(processes, child, visited));
+        }
+    }
+    let threads = process
+        .threads
+        .values()
+        .map(|&(tid, cpu_time)| ThreadUsage::new(tid, cpu_time))
+        .collect();
+
+    ProcessUsage::new(
+        process.ns_pid,
+        process.command_line.clone(),
+        process.cpu_time,
+        process.peak_memory,
+        process.exit_status,
+        threads,
+        child_usages,
+    )
+}
+
+fn command_line(path: &Path) -> Vec<String> {
+    fs::read(path.join("cmdline"))
+        .map(|cmdline| match cmdline.split_last() {
+            // Every argument ends with a NUL
+            Some((0, cmdline)) => cmdline
+                .split(|&byte| byte == 0)
+                .map(|arg| String::from_utf8_lossy(arg).into_owned())
+                .collect(),
+            _ => Vec::new(),
+        })
+        .unwrap_or_default()
+}
+
+fn status_value(status: &str, key: &str) -> Option<u64> {
+    status
+        .lines()
+        .find(|line| line.starts_with(key) && line[key.len()..].starts_with(':'))?
+        .split_whitespace()
+        .nth(1)?
+        .parse()
+        .ok()
+}
+
+/// The pid inside the innermost namespace, the last one on the `NSpid` line.
+fn ns_pid(status: &str) -> Option<u32> {
+    status
+        .lines()
+        .find(|line| line.starts_with("NSpid:"))?
+        .split_whitespace()
+        .last()?
+        .parse()
+        .ok()
+}
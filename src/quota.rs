@@ -0,0 +1,271 @@
+//! Size limited writable mounts.
+//!
+//! A writable directory mount with a size limit is not bound into the jail directly. The
+//! supervisor mounts a tmpfs of that size and binds it into the jail instead. Every jail starts
+//! with a fresh copy of the directory on the tmpfs, and once the supervisor is done the
+//! contents the last jail left there are copied back over the directory. Files the jail removed
+//! are not removed from the directory.
+//!
+//! The tmpfs is controlled by the jail, so copying it back never follows symlinks. Only regular
+//! files and directories are copied back, files are created anew instead of being written
+//! through whatever was in their place and lose their setuid and setgid bits.
+
+use std::env;
+use std::fs::{self, OpenOptions, Permissions};
+use std::io;
+use std::os::unix::fs::{symlink, OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use tempfile::{Builder, TempDir};
+
+use config::{Mount, SpaceUsage};
+use errors::{DiskQuotaError, Errno, Result};
+use ffi;
+use libc;
+use run_info::RunUsage;
+
+/// A size limited mount, the tmpfs standing in for `source`.
+#[derive(Debug)]
+struct Quota {
+    source: PathBuf,
+    mount_point: TempDir,
+    /// The space taken by the copy of `source`, which was not written by the jail
+    copied_space: u64,
+}
+
+#[derive(Debug)]
+pub(crate) struct QuotaMounts {
+    mounts: Vec<Mount>,
+    quotas: Vec<Quota>,
+}
+
+impl QuotaMounts {
+    /// Sets up the size limited ones among `mounts`. Must be done in the supervisor's mount
+    /// namespace, so the tmpfs mounts disappear with it.
+    pub(crate) fn new(mounts: &[Mount]) -> Result<Self> {
+        let mut quota_mounts = Self {
+            mounts: Vec::with_capacity(mounts.len()),
+            quotas: Vec::new(),
+        };
+
+        for mount in mounts {
+            let mount_options = mount.mount_options();
+            let size_limit = match mount_options.size_limit() {
+                Some(size_limit) if !mount_options.read_only() => size_limit,
+                _ => {
+                    quota_mounts.mounts.push(mount.clone());
+                    continue;
+                }
+            };
+
+            if !mount.source().is_dir() {
+                return Err(DiskQuotaError::NotADirectory {
+                    path: mount.source().to_path_buf(),
+                }
+                .into());
+            }
+
+            let mount_point = Builder::new()
+                .prefix("ia-sandbox-mount-")
+                .tempdir()
+                .map_err(|err| DiskQuotaError::CreateDirError {
+                    path: env::temp_dir(),
+                    errno: Errno::from(&err),
+                })?;
+            ffi::mount_tmpfs(mount_point.path(), size_limit)?;
+            let path = mount_point.path().to_path_buf();
+            quota_mounts.quotas.push(Quota {
+                source: mount.source().to_path_buf(),
+                mount_point,
+                copied_space: 0,
+            });
+
+            quota_mounts.mounts.push(Mount::new(
+                path,
+                mount.destination().to_path_buf(),
+                mount_options,
+            ));
+        }
+        Ok(quota_mounts)
+    }
+
+    /// What the jail should mount, with the size limited sources replaced by their tmpfs.
+    pub(crate) fn mounts(&self) -> &[Mount] {
+        &self.mounts
+    }
+
+    /// Empties the tmpfs mounts and copies their sources in again, so a jail never sees what the
+    /// previous one left.
+    pub(crate) fn reset(&mut self) -> Result<()> {
+        for quota in &mut self.quotas {
+            let mount_point = quota.mount_point.path();
+            remove_contents(mount_point)?;
+            copy_contents(&quota.source, mount_point)?;
+            copy_permissions(&quota.source, mount_point)?;
+            quota.copied_space = ffi::used_space(mount_point)?.as_bytes();
+        }
+        Ok(())
+    }
+
+    /// Adds the space the jail took on the tmpfs mounts to `usage`.
+    pub(crate) fn record_usage(&self, usage: RunUsage) -> Result<RunUsage> {
+        let mut disk_usage = 0;
+        for quota in &self.quotas {
+            let used_space = ffi::used_space(quota.mount_point.path())?.as_bytes();
+            disk_usage += used_space.saturating_sub(quota.copied_space);
+        }
+
+        Ok(RunUsage::new(
+            usage.user_time(),
+            usage.wall_time(),
+            usage.memory(),
+            usage.idle_time(),
+            usage.throttling(),
+            usage.io(),
+            SpaceUsage::from_bytes(disk_usage),
+        ))
+    }
+
+    /// Copies what the last jail left on the tmpfs mounts back to their sources.
+    pub(crate) fn copy_back(&self) -> Result<()> {
+        for quota in &self.quotas {
+            copy_back(quota.mount_point.path(), &quota.source)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for QuotaMounts {
+    fn drop(&mut self) {
+        for quota in &self.quotas {
+            // Nothing to do about it, the mount dies with the namespace anyway. The directory is
+            // removed when the `TempDir` is dropped.
+            let _ = ffi::umount(quota.mount_point.path());
+        }
+    }
+}
+
+fn copy_error(from: &Path, to: &Path, err: &io::Error) -> DiskQuotaError {
+    DiskQuotaError::CopyError {
+        from: from.to_path_buf(),
+        to: to.to_path_buf(),
+        errno: Errno::from(err),
+    }
+}
+
+/// Removes `path`, without following it if it is a symlink.
+fn remove(path: &Path) -> io::Result<()> {
+    if fs::symlink_metadata(path)?.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
+/// Removes everything inside `path`.
+fn remove_contents(path: &Path) -> Result<()> {
+    let remove_error = |err: io::Error| DiskQuotaError::RemoveError {
+        path: path.to_path_buf(),
+        errno: Errno::from(&err),
+    };
+
+    for entry in fs::read_dir(path).map_err(remove_error)? {
+        remove(&entry.map_err(remove_error)?.path()).map_err(remove_error)?;
+    }
+    Ok(())
+}
+
+/// Copies everything inside `from` into `to`, overwriting what is already there. Only used from
+/// the host directory to the tmpfs, see [`copy_back`] for the other way around.
+fn copy_contents(from: &Path, to: &Path) -> Result<()> {
+    let copy_error = |err: io::Error| copy_error(from, to, &err);
+
+    for entry in fs::read_dir(from).map_err(copy_error)? {
+        let entry = entry.map_err(copy_error)?;
+        let source = entry.path();
+        let destination = to.join(entry.file_name());
+        let file_type = entry.file_type().map_err(copy_error)?;
+
+        if file_type.is_dir() {
+            if !destination.is_dir() {
+                fs::create_dir(&destination).map_err(copy_error)?;
+            }
+            copy_contents(&source, &destination)?;
+            copy_permissions(&source, &destination)?;
+        } else if file_type.is_symlink() {
+            let target = fs::read_link(&source).map_err(copy_error)?;
+            if fs::symlink_metadata(&destination).is_ok() {
+                fs::remove_file(&destination).map_err(copy_error)?;
+            }
+            symlink(target, &destination).map_err(copy_error)?;
+        } else if file_type.is_file() {
+            let _ = fs::copy(&source, &destination).map_err(copy_error)?;
+        }
+    }
+    Ok(())
+}
+
+fn copy_permissions(from: &Path, to: &Path) -> Result<()> {
+    fs::metadata(from)
+        .and_then(|metadata| fs::set_permissions(to, metadata.permissions()))
+        .map_err(|err| copy_error(from, to, &err).into())
+}
+
+/// The permissions of a copied back file or directory, without setuid and setgid.
+fn safe_permissions(path: &Path) -> io::Result<Permissions> {
+    let mode = fs::symlink_metadata(path)?.permissions().mode();
+    Ok(Permissions::from_mode(
+        mode & !(libc::S_ISUID | libc::S_ISGID) & 0o7777,
+    ))
+}
+
+/// Copies the regular files and directories inside `from` (on the tmpfs) into `to` (on the
+/// host), replacing what is in their place. Nothing is followed on either side.
+fn copy_back(from: &Path, to: &Path) -> Result<()> {
+    let copy_error = |err: io::Error| copy_error(from, to, &err);
+
+    for entry in fs::read_dir(from).map_err(copy_error)? {
+        let entry = entry.map_err(copy_error)?;
+        let source = entry.path();
+        let destination = to.join(entry.file_name());
+        let file_type = entry.file_type().map_err(copy_error)?;
+        let existing = fs::symlink_metadata(&destination).ok();
+
+        if file_type.is_dir() {
+            match existing {
+                Some(ref metadata) if metadata.is_dir() => {}
+                Some(_) => {
+                    remove(&destination).map_err(copy_error)?;
+                    fs::create_dir(&destination).map_err(copy_error)?;
+                }
+                None => fs::create_dir(&destination).map_err(copy_error)?,
+            }
+            copy_back(&source, &destination)?;
+            safe_permissions(&source)
+                .and_then(|permissions| fs::set_permissions(&destination, permissions))
+                .map_err(copy_error)?;
+        } else if file_type.is_file() {
+            if existing.is_some() {
+                remove(&destination).map_err(copy_error)?;
+            }
+            let mut source_file = OpenOptions::new()
+                .read(true)
+                .custom_flags(libc::O_NOFOLLOW)
+                .open(&source)
+                .map_err(copy_error)?;
+            // Created anew (`create_new` is `O_EXCL`), so nothing that took its place is written
+            let mut destination_file = OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .custom_flags(libc::O_NOFOLLOW)
+                .mode(0o600)
+                .open(&destination)
+                .map_err(copy_error)?;
+            let _ = io::copy(&mut source_file, &mut destination_file).map_err(copy_error)?;
+            safe_permissions(&source)
+                .and_then(|permissions| destination_file.set_permissions(permissions))
+                .map_err(copy_error)?;
+        }
+    }
+    Ok(())
+}
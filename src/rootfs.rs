@@ -0,0 +1,596 @@
+//! Building a minimal root filesystem for a dynamically linked executable.
+//!
+//! The ELF program and dynamic headers are parsed directly (no `ldd`), collecting the ELF
+//! interpreter and the closure of `DT_NEEDED` libraries. The result can be bind mounted inside
+//! the new root (see [`mounts`]) or copied into it (see [`populate`]).
+
+use std::collections::{BTreeSet, VecDeque};
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::result;
+
+use config::{Mount, MountOptions};
+use errors::{Errno, RootfsError};
+
+type Result<T> = result::Result<T, RootfsError>;
+
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+const ELF_CLASS_32: u8 = 1;
+const ELF_CLASS_64: u8 = 2;
+const ELF_DATA_LSB: u8 = 1;
+const ELF_DATA_MSB: u8 = 2;
+
+const PT_LOAD: u32 = 1;
+const PT_DYNAMIC: u32 = 2;
+const PT_INTERP: u32 = 3;
+
+const DT_NULL: u64 = 0;
+const DT_NEEDED: u64 = 1;
+const DT_STRTAB: u64 = 5;
+const DT_RPATH: u64 = 15;
+const DT_RUNPATH: u64 = 29;
+
+/// Strings (library names and search paths) are paths, so no longer than `PATH_MAX`.
+const MAX_STRING_LENGTH: u64 = 4096;
+
+const LD_SO_CONF: &str = "/etc/ld.so.conf";
+const DEFAULT_LIBRARY_DIRS_32: &[&str] = &["/lib", "/usr/lib"];
+const DEFAULT_LIBRARY_DIRS_64: &[&str] = &["/lib64", "/usr/lib64", "/lib", "/usr/lib"];
+
+struct ElfReader {
+    path: PathBuf,
+    file: File,
+    /// Offsets and sizes from the headers are checked against it before reading anything
+    len: u64,
+    class: u8,
+    data: u8,
+    machine: u16,
+}
+
+struct ProgramHeader {
+    kind: u32,
+    offset: u64,
+    vaddr: u64,
+    filesz: u64,
+}
+
+/// What we care about from an ELF file
+struct ElfInfo {
+    class: u8,
+    machine: u16,
+    interpreter: Option<PathBuf>,
+    needed: Vec<String>,
+    rpath: Vec<String>,
+    runpath: Vec<String>,
+}
+
+impl ElfReader {
+    fn open(path: &Path) -> Result<Self> {
+        let open_error = |err: io::Error| RootfsError::OpenElfError {
+            path: path.to_path_buf(),
+            errno: Errno::from(&err),
+        };
+        let mut file = File::open(path).map_err(open_error)?;
+        let len = file.metadata().map_err(open_error)?.len();
+
+        let mut ident = [0u8; 20];
+        file.read_exact(&mut ident)
+            .map_err(|_| invalid_elf(path, "file too short"))?;
+        if &ident[..4] != ELF_MAGIC {
+            return Err(invalid_elf(path, "bad magic number"));
+        }
+        let (class, data) = (ident[4], ident[5]);
+        if class != ELF_CLASS_32 && class != ELF_CLASS_64 {
+            return Err(invalid_elf(path, "unknown class"));
+        }
+        if data != ELF_DATA_LSB && data != ELF_DATA_MSB {
+            return Err(invalid_elf(path, "unknown data encoding"));
+        }
+
+        let mut reader = Self {
+            path: path.to_path_buf(),
+            file,
+            len,
+            class,
+            data,
+            machine: 0,
+        };
+        reader.machine = reader.u16_from(&ident[18..20]);
+        Ok(reader)
+    }
+
+    fn is_64(&self) -> bool {
+        self.class == ELF_CLASS_64
+    }
+
+    fn u16_from(&self, bytes: &[u8]) -> u16 {
+        let bytes = [bytes[0], bytes[1]];
+        if self.data == ELF_DATA_LSB {
+            u16::from_le_bytes(bytes)
+        } else {
+            u16::from_be_bytes(bytes)
+        }
+    }
+
+    fn u32_from(&self, bytes: &[u8]) -> u32 {
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        if self.data == ELF_DATA_LSB {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        }
+    }
+
+    fn u64_from(&self, bytes: &[u8]) -> u64 {
+        let mut buffer = [0u8; 8];
+        buffer.copy_from_slice(&bytes[..8]);
+        if self.data == ELF_DATA_LSB {
+            u64::from_le_bytes(buffer)
+        } else {
+            u64::from_be_bytes(buffer)
+        }
+    }
+
+    /// Reads an address sized word (4 bytes for 32 bit, 8 bytes for 64 bit files)
+    fn word_from(&self, bytes: &[u8]) -> u64 {
+        if self.is_64() {
+            self.u64_from(bytes)
+        } else {
+            u64::from(self.u32_from(bytes))
+        }
+    }
+
+    fn read_error(&self, err: &io::Error) -> RootfsError {
+        RootfsError::ReadElfError {
+            path: self.path.clone(),
+            errno: Errno::from(err),
+        }
+    }
+
+    fn read_at(&mut self, offset: u64, len: u64) -> Result<Vec<u8>> {
+        match offset.checked_add(len) {
+            Some(end) if end <= self.len => {}
+            _ => return Err(invalid_elf(&self.path, "read past the end of the file")),
+        }
+
+        let mut buffer = vec![0u8; len as usize];
+        let _ = self
+            .file
+            .seek(SeekFrom::Start(offset))
+            .map_err(|err| self.read_error(&err))?;
+        self.file
+            .read_exact(&mut buffer)
+            .map_err(|err| self.read_error(&err))?;
+        Ok(buffer)
+    }
+
+    fn read_string_at(&mut self, offset: u64) -> Result<String> {
+        if offset >= self.len {
+            return Err(invalid_elf(&self.path, "string past the end of the file"));
+        }
+
+        let mut string = Vec::new();
+        let _ = self
+            .file
+            .seek(SeekFrom::Start(offset))
+            .map_err(|err| self.read_error(&err))?;
+        let _ = BufReader::new((&mut self.file).take(MAX_STRING_LENGTH))
+            .read_until(0, &mut string)
+            .map_err(|err| self.read_error(&err))?;
+        if string.pop() != Some(0) {
+            return Err(invalid_elf(&self.path, "unterminated string"));
+        }
+        Ok(String::from_utf8_lossy(&string).into_owned())
+    }
+
+    fn program_headers(&mut self) -> Result<Vec<ProgramHeader>> {
+        let header = self.read_at(0, if self.is_64() { 64 } else { 52 })?;
+        let (phoff, phentsize, phnum) = if self.is_64() {
+            (
+                self.u64_from(&header[32..]),
+                self.u16_from(&header[54..]),
+                self.u16_from(&header[56..]),
+            )
+        } else {
+            (
+                u64::from(self.u32_from(&header[28..])),
+                self.u16_from(&header[42..]),
+                self.u16_from(&header[44..]),
+            )
+        };
+
+        let minimum_entry_size = if self.is_64() { 56 } else { 32 };
+        if phnum > 0 && usize::from(phentsize) < minimum_entry_size {
+            return Err(invalid_elf(&self.path, "program header entries too small"));
+        }
+
+        let table = self.read_at(phoff, u64::from(phentsize) * u64::from(phnum))?;
+        Ok(table
+            .chunks(usize::from(phentsize.max(1)))
+            .take(usize::from(phnum))
+            .map(|entry| {
+                if self.is_64() {
+                    ProgramHeader {
+                        kind: self.u32_from(&entry[0..]),
+                        offset: self.u64_from(&entry[8..]),
+                        vaddr: self.u64_from(&entry[16..]),
+                        filesz: self.u64_from(&entry[32..]),
+                    }
+                } else {
+                    ProgramHeader {
+                        kind: self.u32_from(&entry[0..]),
+                        offset: u64::from(self.u32_from(&entry[4..])),
+                        vaddr: u64::from(self.u32_from(&entry[8..])),
+                        filesz: u64::from(self.u32_from(&entry[16..])),
+                    }
+                }
+            })
+            .collect())
+    }
+
+    fn info(mut self) -> Result<ElfInfo> {
+        let program_headers = self.program_headers()?;
+
+        let interpreter = match program_headers
+            .iter()
+            .find(|header| header.kind == PT_INTERP)
+        {
+            Some(header) => Some(PathBuf::from(self.read_string_at(header.offset)?)),
+            None => None,
+        };
+
+        let mut info = ElfInfo {
+            class: self.class,
+            machine: self.machine,
+            interpreter,
+            needed: Vec::new(),
+            rpath: Vec::new(),
+            runpath: Vec::new(),
+        };
+
+        let dynamic = match program_headers
+            .iter()
+            .find(|header| header.kind == PT_DYNAMIC)
+        {
+            Some(header) => header,
+            None => return Ok(info), // statically linked
+        };
+
+        let entry_size = if self.is_64() { 16 } else { 8 };
+        let table = self.read_at(dynamic.offset, dynamic.filesz)?;
+        let mut entries = Vec::new();
+        let mut strtab = None;
+        for entry in table.chunks(entry_size) {
+            if entry.len() < entry_size {
+                break;
+            }
+            let tag = self.word_from(entry);
+            let value = self.word_from(&entry[entry_size / 2..]);
+            match tag {
+                DT_NULL => break,
+                DT_STRTAB => strtab = Some(value),
+                DT_NEEDED | DT_RPATH | DT_RUNPATH => entries.push((tag, value)),
+                _ => (),
+            }
+        }
+
+        if entries.is_empty() {
+            return Ok(info);
+        }
+
+        // DT_STRTAB is an address, find the loaded segment containing it to get the file offset
+        let strtab = strtab.ok_or_else(|| invalid_elf(&self.path, "missing DT_STRTAB"))?;
+        let strtab_offset = program_headers
+            .iter()
+            .filter(|header| header.kind == PT_LOAD)
+            .find(|header| match header.vaddr.checked_add(header.filesz) {
+                Some(end) => header.vaddr <= strtab && strtab < end,
+                None => false,
+            })
+            .and_then(|header| (strtab - header.vaddr).checked_add(header.offset))
+            .ok_or_else(|| invalid_elf(&self.path, "DT_STRTAB outside of loaded segments"))?;
+
+        for (tag, value) in entries {
+            let offset = strtab_offset
+                .checked_add(value)
+                .ok_or_else(|| invalid_elf(&self.path, "string outside of the file"))?;
+            let string = self.read_string_at(offset)?;
+            match tag {
+                DT_NEEDED => info.needed.push(string),
+                DT_RPATH => info.rpath.extend(string.split(':').map(str::to_owned)),
+                _ => info.runpath.extend(string.split(':').map(str::to_owned)),
+            }
+        }
+
+        Ok(info)
+    }
+}
+
+fn invalid_elf(path: &Path, error: &str) -> RootfsError {
+    RootfsError::InvalidElfError {
+        path: path.to_path_buf(),
+        error: error.into(),
+    }
+}
+
+/// Matches `name` against a pattern where `*` stands for any (possibly empty) string
+fn matches_glob(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or("");
+    if !name.starts_with(first) {
+        return false;
+    }
+
+    let mut rest = &name[first.len()..];
+    let parts: Vec<&str> = parts.collect();
+    for (index, part) in parts.iter().enumerate() {
+        if index == parts.len() - 1 {
+            return rest.ends_with(part);
+        }
+        match rest.find(part) {
+            Some(position) => rest = &rest[position + part.len()..],
+            None => return false,
+        }
+    }
+    rest.is_empty()
+}
+
+fn expand_include(pattern: &Path) -> Vec<PathBuf> {
+    let name = match pattern.file_name().and_then(OsStr::to_str) {
+        Some(name) if name.contains('*') => name,
+        _ => return vec![pattern.to_path_buf()],
+    };
+    let parent = pattern.parent().unwrap_or_else(|| Path::new("/"));
+
+    let mut paths: Vec<PathBuf> = fs::read_dir(parent)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| match path.file_name().and_then(OsStr::to_str) {
+                    Some(file_name) => matches_glob(name, file_name),
+                    None => false,
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    paths.sort();
+    paths
+}
+
+/// Reads the library directories from `ld.so.conf`, following `include` directives
+fn ld_so_conf_dirs(path: &Path, dirs: &mut Vec<PathBuf>, depth: usize) {
+    // Protect against include cycles
+    if depth > 8 {
+        return;
+    }
+
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(_) => return,
+    };
+
+    for line in BufReader::new(file).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() || line.starts_with("hwcap ") {
+            continue;
+        }
+
+        if let Some(patterns) = line.strip_prefix("include ") {
+            for pattern in patterns.split_whitespace() {
+                let pattern = path
+                    .parent()
+                    .unwrap_or_else(|| Path::new("/"))
+                    .join(pattern);
+                for included in expand_include(&pattern) {
+                    ld_so_conf_dirs(&included, dirs, depth + 1);
+                }
+            }
+        } else {
+            dirs.extend(
+                line.split(|c: char| c == ':' || c == ',' || c.is_whitespace())
+                    .filter(|dir| !dir.is_empty())
+                    .map(PathBuf::from),
+            );
+        }
+    }
+}
+
+struct Resolver {
+    system_dirs: Vec<PathBuf>,
+}
+
+impl Resolver {
+    fn new() -> Self {
+        let mut system_dirs = Vec::new();
+        ld_so_conf_dirs(Path::new(LD_SO_CONF), &mut system_dirs, 0);
+        Self { system_dirs }
+    }
+
+    fn expand_origin(dir: &str, origin: &Path) -> PathBuf {
+        let origin = origin.to_string_lossy();
+        PathBuf::from(
+            dir.replace("${ORIGIN}", &origin)
+                .replace("$ORIGIN", &origin),
+        )
+    }
+
+    /// Whether `path` is an ELF library loadable next to an object of the given class/machine
+    fn is_compatible(path: &Path, class: u8, machine: u16) -> bool {
+        match ElfReader::open(path) {
+            Ok(reader) => reader.class == class && reader.machine == machine,
+            Err(_) => false,
+        }
+    }
+
+    /// Mimics the search order of the dynamic loader (minus `LD_LIBRARY_PATH`, which is not
+    /// forwarded to the sandbox by default)
+    fn resolve(&self, library: &str, needed_by: &Path, info: &ElfInfo) -> Result<PathBuf> {
+        if library.contains('/') {
+            return Ok(PathBuf::from(library));
+        }
+
+        let origin = needed_by
+            .canonicalize()
+            .ok()
+            .and_then(|path| path.parent().map(Path::to_path_buf))
+            .unwrap_or_else(|| PathBuf::from("/"));
+
+        let mut dirs = Vec::new();
+        if info.runpath.is_empty() {
+            dirs.extend(
+                info.rpath
+                    .iter()
+                    .map(|dir| Self::expand_origin(dir, &origin)),
+            );
+        }
+        dirs.extend(
+            info.runpath
+                .iter()
+                .map(|dir| Self::expand_origin(dir, &origin)),
+        );
+        dirs.extend(self.system_dirs.iter().cloned());
+        let defaults = if info.class == ELF_CLASS_64 {
+            DEFAULT_LIBRARY_DIRS_64
+        } else {
+            DEFAULT_LIBRARY_DIRS_32
+        };
+        dirs.extend(defaults.iter().map(PathBuf::from));
+
+        dirs.into_iter()
+            .map(|dir| dir.join(library))
+            .find(|path| Self::is_compatible(path, info.class, info.machine))
+            .ok_or_else(|| RootfsError::LibraryNotFound {
+                library: library.into(),
+                needed_by: needed_by.to_path_buf(),
+            })
+    }
+}
+
+/// Computes the ELF interpreter and all the shared libraries (transitively) needed by the given
+/// executables. The executables themselves are not part of the result.
+///
+/// Paths are absolute (as they are found on the host) and sorted.
+pub fn dependencies<T: AsRef<Path>>(executables: &[T]) -> Result<Vec<PathBuf>> {
+    let resolver = Resolver::new();
+    let mut dependencies = BTreeSet::new();
+    let mut queue = VecDeque::new();
+
+    let mut infos = Vec::new();
+    let mut interpreters = Vec::new();
+    for executable in executables {
+        let path = executable.as_ref();
+        let info = ElfReader::open(path)?.info()?;
+
+        // Only the interpreter of an executable matters, the one of a library is never used
+        if let Some(ref interpreter) = info.interpreter {
+            if dependencies.insert(interpreter.clone()) {
+                queue.push_back(interpreter.clone());
+                interpreters.push(interpreter.clone());
+            }
+        }
+        infos.push((path, info));
+    }
+
+    for (path, info) in infos {
+        add_needed(
+            &resolver,
+            path,
+            &info,
+            &interpreters,
+            &mut dependencies,
+            &mut queue,
+        )?;
+    }
+
+    while let Some(path) = queue.pop_front() {
+        let info = ElfReader::open(&path)?.info()?;
+        add_needed(
+            &resolver,
+            &path,
+            &info,
+            &interpreters,
+            &mut dependencies,
+            &mut queue,
+        )?;
+    }
+
+    Ok(dependencies.into_iter().collect())
+}
+
+fn add_needed(
+    resolver: &Resolver,
+    path: &Path,
+    info: &ElfInfo,
+    interpreters: &[PathBuf],
+    dependencies: &mut BTreeSet<PathBuf>,
+    queue: &mut VecDeque<PathBuf>,
+) -> Result<()> {
+    for needed in &info.needed {
+        // The loader satisfies a dependency on itself with the already loaded interpreter
+        if interpreters
+            .iter()
+            .any(|interpreter| interpreter.file_name() == Some(OsStr::new(needed)))
+        {
+            continue;
+        }
+
+        let library = resolver.resolve(needed, path, info)?;
+        if dependencies.insert(library.clone()) {
+            queue.push_back(library);
+        }
+    }
+    Ok(())
+}
+
+/// Read-only, executable bind mounts of all the dependencies of `executables`, mounted at the
+/// same path inside the new root
+pub fn mounts<T: AsRef<Path>>(executables: &[T]) -> Result<Vec<Mount>> {
+    let mut mount_options = MountOptions::default();
+    mount_options.set_exec(true);
+
+    Ok(dependencies(executables)?
+        .into_iter()
+        .map(|path| Mount::new(path.clone(), path, mount_options))
+        .collect())
+}
+
+/// Copies all the dependencies of `executables` inside `new_root`, at the same path they have
+/// on the host
+///
+/// Every copy is a new file, the one already there is removed first. A jail executing it (or
+/// one spawned concurrently holding it open for writing) can not make the copy fail with
+/// `ETXTBSY`.
+pub fn populate<T1: AsRef<Path>, T2: AsRef<Path>>(executables: &[T1], new_root: T2) -> Result<()> {
+    for library in dependencies(executables)? {
+        let destination = new_root
+            .as_ref()
+            .join(library.strip_prefix("/").unwrap_or(library.as_path()));
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent).map_err(|err| RootfsError::CreateDirError {
+                path: parent.to_path_buf(),
+                errno: Errno::from(&err),
+            })?;
+        }
+
+        let copy_error = |err: io::Error| RootfsError::CopyError {
+            from: library.clone(),
+            to: destination.clone(),
+            errno: Errno::from(&err),
+        };
+        match fs::remove_file(&destination) {
+            Ok(()) => {}
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(copy_error(err)),
+        }
+        let _ = fs::copy(&library, &destination).map_err(copy_error)?;
+    }
+
+    Ok(())
+}
@@ -1,17 +1,140 @@
 use std::fmt::{self, Display, Formatter};
 use std::time::Duration;
 
-use config::{Limits, SpaceUsage};
+use config::{Limits, SoftLimits, SpaceUsage};
 use utils::DurationDisplay;
 
+/// How the process actually ended.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum ExitStatus {
+    Exited(u32),
+    Signaled(u32),
+}
+
+impl Display for ExitStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::Exited(exit_code) => write!(f, "exited with status {}", exit_code),
+            Self::Signaled(signal) => write!(f, "killed by signal {}", signal),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Termination {
+    exit_status: ExitStatus,
+    killed_by_sandbox: bool,
+    soft_limit_signaled: bool,
+}
+
+impl Termination {
+    pub fn new(
+        exit_status: ExitStatus,
+        killed_by_sandbox: bool,
+        soft_limit_signaled: bool,
+    ) -> Self {
+        Self {
+            exit_status,
+            killed_by_sandbox,
+            soft_limit_signaled,
+        }
+    }
+
+    pub fn exit_status(&self) -> ExitStatus {
+        self.exit_status
+    }
+
+    /// Whether the sandbox killed the process for exceeding a limit, as opposed to it exiting
+    /// or being killed by something else (like the OOM killer).
+    pub fn killed_by_sandbox(&self) -> bool {
+        self.killed_by_sandbox
+    }
+
+    /// Whether the process was sent the signal for exceeding a soft limit.
+    pub fn soft_limit_signaled(&self) -> bool {
+        self.soft_limit_signaled
+    }
+
+    /// Whether the process ended on its own after the soft limit signal, before a hard limit.
+    pub fn exited_during_grace_period(&self) -> bool {
+        self.soft_limit_signaled && !self.killed_by_sandbox
+    }
+}
+
+impl Display for Termination {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.killed_by_sandbox {
+            write!(f, "killed by sandbox")
+        } else if self.soft_limit_signaled {
+            write!(f, "{} during grace period", self.exit_status)
+        } else {
+            write!(f, "{}", self.exit_status)
+        }
+    }
+}
+
+/// The limit that was exceeded, how much was used and what happened to the process.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct LimitExceeded<T> {
+    limit: T,
+    usage: T,
+    termination: Termination,
+}
+
+impl<T: Copy> LimitExceeded<T> {
+    pub fn new(limit: T, usage: T, termination: Termination) -> Self {
+        Self {
+            limit,
+            usage,
+            termination,
+        }
+    }
+
+    pub fn limit(&self) -> T {
+        self.limit
+    }
+
+    pub fn usage(&self) -> T {
+        self.usage
+    }
+
+    pub fn termination(&self) -> Termination {
+        self.termination
+    }
+}
+
+impl Display for LimitExceeded<Duration> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} at {} (limit {})",
+            self.termination,
+            DurationDisplay(self.usage),
+            DurationDisplay(self.limit)
+        )
+    }
+}
+
+impl Display for LimitExceeded<SpaceUsage> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} at {} (limit {})",
+            self.termination, self.usage, self.limit
+        )
+    }
+}
+
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub enum RunInfoResult<T> {
     Success(T),
     NonZeroExitStatus(u32),
     KilledBySignal(u32),
-    MemoryLimitExceeded,
-    TimeLimitExceeded,
-    WallTimeLimitExceeded,
+    MemoryLimitExceeded(LimitExceeded<SpaceUsage>),
+    TimeLimitExceeded(LimitExceeded<Duration>),
+    WallTimeLimitExceeded(LimitExceeded<Duration>),
+    /// The process did not use the CPU for too long
+    Idle(LimitExceeded<Duration>),
 }
 
 #[allow(clippy::use_self)]
@@ -31,9 +154,10 @@ impl<T> RunInfoResult<T> {
             Self::Success(obj) => RunInfoResult::Success(cb(obj)?),
             Self::NonZeroExitStatus(exit_status) => RunInfoResult::NonZeroExitStatus(exit_status),
             Self::KilledBySignal(signal) => RunInfoResult::KilledBySignal(signal),
-            Self::MemoryLimitExceeded => RunInfoResult::MemoryLimitExceeded,
-            Self::TimeLimitExceeded => RunInfoResult::TimeLimitExceeded,
-            Self::WallTimeLimitExceeded => RunInfoResult::WallTimeLimitExceeded,
+            Self::MemoryLimitExceeded(exceeded) => RunInfoResult::MemoryLimitExceeded(exceeded),
+            Self::TimeLimitExceeded(exceeded) => RunInfoResult::TimeLimitExceeded(exceeded),
+            Self::WallTimeLimitExceeded(exceeded) => RunInfoResult::WallTimeLimitExceeded(exceeded),
+            Self::Idle(exceeded) => RunInfoResult::Idle(exceeded),
         })
     }
 
@@ -43,6 +167,19 @@ impl<T> RunInfoResult<T> {
             _ => None,
         }
     }
+
+    /// How the process ended, whatever the verdict
+    pub fn exit_status(&self) -> ExitStatus {
+        match *self {
+            Self::Success(_) => ExitStatus::Exited(0),
+            Self::NonZeroExitStatus(exit_code) => ExitStatus::Exited(exit_code),
+            Self::KilledBySignal(signal) => ExitStatus::Signaled(signal),
+            Self::MemoryLimitExceeded(ref exceeded) => exceeded.termination().exit_status(),
+            Self::TimeLimitExceeded(ref exceeded)
+            | Self::WallTimeLimitExceeded(ref exceeded)
+            | Self::Idle(ref exceeded) => exceeded.termination().exit_status(),
+        }
+    }
 }
 
 impl<T> Display for RunInfoResult<T> {
@@ -53,26 +190,99 @@ impl<T> Display for RunInfoResult<T> {
                 write!(f, "Non zero exit status: {}", exit_code)
             }
             Self::KilledBySignal(ref signal) => write!(f, "Killed by Signal {}", signal),
-            Self::MemoryLimitExceeded => write!(f, "Memory limit exceeded"),
-            Self::TimeLimitExceeded => write!(f, "Time limit exceeded"),
-            Self::WallTimeLimitExceeded => write!(f, "Wall time limit exceeded"),
+            Self::MemoryLimitExceeded(ref exceeded) => {
+                write!(f, "Memory limit exceeded: {}", exceeded)
+            }
+            Self::TimeLimitExceeded(ref exceeded) => write!(f, "Time limit exceeded: {}", exceeded),
+            Self::WallTimeLimitExceeded(ref exceeded) => {
+                write!(f, "Wall time limit exceeded: {}", exceeded)
+            }
+            Self::Idle(ref exceeded) => write!(f, "Idle: {}", exceeded),
         }
     }
 }
 
+/// How much the cpu quota held the process back.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct CpuThrottling {
+    nr_throttled: u64,
+    throttled_time: Duration,
+}
+
+impl CpuThrottling {
+    pub fn new(nr_throttled: u64, throttled_time: Duration) -> Self {
+        Self {
+            nr_throttled,
+            throttled_time,
+        }
+    }
+
+    /// In how many periods the process used up its quota
+    pub fn nr_throttled(&self) -> u64 {
+        self.nr_throttled
+    }
+
+    /// How long the process could not run because of the quota
+    pub fn throttled_time(&self) -> Duration {
+        self.throttled_time
+    }
+}
+
+/// Bytes read from and written to block devices.
+///
+/// Writes that go through the page cache are only counted with cgroup v2.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct IoUsage {
+    read_bytes: u64,
+    write_bytes: u64,
+}
+
+impl IoUsage {
+    pub fn new(read_bytes: u64, write_bytes: u64) -> Self {
+        Self {
+            read_bytes,
+            write_bytes,
+        }
+    }
+
+    pub fn read_bytes(&self) -> u64 {
+        self.read_bytes
+    }
+
+    pub fn write_bytes(&self) -> u64 {
+        self.write_bytes
+    }
+}
+
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct RunUsage {
     user_time: Duration,
     wall_time: Duration,
     memory: SpaceUsage,
+    idle_time: Duration,
+    throttling: CpuThrottling,
+    io: IoUsage,
+    disk_usage: SpaceUsage,
 }
 
 impl RunUsage {
-    pub fn new(user_time: Duration, wall_time: Duration, memory: SpaceUsage) -> Self {
+    pub fn new(
+        user_time: Duration,
+        wall_time: Duration,
+        memory: SpaceUsage,
+        idle_time: Duration,
+        throttling: CpuThrottling,
+        io: IoUsage,
+        disk_usage: SpaceUsage,
+    ) -> Self {
         Self {
             user_time,
             wall_time,
             memory,
+            idle_time,
+            throttling,
+            io,
+            disk_usage,
         }
     }
 
@@ -88,30 +298,112 @@ impl RunUsage {
         self.memory
     }
 
-    pub fn check_limits<T>(self, limits: Limits) -> Option<RunInfo<T>> {
-        if limits
-            .user_time()
-            .map_or(false, |time| time < self.user_time())
-        {
-            return Some(RunInfo::new(RunInfoResult::TimeLimitExceeded, self));
+    /// The longest stretch of wall time in which the process did not use the CPU
+    pub fn idle_time(&self) -> Duration {
+        self.idle_time
+    }
+
+    /// Only measured with a cpu quota
+    pub fn throttling(&self) -> CpuThrottling {
+        self.throttling
+    }
+
+    /// Only measured if the blkio (or io) controller is available
+    pub fn io(&self) -> IoUsage {
+        self.io
+    }
+
+    /// The space taken on the size limited mounts
+    pub fn disk_usage(&self) -> SpaceUsage {
+        self.disk_usage
+    }
+
+    fn exceeded_limit(&self, limits: Limits) -> Option<ExceededLimit> {
+        if let Some(limit) = limits.user_time() {
+            if limit < self.user_time() {
+                return Some(ExceededLimit::UserTime(limit));
+            }
+        }
+
+        if let Some(limit) = limits.wall_time() {
+            if limit < self.wall_time() {
+                return Some(ExceededLimit::WallTime(limit));
+            }
         }
 
-        if limits
-            .wall_time()
-            .map_or(false, |time| time < self.wall_time())
-        {
-            return Some(RunInfo::new(RunInfoResult::WallTimeLimitExceeded, self));
+        if let Some(limit) = limits.memory() {
+            if limit < self.memory() {
+                return Some(ExceededLimit::Memory(limit));
+            }
         }
 
-        if limits
-            .memory()
-            .map_or(false, |memory| memory < self.memory())
-        {
-            return Some(RunInfo::new(RunInfoResult::MemoryLimitExceeded, self));
+        if let Some(limit) = limits.idle_time() {
+            if limit < self.idle_time() {
+                return Some(ExceededLimit::Idle(limit));
+            }
         }
 
         None
     }
+
+    pub fn exceeds_limits(&self, limits: Limits) -> bool {
+        self.exceeded_limit(limits).is_some()
+    }
+
+    fn exceeded_soft_limit(&self, soft: SoftLimits) -> Option<ExceededLimit> {
+        if let Some(limit) = soft.user_time() {
+            if limit < self.user_time() {
+                return Some(ExceededLimit::UserTime(limit));
+            }
+        }
+
+        if let Some(limit) = soft.wall_time() {
+            if limit < self.wall_time() {
+                return Some(ExceededLimit::WallTime(limit));
+            }
+        }
+
+        None
+    }
+
+    /// Whether the process should be sent the soft limit signal.
+    pub fn exceeds_soft_limits(&self, soft: SoftLimits) -> bool {
+        self.exceeded_soft_limit(soft).is_some()
+    }
+
+    pub fn check_limits<T>(self, limits: Limits, termination: Termination) -> Option<RunInfo<T>> {
+        let result =
+            // A process that ended during its grace period still exceeded the soft limit
+            match self.exceeded_limit(limits).or_else(|| {
+                if termination.soft_limit_signaled() {
+                    self.exceeded_soft_limit(limits.soft())
+                } else {
+                    None
+                }
+            })? {
+                ExceededLimit::UserTime(limit) => RunInfoResult::TimeLimitExceeded(
+                    LimitExceeded::new(limit, self.user_time(), termination),
+                ),
+                ExceededLimit::WallTime(limit) => RunInfoResult::WallTimeLimitExceeded(
+                    LimitExceeded::new(limit, self.wall_time(), termination),
+                ),
+                ExceededLimit::Memory(limit) => RunInfoResult::MemoryLimitExceeded(
+                    LimitExceeded::new(limit, self.memory(), termination),
+                ),
+                ExceededLimit::Idle(limit) => {
+                    RunInfoResult::Idle(LimitExceeded::new(limit, self.idle_time(), termination))
+                }
+            };
+        Some(RunInfo::new(result, self))
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+enum ExceededLimit {
+    UserTime(Duration),
+    WallTime(Duration),
+    Memory(SpaceUsage),
+    Idle(Duration),
 }
 
 impl Default for RunUsage {
@@ -120,6 +412,10 @@ impl Default for RunUsage {
             Duration::from_secs(0),
             Duration::from_secs(0),
             SpaceUsage::from_bytes(0),
+            Duration::from_secs(0),
+            CpuThrottling::default(),
+            IoUsage::default(),
+            SpaceUsage::from_bytes(0),
         )
     }
 }
@@ -128,19 +424,248 @@ impl Display for RunUsage {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         writeln!(f, "Total user time: {}", DurationDisplay(self.user_time()))?;
         writeln!(f, "Wall time: {}", DurationDisplay(self.wall_time()))?;
-        write!(f, "Maximum memory: {}", self.memory())
+        writeln!(f, "Maximum memory: {}", self.memory())?;
+        write!(
+            f,
+            "Longest idle time: {}",
+            DurationDisplay(self.idle_time())
+        )?;
+        if self.throttling.nr_throttled() > 0 {
+            write!(
+                f,
+                "\nThrottled: {} times for {}",
+                self.throttling.nr_throttled(),
+                DurationDisplay(self.throttling.throttled_time())
+            )?;
+        }
+        if self.io != IoUsage::default() {
+            write!(
+                f,
+                "\nI/O: {} bytes read, {} bytes written",
+                self.io.read_bytes(),
+                self.io.write_bytes()
+            )?;
+        }
+        if self.disk_usage.as_bytes() > 0 {
+            write!(f, "\nDisk usage: {} bytes", self.disk_usage.as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+/// Usage of a single thread of a [`ProcessUsage`]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ThreadUsage {
+    tid: u32,
+    cpu_time: Duration,
+}
+
+impl ThreadUsage {
+    pub fn new(tid: u32, cpu_time: Duration) -> Self {
+        Self { tid, cpu_time }
+    }
+
+    /// As seen inside the jail
+    pub fn tid(&self) -> u32 {
+        self.tid
+    }
+
+    /// User and system time
+    pub fn cpu_time(&self) -> Duration {
+        self.cpu_time
+    }
+}
+
+/// Usage of one of the processes in the jail, along with its threads and the processes it
+/// started.
+///
+/// The values are the last ones sampled from `/proc`, so processes living for less than a
+/// sample are missed. The exit status is only known for processes seen as zombies (and for the
+/// sandboxed process itself).
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ProcessUsage {
+    pid: u32,
+    command_line: Vec<String>,
+    cpu_time: Duration,
+    peak_memory: SpaceUsage,
+    exit_status: Option<ExitStatus>,
+    threads: Vec<ThreadUsage>,
+    children: Vec<Self>,
+}
+
+impl ProcessUsage {
+    #![allow(clippy::too_many_arguments)]
+    pub fn new(
+        pid: u32,
+        command_line: Vec<String>,
+        cpu_time: Duration,
+        peak_memory: SpaceUsage,
+        exit_status: Option<ExitStatus>,
+        threads: Vec<ThreadUsage>,
+        children: Vec<Self>,
+    ) -> Self {
+        Self {
+            pid,
+            command_line,
+            cpu_time,
+            peak_memory,
+            exit_status,
+            threads,
+            children,
+        }
+    }
+
+    /// As seen inside the jail
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    pub fn command_line(&self) -> &[String] {
+        &self.command_line
+    }
+
+    /// User and system time of all the threads (not including children)
+    pub fn cpu_time(&self) -> Duration {
+        self.cpu_time
+    }
+
+    /// Peak resident set size
+    pub fn peak_memory(&self) -> SpaceUsage {
+        self.peak_memory
+    }
+
+    pub fn exit_status(&self) -> Option<ExitStatus> {
+        self.exit_status
+    }
+
+    pub fn threads(&self) -> &[ThreadUsage] {
+        &self.threads
+    }
+
+    pub fn children(&self) -> &[Self] {
+        &self.children
+    }
+
+    fn fmt_tree(&self, f: &mut Formatter<'_>, depth: usize) -> fmt::Result {
+        let indent = "  ".repeat(depth);
+        write!(
+            f,
+            "\n{}{} {}: {} cpu time, {} peak memory, ",
+            indent,
+            self.pid,
+            self.command_line.join(" "),
+            DurationDisplay(self.cpu_time),
+            self.peak_memory
+        )?;
+        match self.exit_status {
+            Some(exit_status) => write!(f, "{}", exit_status)?,
+            None => write!(f, "exit status unknown")?,
+        }
+
+        if self.threads.len() > 1 {
+            for thread in &self.threads {
+                write!(
+                    f,
+                    "\n{}  thread {}: {} cpu time",
+                    indent,
+                    thread.tid,
+                    DurationDisplay(thread.cpu_time)
+                )?;
+            }
+        }
+        for child in &self.children {
+            child.fmt_tree(f, depth + 1)?;
+        }
+        Ok(())
     }
 }
+
+/// How the output of a successful run compares to the expected one.
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum Verdict {
+    Accepted,
+    WrongAnswer,
+    /// The answer is right, but not laid out as expected (whitespace, line breaks)
+    PresentationError,
+}
+
+impl Display for Verdict {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::Accepted => write!(f, "Accepted"),
+            Self::WrongAnswer => write!(f, "Wrong answer"),
+            Self::PresentationError => write!(f, "Presentation error"),
+        }
+    }
+}
+
+/// The verdict of a checker (see [`checker::Checker`]), along with its explanation.
+///
+/// [`checker::Checker`]: ../checker/enum.Checker.html
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct CheckResult {
+    verdict: Verdict,
+    message: String,
+}
+
+impl CheckResult {
+    pub fn new(verdict: Verdict, message: String) -> Self {
+        Self { verdict, message }
+    }
+
+    pub fn verdict(&self) -> Verdict {
+        self.verdict
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn is_accepted(&self) -> bool {
+        self.verdict == Verdict::Accepted
+    }
+}
+
+impl Display for CheckResult {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.message.is_empty() {
+            write!(f, "{}", self.verdict)
+        } else {
+            write!(f, "{}: {}", self.verdict, self.message)
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct RunInfo<T> {
     result: RunInfoResult<T>,
     usage: RunUsage,
+    processes: Vec<ProcessUsage>,
+    check: Option<CheckResult>,
 }
 
 #[allow(clippy::use_self)]
 impl<T> RunInfo<T> {
     pub fn new(result: RunInfoResult<T>, usage: RunUsage) -> Self {
-        Self { result, usage }
+        Self {
+            result,
+            usage,
+            processes: Vec::new(),
+            check: None,
+        }
+    }
+
+    /// Attaches the per process breakdown of the usage
+    pub fn with_processes(self, processes: Vec<ProcessUsage>) -> Self {
+        Self { processes, ..self }
+    }
+
+    /// Attaches the verdict of checking the output
+    pub fn with_check(self, check: CheckResult) -> Self {
+        Self {
+            check: Some(check),
+            ..self
+        }
     }
 
     pub fn result(&self) -> &RunInfoResult<T> {
@@ -151,15 +676,33 @@ impl<T> RunInfo<T> {
         &self.usage
     }
 
+    /// The process tree with per process usage, only recorded in detailed usage mode
+    pub fn processes(&self) -> &[ProcessUsage] {
+        &self.processes
+    }
+
+    /// The verdict on the output, only there if it was checked
+    pub fn check(&self) -> Option<&CheckResult> {
+        self.check.as_ref()
+    }
+
     pub fn is_success(&self) -> bool {
         self.result.is_success()
     }
 
     pub fn and_then<A, B, F: FnOnce(T) -> Result<A, B>>(self, cb: F) -> Result<RunInfo<A>, B> {
-        let Self { result, usage } = self;
-        result
-            .and_then(cb)
-            .map(|result| RunInfo::new(result, usage))
+        let Self {
+            result,
+            usage,
+            processes,
+            check,
+        } = self;
+        result.and_then(cb).map(|result| RunInfo {
+            result,
+            usage,
+            processes,
+            check,
+        })
     }
 
     pub fn success(self) -> Option<T> {
@@ -170,6 +713,16 @@ impl<T> RunInfo<T> {
 impl<T> Display for RunInfo<T> {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         writeln!(f, "{}", self.result)?;
-        write!(f, "{}", self.usage)
+        if let Some(check) = &self.check {
+            writeln!(f, "Checker: {}", check)?;
+        }
+        write!(f, "{}", self.usage)?;
+        if !self.processes.is_empty() {
+            write!(f, "\nProcesses:")?;
+            for process in &self.processes {
+                process.fmt_tree(f, 1)?;
+            }
+        }
+        Ok(())
     }
 }
@@ -0,0 +1,350 @@
+//! Provisioning the cgroup hierarchies the sandbox runs in.
+//!
+//! The sandbox itself runs unprivileged, so someone with root has to create the `ia-sandbox`
+//! hierarchies and hand them over to the judge user. [`setup`] does that for both cgroup v1
+//! (one hierarchy per controller, chowned to the user) and cgroup v2 (a single delegated
+//! hierarchy with the controllers enabled) and reports which kernel features are available.
+
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use errors::{Errno, Error, Result, SetupError};
+use ffi;
+
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+const HIERARCHY_NAME: &str = "ia-sandbox";
+const V1_CONTROLLERS: &[&str] = &["cpuacct", "memory", "pids"];
+const V1_DELEGATED_FILES: &[&str] = &["cgroup.procs", "tasks"];
+/// Only needed by some jails (e.g. with a cpuset), so they are set up when available. The files
+/// are copied from the parent, a new cpuset cgroup has no cpus or memory nodes.
+const V1_OPTIONAL_CONTROLLERS: &[(&str, &[&str])] = &[
+    ("cpuset", &["cpuset.cpus", "cpuset.mems"]),
+    ("cpu", &[]),
+    ("blkio", &[]),
+];
+const V2_CONTROLLERS: &[&str] = &["cpu", "memory", "pids"];
+const V2_DELEGATED_FILES: &[&str] = &["cgroup.procs", "cgroup.subtree_control", "cgroup.threads"];
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub enum CGroupVersion {
+    V1,
+    V2,
+}
+
+impl CGroupVersion {
+    /// Detects which cgroup version is mounted at `/sys/fs/cgroup`.
+    pub fn detect() -> Result<Self> {
+        let root = Path::new(CGROUP_ROOT);
+        if root.join("cgroup.controllers").exists() {
+            Ok(Self::V2)
+        } else if root.join("memory").exists() {
+            Ok(Self::V1)
+        } else {
+            Err(SetupError::UnknownCGroupVersion {
+                path: root.to_path_buf(),
+            }
+            .into())
+        }
+    }
+}
+
+impl fmt::Display for CGroupVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::V1 => write!(f, "v1"),
+            Self::V2 => write!(f, "v2"),
+        }
+    }
+}
+
+/// The owner of the hierarchies, usually the user the judge runs as.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub struct Owner {
+    uid: u32,
+    gid: u32,
+}
+
+impl Owner {
+    pub fn new(uid: u32, gid: u32) -> Self {
+        Self { uid, gid }
+    }
+
+    /// Looks up a user by name. A numeric uid is also accepted, with a group of the same id.
+    pub fn from_user(user: &str) -> Result<Self> {
+        if let Some((uid, gid)) = ffi::get_user_ids(user) {
+            return Ok(Self::new(uid, gid));
+        }
+
+        match user.parse::<u32>() {
+            Ok(uid) => Ok(Self::new(uid, uid)),
+            Err(_) => Err(SetupError::UserNotFound {
+                user: user.to_owned(),
+            }
+            .into()),
+        }
+    }
+
+    pub fn uid(self) -> u32 {
+        self.uid
+    }
+
+    pub fn gid(self) -> u32 {
+        self.gid
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub struct SetupOptions {
+    version: Option<CGroupVersion>,
+    owner: Owner,
+}
+
+impl SetupOptions {
+    /// Without a version the one mounted at `/sys/fs/cgroup` is used.
+    pub fn new(version: Option<CGroupVersion>, owner: Owner) -> Self {
+        Self { version, owner }
+    }
+
+    pub fn version(&self) -> Option<CGroupVersion> {
+        self.version
+    }
+
+    pub fn owner(&self) -> Owner {
+        self.owner
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub enum KernelFeature {
+    /// Unprivileged processes can create user namespaces
+    UserNamespaces,
+    /// Swap usage is accounted, so memory limits also cover swap
+    SwapAccounting,
+    /// Processes can be given their own cgroup namespace
+    CGroupNamespaces,
+}
+
+impl KernelFeature {
+    pub fn all() -> &'static [Self] {
+        &[
+            Self::UserNamespaces,
+            Self::SwapAccounting,
+            Self::CGroupNamespaces,
+        ]
+    }
+
+    pub fn is_available(self, version: CGroupVersion) -> bool {
+        match self {
+            Self::UserNamespaces => user_namespaces_available(),
+            Self::SwapAccounting => swap_accounting_available(version),
+            Self::CGroupNamespaces => Path::new("/proc/self/ns/cgroup").exists(),
+        }
+    }
+}
+
+impl fmt::Display for KernelFeature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::UserNamespaces => write!(f, "user namespaces"),
+            Self::SwapAccounting => write!(f, "swap accounting"),
+            Self::CGroupNamespaces => write!(f, "cgroup namespaces"),
+        }
+    }
+}
+
+pub(crate) fn read_sysctl(path: &str) -> Option<u64> {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|value| value.trim().parse().ok())
+}
+
+fn user_namespaces_available() -> bool {
+    // Debian and Ubuntu have extra knobs for unprivileged user namespaces
+    read_sysctl("/proc/sys/user/max_user_namespaces").unwrap_or(0) > 0
+        && read_sysctl("/proc/sys/kernel/unprivileged_userns_clone").unwrap_or(1) == 1
+        && read_sysctl("/proc/sys/kernel/apparmor_restrict_unprivileged_userns").unwrap_or(0) == 0
+}
+
+fn swap_accounting_available(version: CGroupVersion) -> bool {
+    match version {
+        CGroupVersion::V1 => Path::new(CGROUP_ROOT)
+            .join("memory/memory.memsw.limit_in_bytes")
+            .exists(),
+        // The root cgroup has no interface files, so look inside our hierarchy
+        CGroupVersion::V2 => Path::new(CGROUP_ROOT)
+            .join(HIERARCHY_NAME)
+            .join("memory.swap.max")
+            .exists(),
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SetupReport {
+    version: CGroupVersion,
+    hierarchies: Vec<PathBuf>,
+    features: Vec<(KernelFeature, bool)>,
+}
+
+impl SetupReport {
+    pub fn version(&self) -> CGroupVersion {
+        self.version
+    }
+
+    /// The hierarchies created (or found) and handed over to the owner.
+    pub fn hierarchies(&self) -> &[PathBuf] {
+        &self.hierarchies
+    }
+
+    /// Every kernel feature the sandbox uses and whether it is available.
+    pub fn features(&self) -> &[(KernelFeature, bool)] {
+        &self.features
+    }
+}
+
+impl fmt::Display for SetupReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "cgroup {}", self.version)?;
+        for hierarchy in &self.hierarchies {
+            writeln!(f, "hierarchy {}", hierarchy.display())?;
+        }
+        for &(feature, available) in &self.features {
+            if available {
+                writeln!(f, "{}: available", feature)?;
+            } else {
+                writeln!(f, "{}: missing", feature)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn create_dir(path: &Path) -> Result<()> {
+    fs::create_dir_all(path).map_err(|err| {
+        Error::from(SetupError::CreateDirError {
+            path: path.to_path_buf(),
+            errno: Errno::from(&err),
+        })
+    })
+}
+
+fn write_file(path: &Path, line: &str) -> Result<()> {
+    OpenOptions::new()
+        .write(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(line.as_bytes()))
+        .map_err(|err| {
+            Error::from(SetupError::WriteError {
+                path: path.to_path_buf(),
+                errno: Errno::from(&err),
+            })
+        })
+}
+
+fn delegate(path: &Path, files: &[&str], owner: Owner) -> Result<()> {
+    ffi::chown(path, owner.uid, owner.gid)?;
+    for file in files {
+        let file = path.join(file);
+        // Not every kernel has all the files (e.g. cgroup.threads)
+        if file.exists() {
+            ffi::chown(&file, owner.uid, owner.gid)?;
+        }
+    }
+    Ok(())
+}
+
+fn setup_v1(owner: Owner) -> Result<Vec<PathBuf>> {
+    let mut hierarchies = V1_CONTROLLERS
+        .iter()
+        .map(|controller| {
+            let controller_root = Path::new(CGROUP_ROOT).join(controller);
+            if !controller_root.exists() {
+                return Err(SetupError::ControllerNotAvailable {
+                    controller: (*controller).to_owned(),
+                    path: controller_root,
+                }
+                .into());
+            }
+
+            let hierarchy = controller_root.join(HIERARCHY_NAME);
+            create_dir(&hierarchy)?;
+            delegate(&hierarchy, V1_DELEGATED_FILES, owner)?;
+            Ok(hierarchy)
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    for &(controller, inherited_files) in V1_OPTIONAL_CONTROLLERS {
+        let controller_root = Path::new(CGROUP_ROOT).join(controller);
+        if !controller_root.exists() {
+            continue;
+        }
+
+        let hierarchy = controller_root.join(HIERARCHY_NAME);
+        create_dir(&hierarchy)?;
+        for file in inherited_files {
+            let value = fs::read_to_string(controller_root.join(file)).unwrap_or_default();
+            write_file(&hierarchy.join(file), &value)?;
+        }
+        delegate(&hierarchy, V1_DELEGATED_FILES, owner)?;
+        hierarchies.push(hierarchy);
+    }
+    Ok(hierarchies)
+}
+
+fn enable_controllers(path: &Path) -> Result<()> {
+    let line = V2_CONTROLLERS
+        .iter()
+        .map(|controller| format!("+{}", controller))
+        .collect::<Vec<_>>()
+        .join(" ");
+    write_file(&path.join("cgroup.subtree_control"), &line)
+}
+
+fn setup_v2(owner: Owner) -> Result<Vec<PathBuf>> {
+    let root = Path::new(CGROUP_ROOT);
+    let available = fs::read_to_string(root.join("cgroup.controllers")).unwrap_or_default();
+    for controller in V2_CONTROLLERS {
+        if !available.split_whitespace().any(|name| name == *controller) {
+            return Err(SetupError::ControllerNotAvailable {
+                controller: (*controller).to_owned(),
+                path: root.to_path_buf(),
+            }
+            .into());
+        }
+    }
+
+    // Controllers have to be enabled all the way down, instances live under our hierarchy
+    enable_controllers(root)?;
+    let hierarchy = root.join(HIERARCHY_NAME);
+    create_dir(&hierarchy)?;
+    enable_controllers(&hierarchy)?;
+    delegate(&hierarchy, V2_DELEGATED_FILES, owner)?;
+    Ok(vec![hierarchy])
+}
+
+/// Creates the `ia-sandbox` hierarchies and hands them over to the owner. This needs root.
+///
+/// Running it again is harmless, existing hierarchies are reused.
+pub fn setup(options: &SetupOptions) -> Result<SetupReport> {
+    let version = match options.version() {
+        Some(version) => version,
+        None => CGroupVersion::detect()?,
+    };
+
+    let hierarchies = match version {
+        CGroupVersion::V1 => setup_v1(options.owner())?,
+        CGroupVersion::V2 => setup_v2(options.owner())?,
+    };
+
+    let features = KernelFeature::all()
+        .iter()
+        .map(|&feature| (feature, feature.is_available(version)))
+        .collect();
+
+    Ok(SetupReport {
+        version,
+        hierarchies,
+        features,
+    })
+}
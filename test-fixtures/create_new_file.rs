@@ -0,0 +1,15 @@
+use std::env;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::process;
+
+fn main() {
+    let path = env::args().nth(1).unwrap();
+
+    // Fails if an earlier run left the file behind
+    let mut file = match OpenOptions::new().write(true).create_new(true).open(path) {
+        Ok(file) => file,
+        Err(_) => process::exit(1),
+    };
+    file.write_all(b"a").unwrap();
+}
@@ -0,0 +1,18 @@
+extern crate libc;
+
+use std::thread;
+use std::time::Duration;
+
+extern "C" fn exit_gracefully(_: libc::c_int) {
+    unsafe { libc::_exit(3) };
+}
+
+fn main() {
+    unsafe {
+        libc::signal(
+            libc::SIGTERM,
+            exit_gracefully as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        )
+    };
+    thread::sleep(Duration::from_secs(1));
+}
@@ -0,0 +1,22 @@
+use std::fs::File;
+use std::io::Write;
+use std::os::unix::io::FromRawFd;
+use std::process;
+
+fn main() {
+    // The page cache of what is written fills the memory, synced so it can be reclaimed instead
+    // of the program being killed. Written a page at a time, so it is charged a page at a time
+    let mut stdout = unsafe { File::from_raw_fd(1) };
+    for _ in 0..20 {
+        for _ in 0..256 {
+            stdout.write_all(&[b'a'; 4096]).unwrap();
+        }
+        stdout.sync_data().unwrap();
+    }
+
+    // Too large to fit anywhere, so it fails like an allocation past the limit
+    let mut vec: Vec<u8> = Vec::new();
+    if vec.try_reserve_exact(1 << 46).is_err() {
+        process::exit(1);
+    }
+}
@@ -0,0 +1,13 @@
+use std::env;
+use std::fs::File;
+use std::io::Write;
+use std::process;
+
+fn main() {
+    let path = env::args().nth(1).unwrap();
+
+    let mut file = File::create(path).unwrap();
+    if file.write_all(&[b'a'; 1_000_000]).is_err() {
+        process::exit(1);
+    }
+}
@@ -1,13 +1,36 @@
 extern crate ia_sandbox;
 extern crate libc;
+extern crate serde_json;
 extern crate tempfile;
 
-use std::fs::File;
+use std::env;
+use std::ffi::OsStr;
+use std::fs::{self, File};
 use std::io::Write;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::process::Command;
 use std::time::Duration;
 
-use ia_sandbox::config::{ClearUsage, Environment, Mount, MountOptions, SpaceUsage, SwapRedirects};
-use ia_sandbox::errors::{ChildError, Error, FFIError};
+use ia_sandbox::batch::{self, Case, OnFailure};
+use ia_sandbox::checker::Checker;
+use ia_sandbox::config::{
+    ClearUsage, ControllerPath, CpuQuota, CpuSet, DetailedUsage, Environment, IoDevice, IoLimits,
+    LandlockRules, Mount, MountOptions, SoftLimits, SpaceUsage, SwapRedirects,
+};
+use ia_sandbox::cpuset::{self, CpuAllocator};
+use ia_sandbox::doctor::{self, Status};
+use ia_sandbox::errors::{
+    CheckerError, ChildError, CpuSetError, Errno, Error, FFIError, RootfsError, SetupError,
+};
+use ia_sandbox::format::{self, Template};
+use ia_sandbox::job::{self, Artifact, ArtifactMode, CompileJob, JobReport, StageReport};
+use ia_sandbox::pool::{Job, Pool};
+use ia_sandbox::preset::{Language, Preset};
+use ia_sandbox::rootfs;
+use ia_sandbox::run_info::{CheckResult, ExitStatus, RunInfoResult, Verdict};
+use ia_sandbox::setup::{self, CGroupVersion, KernelFeature, Owner, SetupOptions};
 
 use tempfile::Builder;
 
@@ -15,10 +38,10 @@ mod utils;
 #[cfg(feature = "nightly")]
 use utils::matchers::KilledBySignal;
 use utils::matchers::{
-    AnnotateAssert, CompareLimits, IsSuccess, MemoryLimitExceeded, NonZeroExitStatus,
+    AnnotateAssert, CompareLimits, Idle, IsSuccess, MemoryLimitExceeded, NonZeroExitStatus,
     TimeLimitExceeded, WallTimeLimitExceeded,
 };
-use utils::{LimitsBuilder, PivotRoot, RunInfoExt, TestRunnerHelper};
+use utils::{ConfigBuilder, LimitsBuilder, PivotRoot, RunInfoExt, TestRunnerHelper};
 
 const HELLO_WORLD: &str = "./target/debug/hello_world";
 
@@ -31,6 +54,8 @@ const KILL_WITH_SIGNAL_ARG: &str = "./target/debug/kill_with_signal_arg";
 
 const SLEEP_1_SECOND: &str = "./target/debug/sleep_1_second";
 
+const EXIT_ON_SIGTERM: &str = "./target/debug/exit_on_sigterm";
+
 const LOOP_500_MS: &str = "./target/debug/loop_500_ms";
 
 const THREADS_LOOP_500_MS: &str = "./target/debug/threads_loop_500_ms";
@@ -48,6 +73,10 @@ const EXIT_WITH_ENV: &str = "./target/debug/exit_with_env";
 const WRITE_THEN_READ: &str = "./target/debug/write_then_read";
 const READ_THEN_WRITE: &str = "./target/debug/read_then_write";
 
+const WRITE_1_MEGABYTE: &str = "./target/debug/write_1_megabyte";
+const FILL_MEMORY_THEN_FAIL_ALLOCATION: &str = "./target/debug/fill_memory_then_fail_allocation";
+const CREATE_NEW_FILE: &str = "./target/debug/create_new_file";
+
 #[test]
 fn test_basic_sandbox() {
     TestRunnerHelper::for_simple_exec("test_basic_sandbox", HELLO_WORLD, PivotRoot::DoNot)
@@ -59,12 +88,19 @@ fn test_basic_sandbox() {
 
 #[test]
 fn test_exec_failed() {
-    match TestRunnerHelper::for_simple_exec("test_exec_failed", HELLO_WORLD, PivotRoot::DoNot)
+    let err = TestRunnerHelper::for_simple_exec("test_exec_failed", HELLO_WORLD, PivotRoot::DoNot)
         .config_builder()
         .command("missing")
         .build_and_run()
-        .unwrap_err()
-    {
+        .unwrap_err();
+    // The code is the one of the underlying error
+    assert_eq!(err.code(), "ffi.exec");
+    assert_eq!(err.errno(), Some(Errno::new(libc::ENOENT)));
+    assert_eq!(
+        Errno::new(libc::ENOENT).to_string(),
+        "No such file or directory"
+    );
+    match err {
         Error::ChildError(ChildError::FFIError(FFIError::ExecError { .. })) => (),
         err => panic!("Expected exec error, got {}", err),
     }
@@ -111,6 +147,441 @@ fn test_redirect_stdin() {
         .assert(NonZeroExitStatus::new(23));
 }
 
+#[test]
+fn test_batch() {
+    let mut helper =
+        TestRunnerHelper::for_simple_exec("test_batch", EXIT_WITH_INPUT, PivotRoot::Pivot);
+    let config = helper.config_builder().build();
+
+    let cases: Vec<Case> = [0, 3, 5]
+        .iter()
+        .map(|exit_code| {
+            let input = format!("input{}", exit_code);
+            helper.write_file(&input, exit_code.to_string().as_bytes());
+            Case::new(
+                Some(helper.file_path(&input)),
+                Some(helper.file_path(format!("output{}", exit_code))),
+                config.limits(),
+            )
+        })
+        .collect();
+
+    let run_infos = batch::run(&config, &cases, OnFailure::KeepGoing).unwrap();
+    assert_eq!(run_infos.len(), 3);
+    for (run_info, &exit_code) in run_infos.into_iter().zip(&[0, 3, 5]) {
+        let run_info = run_info.unwrap();
+        if exit_code == 0 {
+            run_info.assert(IsSuccess);
+        } else {
+            run_info.assert(NonZeroExitStatus::new(exit_code));
+        }
+        let output_path = helper.file_path(format!("output{}", exit_code));
+        assert_eq!(helper.read_line(output_path), format!("{}\n", exit_code));
+    }
+
+    // Nothing runs after the first failure
+    let run_infos = batch::run(&config, &cases, OnFailure::Stop).unwrap();
+    assert_eq!(run_infos.len(), 2);
+    run_infos
+        .into_iter()
+        .last()
+        .unwrap()
+        .unwrap()
+        .assert(NonZeroExitStatus::new(3));
+
+    // A case that can not run does not stop the others
+    let mut cases = cases;
+    cases[0] = Case::new(
+        Some(helper.file_path("missing")),
+        Some(helper.file_path("output0")),
+        config.limits(),
+    );
+    let run_infos = batch::run(&config, &cases, OnFailure::KeepGoing).unwrap();
+    assert_eq!(run_infos.len(), 3);
+    assert!(run_infos[0].is_err());
+    for (run_info, &exit_code) in run_infos.into_iter().skip(1).zip(&[3, 5]) {
+        run_info.unwrap().assert(NonZeroExitStatus::new(exit_code));
+    }
+
+    let run_infos = batch::run(&config, &cases, OnFailure::Stop).unwrap();
+    assert_eq!(run_infos.len(), 1);
+    assert!(run_infos[0].is_err());
+}
+
+#[test]
+fn test_checker() {
+    let mut helper =
+        TestRunnerHelper::for_simple_exec("test_checker", HELLO_WORLD, PivotRoot::Pivot);
+    let input = helper.file_path("input");
+    helper.write_file("input", b"");
+    let output = helper.file_path("output");
+    let answer = helper.file_path("answer");
+    let run_info = helper
+        .config_builder()
+        .stdout(&output)
+        .build_and_run()
+        .unwrap();
+
+    let cases: &[(Checker, &[u8], Verdict)] = &[
+        (Checker::Exact, b"Hello World!\n", Verdict::Accepted),
+        (Checker::Exact, b"Hello  World!", Verdict::PresentationError),
+        (Checker::Exact, b"Hello World?\n", Verdict::WrongAnswer),
+        (
+            Checker::IgnoreWhitespace,
+            b" Hello  World!\n\n",
+            Verdict::Accepted,
+        ),
+        (
+            Checker::IgnoreWhitespace,
+            b"Hello\nWorld!",
+            Verdict::PresentationError,
+        ),
+        (Checker::Tokens, b"Hello\nWorld!", Verdict::Accepted),
+        (Checker::Tokens, b"Hello World! !", Verdict::WrongAnswer),
+        (Checker::Floats(1e-3), b"Hello World!", Verdict::Accepted),
+    ];
+    for (checker, expected, verdict) in cases {
+        helper.write_file("answer", expected);
+        let check = checker.check(&input, &output, &answer).unwrap();
+        assert_eq!(check.verdict(), *verdict, "{:?} {}", checker, check);
+    }
+
+    helper.write_file("float_output", b"1.0001 2");
+    helper.write_file("answer", b"1 2.0");
+    let float_output = helper.file_path("float_output");
+    for &(epsilon, verdict) in &[(1e-3, Verdict::Accepted), (1e-5, Verdict::WrongAnswer)] {
+        let check = Checker::Floats(epsilon)
+            .check(&input, &float_output, &answer)
+            .unwrap();
+        assert_eq!(check.verdict(), verdict);
+    }
+
+    // Exits with the number in the answer file
+    let mut checker_helper = TestRunnerHelper::for_simple_exec(
+        "test_checker_custom",
+        EXIT_WITH_ARG_FILE,
+        PivotRoot::Pivot,
+    );
+    let checker = Checker::Custom(Box::new(checker_helper.config_builder().build()));
+    for &(exit_code, verdict) in &[
+        (0, Verdict::Accepted),
+        (1, Verdict::WrongAnswer),
+        (2, Verdict::PresentationError),
+    ] {
+        helper.write_file("answer", exit_code.to_string().as_bytes());
+        let checked = checker
+            .check_run(run_info.clone(), &input, &output, &answer)
+            .unwrap();
+        assert_eq!(checked.check().unwrap().verdict(), verdict);
+    }
+
+    helper.write_file("answer", b"3");
+    match checker.check_run(run_info, &input, &output, &answer) {
+        Err(Error::CheckerError(CheckerError::CheckerFailed { exit_code: 3, .. })) => {}
+        result => panic!("Unexpected result {:?}", result),
+    }
+}
+
+#[test]
+fn test_job() {
+    let mut helper = TestRunnerHelper::for_simple_exec("test_job", HELLO_WORLD, PivotRoot::Pivot);
+    let artifact = helper.file_path("artifact");
+    // Compiling is copying the program, without a new root
+    let compile = |source: &Path| {
+        ConfigBuilder::new("/bin/cp")
+            .arg(source)
+            .arg(&artifact)
+            .unique_instance()
+            .no_output_redirects()
+            .build()
+    };
+    let hello_world = fs::canonicalize(HELLO_WORLD).unwrap();
+
+    for &mode in &[ArtifactMode::Copy, ArtifactMode::Mount] {
+        let job = CompileJob::new(
+            compile(&hello_world),
+            helper
+                .config_builder()
+                .command("/bin/hello_world")
+                .no_output_redirects()
+                .build(),
+            Artifact::new(artifact.clone(), "/bin/hello_world".into(), mode),
+        );
+        let report = job::run(&job).unwrap();
+        report.compile().run_info().clone().assert(IsSuccess);
+        let run = report.run().unwrap();
+        run.run_info().clone().assert(IsSuccess);
+        assert_eq!(run.stdout(), "Hello World!\n");
+        assert_eq!(run.stderr(), "Hello stderr!\n");
+        assert!(report.is_success());
+        fs::remove_file(&artifact).unwrap();
+    }
+
+    // Only the beginning of a long log is kept, the compiler is not blocked on the rest
+    let job = CompileJob::new(
+        ConfigBuilder::new("/bin/sh")
+            .arg("-c")
+            .arg(format!(
+                "head -c 1000000 /dev/zero && cp {} {}",
+                hello_world.display(),
+                artifact.display()
+            ))
+            .unique_instance()
+            .no_output_redirects()
+            .build(),
+        helper
+            .config_builder()
+            .command("/bin/hello_world")
+            .no_output_redirects()
+            .build(),
+        Artifact::new(
+            artifact.clone(),
+            "/bin/hello_world".into(),
+            ArtifactMode::Copy,
+        ),
+    );
+    let report = job::run(&job).unwrap();
+    report.compile().run_info().clone().assert(IsSuccess);
+    assert_eq!(report.compile().stdout().len(), 64 * 1024);
+    assert!(report.is_success());
+    fs::remove_file(&artifact).unwrap();
+
+    // A successful run with a wrong answer is not
+    let output = helper.file_path("output");
+    helper.write_file("output", report.run().unwrap().stdout().as_bytes());
+    helper.write_file("answer", b"Goodbye World!\n");
+    let run_info = Checker::Exact
+        .check_run(
+            report.run().unwrap().run_info().clone(),
+            Path::new("/dev/null"),
+            &output,
+            &helper.file_path("answer"),
+        )
+        .unwrap();
+    let report = JobReport::new(
+        report.compile().clone(),
+        Some(StageReport::new(run_info, String::new(), String::new())),
+    );
+    assert!(!report.is_success());
+
+    let job = CompileJob::new(
+        compile(Path::new("/missing")),
+        helper.config_builder().build(),
+        Artifact::new(
+            artifact.clone(),
+            "/bin/hello_world".into(),
+            ArtifactMode::Copy,
+        ),
+    );
+    let report = job::run(&job).unwrap();
+    report
+        .compile()
+        .run_info()
+        .clone()
+        .assert(NonZeroExitStatus::new(1));
+    assert!(report.compile().stderr().contains("/missing"));
+    assert!(report.run().is_none());
+    assert!(!report.is_success());
+}
+
+#[test]
+fn test_preset() {
+    assert_eq!("c++".parse::<Language>(), Ok(Language::Cpp));
+    assert!("cobol".parse::<Language>().is_err());
+
+    let preset = Preset::for_language(Language::Rust);
+    assert_eq!(preset.pids(), 50);
+    assert!(preset
+        .mounts()
+        .iter()
+        .any(|mount| mount.destination() == Path::new("/lib")));
+
+    let mut helper =
+        TestRunnerHelper::for_simple_exec("test_preset", HELLO_WORLD, PivotRoot::Pivot);
+    let config = preset.apply(&helper.config_builder().build());
+    assert_eq!(config.limits().pids(), Some(50));
+    match *config.environment() {
+        Environment::EnvList(ref variables) => {
+            assert!(variables.iter().any(|(name, _)| name == "PATH"))
+        }
+        Environment::Forward => panic!("Expected the environment of the preset"),
+    }
+    ia_sandbox::spawn_jail(&config)
+        .and_then(|handle| handle.wait())
+        .unwrap()
+        .assert(IsSuccess);
+
+    // The configuration wins over the preset
+    let config = preset.apply(
+        &helper
+            .config_builder()
+            .limits(*LimitsBuilder::new().pids(3))
+            .environment(Environment::EnvList(vec![(
+                "PATH".to_owned(),
+                "/bin".to_owned(),
+            )]))
+            .mount(Mount::new(
+                "/usr/lib".into(),
+                "/lib".into(),
+                MountOptions::default(),
+            ))
+            .build(),
+    );
+    assert_eq!(config.limits().pids(), Some(3));
+    assert_eq!(
+        *config.environment(),
+        Environment::EnvList(vec![("PATH".to_owned(), "/bin".to_owned())])
+    );
+    let lib_mounts: Vec<&Mount> = config
+        .mounts()
+        .iter()
+        .filter(|mount| mount.destination() == Path::new("/lib"))
+        .collect();
+    assert_eq!(lib_mounts.len(), 1);
+    assert_eq!(lib_mounts[0].source(), Path::new("/usr/lib"));
+
+    // The memory limit is passed on to managed runtimes, forwarded variables that are not UTF-8
+    // are left out
+    env::set_var("IA_SANDBOX_NOT_UTF8", OsStr::from_bytes(b"\xff"));
+    let config = Preset::for_language(Language::Go).apply(
+        &helper
+            .config_builder()
+            .limits(*LimitsBuilder::new().memory(SpaceUsage::from_mebibytes(64)))
+            .environment(Environment::default())
+            .build(),
+    );
+    env::remove_var("IA_SANDBOX_NOT_UTF8");
+    match *config.environment() {
+        Environment::EnvList(ref variables) => {
+            assert!(variables
+                .iter()
+                .any(|(name, value)| name == "GOMEMLIMIT" && value == "67108864"));
+            assert!(variables
+                .iter()
+                .all(|(name, _)| name != "IA_SANDBOX_NOT_UTF8"));
+        }
+        Environment::Forward => panic!("Expected the environment of the preset"),
+    }
+
+    // Along with the stack limit for Java
+    let config = Preset::for_language(Language::Java).apply(
+        &helper
+            .config_builder()
+            .limits(
+                *LimitsBuilder::new()
+                    .memory(SpaceUsage::from_mebibytes(64))
+                    .stack(SpaceUsage::from_mebibytes(8)),
+            )
+            .environment(Environment::default())
+            .build(),
+    );
+    match *config.environment() {
+        Environment::EnvList(ref variables) => {
+            let (_, options) = variables
+                .iter()
+                .find(|(name, _)| name == "JAVA_TOOL_OPTIONS")
+                .expect("JAVA_TOOL_OPTIONS");
+            assert!(options.ends_with(" -Xmx65536k -Xss8192k"), "{}", options);
+        }
+        Environment::Forward => panic!("Expected the environment of the preset"),
+    }
+
+    // Merged with the forwarded environment
+    let config = Preset::for_language(Language::Java).apply(
+        &helper
+            .config_builder()
+            .environment(Environment::Forward)
+            .build(),
+    );
+    match *config.environment() {
+        Environment::EnvList(ref variables) => {
+            assert!(variables
+                .iter()
+                .any(|(name, _)| name == "JAVA_TOOL_OPTIONS"));
+            assert!(variables.iter().any(|(name, _)| name == "PATH"));
+        }
+        Environment::Forward => panic!("Expected the forwarded and preset environment"),
+    }
+}
+
+#[test]
+fn test_measure_memory_baseline() {
+    let mut helper = TestRunnerHelper::for_simple_exec(
+        "test_measure_memory_baseline",
+        HELLO_WORLD,
+        PivotRoot::Pivot,
+    );
+    let config = helper.config_builder().build();
+    let preset = Preset::for_language(Language::Rust);
+    let baseline = preset
+        .measure_memory_baseline(&config, config.command().to_path_buf(), vec![])
+        .unwrap();
+    assert!(baseline > SpaceUsage::from_bytes(0));
+    assert_eq!(
+        preset
+            .clone()
+            .with_memory_baseline(baseline)
+            .apply(&config)
+            .memory_baseline(),
+        Some(baseline)
+    );
+
+    // The empty program has to succeed
+    match preset.measure_memory_baseline(&config, "/missing".into(), vec![]) {
+        Err(Error::ChildError(ChildError::FFIError(FFIError::ExecError { .. }))) => {}
+        result => panic!("Expected exec error, got {:?}", result),
+    }
+
+    // A huge baseline does not overflow the memory limit
+    let mut limits = LimitsBuilder::new();
+    limits.memory(SpaceUsage::from_megabytes(10));
+    TestRunnerHelper::for_simple_exec(
+        "test_measure_memory_baseline_huge",
+        HELLO_WORLD,
+        PivotRoot::Pivot,
+    )
+    .config_builder()
+    .limits(limits)
+    .memory_baseline(SpaceUsage::from_bytes(u64::MAX))
+    .build_and_run()
+    .unwrap()
+    .assert(IsSuccess);
+}
+
+#[test]
+fn test_pool() {
+    let mut helper =
+        TestRunnerHelper::for_simple_exec("test_pool", EXIT_WITH_INPUT, PivotRoot::Pivot);
+    let config = helper.config_builder().build();
+    let command = config.command().to_path_buf();
+    let mut pool = Pool::new(config, 2).unwrap();
+
+    for &exit_code in &[0, 3, 5] {
+        let input = format!("input{}", exit_code);
+        let output = format!("output{}", exit_code);
+        helper.write_file(&input, exit_code.to_string().as_bytes());
+        let job = Job::new(
+            command.clone(),
+            vec![],
+            File::open(helper.file_path(&input)).unwrap(),
+            File::create(helper.file_path(&output)).unwrap(),
+            File::create("/dev/null").unwrap(),
+        );
+
+        let handle = pool.run(job).unwrap();
+        // Refilling while the job runs leaves it alone
+        pool.fill().unwrap();
+        let run_info = handle.wait().unwrap();
+        if exit_code == 0 {
+            run_info.assert(IsSuccess);
+        } else {
+            run_info.assert(NonZeroExitStatus::new(exit_code));
+        }
+        let output_path = helper.file_path(&output);
+        assert_eq!(helper.read_line(output_path), format!("{}\n", exit_code));
+    }
+}
+
 #[test]
 fn test_redirect_stdout() {
     let mut helper =
@@ -236,6 +707,153 @@ fn test_time_limit_exceeded() {
         .assert(CompareLimits::new(TimeLimitExceeded, limits));
 }
 
+#[test]
+fn test_idle_time_limit_exceeded() {
+    let mut limits = LimitsBuilder::new();
+    limits.idle_time(Duration::from_millis(200));
+
+    TestRunnerHelper::for_simple_exec(
+        "test_idle_time_limit_exceeded",
+        LOOP_500_MS,
+        PivotRoot::Pivot,
+    )
+    .config_builder()
+    .limits(limits)
+    .build_and_run()
+    .unwrap()
+    .assert(CompareLimits::new(IsSuccess, limits));
+
+    TestRunnerHelper::for_simple_exec(
+        "test_idle_time_limit_exceeded",
+        SLEEP_1_SECOND,
+        PivotRoot::Pivot,
+    )
+    .config_builder()
+    .limits(limits)
+    .build_and_run()
+    .unwrap()
+    .assert(CompareLimits::new(Idle, limits));
+}
+
+#[test]
+fn test_soft_wall_time_limit() {
+    let mut limits = LimitsBuilder::new();
+    limits
+        .wall_time(Duration::from_millis(800))
+        .soft(SoftLimits::new(
+            Some(Duration::from_millis(200)),
+            None,
+            libc::SIGTERM,
+        ));
+
+    let run_info = TestRunnerHelper::for_simple_exec(
+        "test_soft_wall_time_limit",
+        EXIT_ON_SIGTERM,
+        PivotRoot::Pivot,
+    )
+    .config_builder()
+    .limits(limits)
+    .build_and_run()
+    .unwrap();
+
+    match *run_info.result() {
+        RunInfoResult::WallTimeLimitExceeded(exceeded) => {
+            assert_eq!(exceeded.limit(), Duration::from_millis(200));
+            assert!(exceeded.termination().exited_during_grace_period());
+            assert_eq!(exceeded.termination().exit_status(), ExitStatus::Exited(3));
+        }
+        ref result => panic!("Expected wall time limit exceeded, got {}", result),
+    }
+
+    // Without a handler the signal does nothing, so the hard limit kills it
+    let run_info = TestRunnerHelper::for_simple_exec(
+        "test_soft_wall_time_limit",
+        SLEEP_1_SECOND,
+        PivotRoot::Pivot,
+    )
+    .config_builder()
+    .limits(limits)
+    .build_and_run()
+    .unwrap();
+
+    match *run_info.result() {
+        RunInfoResult::WallTimeLimitExceeded(exceeded) => {
+            assert_eq!(exceeded.limit(), Duration::from_millis(800));
+            assert!(exceeded.termination().soft_limit_signaled());
+            assert!(exceeded.termination().killed_by_sandbox());
+        }
+        ref result => panic!("Expected wall time limit exceeded, got {}", result),
+    }
+}
+
+#[test]
+fn test_cpu_quota() {
+    let mut limits = LimitsBuilder::new();
+    limits.cpu_quota(CpuQuota::from_millicores(500));
+
+    let run_info =
+        TestRunnerHelper::for_simple_exec("test_cpu_quota", LOOP_500_MS, PivotRoot::Pivot)
+            .config_builder()
+            .limits(limits)
+            .unique_instance()
+            .build_and_run()
+            .unwrap();
+
+    assert!(run_info.is_success(), "{}", run_info.result());
+    // Half a core makes 500ms of cpu time take at least 900ms
+    assert!(run_info.usage().wall_time() >= Duration::from_millis(900));
+    assert!(run_info.usage().throttling().nr_throttled() > 0);
+}
+
+#[test]
+fn test_io_limits() {
+    let mut limits = LimitsBuilder::new();
+    limits.io(IoLimits::new(
+        IoDevice::containing(".").unwrap(),
+        Some(1_000_000),
+        Some(1_000_000),
+        Some(100),
+        None,
+    ));
+
+    TestRunnerHelper::for_simple_exec("test_io_limits", HELLO_WORLD, PivotRoot::Pivot)
+        .config_builder()
+        .limits(limits)
+        .unique_instance()
+        .build_and_run()
+        .unwrap()
+        .assert(IsSuccess);
+}
+
+#[test]
+fn test_time_limit_exceeded_termination() {
+    let mut limits = LimitsBuilder::new();
+    limits.user_time(Duration::from_millis(100));
+
+    let run_info = TestRunnerHelper::for_simple_exec(
+        "test_time_limit_exceeded_termination",
+        LOOP_500_MS,
+        PivotRoot::Pivot,
+    )
+    .config_builder()
+    .limits(limits)
+    .build_and_run()
+    .unwrap();
+
+    match *run_info.result() {
+        RunInfoResult::TimeLimitExceeded(exceeded) => {
+            assert_eq!(exceeded.limit(), Duration::from_millis(100));
+            assert!(exceeded.usage() > exceeded.limit());
+            assert!(exceeded.termination().killed_by_sandbox());
+            assert_eq!(
+                exceeded.termination().exit_status(),
+                ExitStatus::Signaled(libc::SIGKILL as u32)
+            );
+        }
+        ref result => panic!("Expected time limit exceeded, got {}", result),
+    }
+}
+
 #[test]
 fn test_threads_time_limit_exceeded() {
     let mut limits = LimitsBuilder::new();
@@ -265,6 +883,33 @@ fn test_threads_time_limit_exceeded() {
     .assert(CompareLimits::new(TimeLimitExceeded, limits));
 }
 
+#[test]
+fn test_detailed_usage() {
+    let run_info = TestRunnerHelper::for_simple_exec(
+        "test_detailed_usage",
+        THREADS_LOOP_500_MS,
+        PivotRoot::Pivot,
+    )
+    .config_builder()
+    .detailed_usage(DetailedUsage::Yes)
+    .build_and_run()
+    .unwrap();
+
+    assert!(run_info.is_success(), "{}", run_info.result());
+    let processes = run_info.processes();
+    assert_eq!(processes.len(), 1, "{}", run_info);
+    let process = &processes[0];
+    assert_eq!(process.pid(), 1);
+    assert_eq!(process.exit_status(), Some(ExitStatus::Exited(0)));
+    assert!(process.threads().len() > 1, "{}", run_info);
+    assert!(
+        process.cpu_time() >= Duration::from_millis(400),
+        "{}",
+        run_info
+    );
+    assert!(process.children().is_empty());
+}
+
 #[test]
 fn test_threads_wall_time_limit_exceeded() {
     let mut limits = LimitsBuilder::new();
@@ -323,6 +968,132 @@ fn test_memory_limit_exceeded() {
     .assert(CompareLimits::new(MemoryLimitExceeded, limits));
 }
 
+#[test]
+fn test_memory_limit_exceeded_exactly() {
+    // Just below what the program needs, so the OOM killer is what stops it
+    let mut limits = LimitsBuilder::new();
+    limits.memory(SpaceUsage::from_bytes(20_000_000));
+
+    TestRunnerHelper::for_simple_exec(
+        "test_memory_limit_exceeded_exactly",
+        ALLOCATE_20_MEGABYTES,
+        PivotRoot::Pivot,
+    )
+    .config_builder()
+    .limits(limits)
+    .unique_instance()
+    .build_and_run()
+    .unwrap()
+    .assert(CompareLimits::new(MemoryLimitExceeded, limits));
+}
+
+#[test]
+fn test_memory_limit_exceeded_allocation_failed() {
+    // A program exiting because it could not allocate at the limit exceeded it as well
+    let mut limits = LimitsBuilder::new();
+    limits.memory(SpaceUsage::from_megabytes(10));
+
+    let mut helper = TestRunnerHelper::for_simple_exec(
+        "test_memory_limit_exceeded_allocation_failed",
+        FILL_MEMORY_THEN_FAIL_ALLOCATION,
+        PivotRoot::Pivot,
+    );
+    let output = helper.file_path("output");
+    let run_info = helper
+        .config_builder()
+        .limits(limits)
+        .stdout(&output)
+        .unique_instance()
+        .build_and_run()
+        .unwrap();
+    match *run_info.result() {
+        RunInfoResult::MemoryLimitExceeded(ref exceeded) => {
+            assert_eq!(exceeded.termination().exit_status(), ExitStatus::Exited(1));
+            assert!(!exceeded.termination().killed_by_sandbox());
+        }
+        ref result => panic!("Expected memory limit exceeded, got {}", result),
+    }
+
+    // Far from the limit it is only a failure of the program
+    limits.memory(SpaceUsage::from_megabytes(100));
+    helper
+        .config_builder()
+        .limits(limits)
+        .build_and_run()
+        .unwrap()
+        .assert(NonZeroExitStatus::new(1));
+}
+
+#[test]
+fn test_memory_baseline() {
+    // The baseline raises the limit enforced and is left out of the usage
+    let mut limits = LimitsBuilder::new();
+    limits.memory(SpaceUsage::from_megabytes(10));
+
+    let run_info = TestRunnerHelper::for_simple_exec(
+        "test_memory_baseline",
+        ALLOCATE_20_MEGABYTES,
+        PivotRoot::Pivot,
+    )
+    .config_builder()
+    .limits(limits)
+    .memory_baseline(SpaceUsage::from_megabytes(15))
+    .build_and_run()
+    .unwrap();
+    assert!(run_info.usage().memory() < SpaceUsage::from_megabytes(10));
+    run_info.assert(CompareLimits::new(IsSuccess, limits));
+}
+
+#[test]
+fn test_output_formats() {
+    let mut limits = LimitsBuilder::new();
+    limits.memory(SpaceUsage::from_mebibytes(10));
+
+    let run_info = TestRunnerHelper::for_simple_exec(
+        "test_output_formats",
+        ALLOCATE_20_MEGABYTES,
+        PivotRoot::Pivot,
+    )
+    .config_builder()
+    .limits(limits)
+    .build_and_run()
+    .unwrap();
+
+    let template: Template = "{verdict} {limit_kib}KiB {{{limit_ms}}}".parse().unwrap();
+    assert_eq!(
+        template.render(&run_info),
+        "memory_limit_exceeded 10240KiB {}"
+    );
+    assert!("{verdict".parse::<Template>().is_err());
+    assert!("{missing}".parse::<Template>().is_err());
+
+    let key_value = format::key_value(&run_info);
+    assert!(key_value.starts_with("verdict=memory_limit_exceeded result=\"Memory limit"));
+    assert!(key_value.contains(" limit_kib=10240 "));
+
+    let header = format::csv_header();
+    let record = format::csv_record(&run_info);
+    assert!(header.starts_with("verdict,result,"));
+    assert!(record.starts_with("memory_limit_exceeded,Memory limit exceeded: "));
+    assert_eq!(header.split(',').count(), format::FIELDS.len());
+
+    // A message with every character that has to be escaped or quoted
+    let run_info = run_info.with_check(CheckResult::new(
+        Verdict::WrongAnswer,
+        "a, \"b\"\tc\\d\ne\u{1b}".to_owned(),
+    ));
+    let template: Template = "{check_verdict}: {check_message}".parse().unwrap();
+    assert_eq!(
+        template.render(&run_info),
+        "wrong_answer: a, \"b\"\\tc\\\\d\\ne\\u001b"
+    );
+    assert!(format::key_value(&run_info)
+        .ends_with(" check_message=\"a, \\\"b\\\"\\tc\\\\d\\ne\\u001b\""));
+    assert!(
+        format::csv_record(&run_info).ends_with(",wrong_answer,\"a, \"\"b\"\"\tc\\d\ne\u{1b}\"")
+    );
+}
+
 #[test]
 fn test_threads_memory_limit_exceeded() {
     let mut limits = LimitsBuilder::new();
@@ -404,6 +1175,205 @@ fn test_mount_directory() {
         .assert(NonZeroExitStatus::new(15));
 }
 
+#[test]
+fn test_mount_size_limit() {
+    let temp_dir = Builder::new()
+        .prefix("test_mount_size_limit")
+        .tempdir()
+        .unwrap();
+    let run_with_size_limit = |size_limit| {
+        let mut mount_options = MountOptions::default();
+        mount_options.set_read_only(false);
+        mount_options.set_size_limit(Some(size_limit));
+
+        TestRunnerHelper::for_simple_exec(
+            "test_mount_size_limit",
+            WRITE_1_MEGABYTE,
+            PivotRoot::Pivot,
+        )
+        .config_builder()
+        .mount(Mount::new(
+            temp_dir.path().into(),
+            "/mount".into(),
+            mount_options,
+        ))
+        .arg("/mount/output")
+        .build_and_run()
+        .unwrap()
+    };
+
+    run_with_size_limit(SpaceUsage::from_kibibytes(512)).assert(NonZeroExitStatus::new(1));
+    // What fit is copied back as well, only what the jail writes counts for the usage
+    let output = temp_dir.path().join("output");
+    assert!(output.metadata().unwrap().len() <= 512 * 1024);
+    fs::remove_file(&output).unwrap();
+
+    let run_info = run_with_size_limit(SpaceUsage::from_mebibytes(2));
+    assert!(run_info.is_success(), "{}", run_info.result());
+    assert!(run_info.usage().disk_usage() >= SpaceUsage::from_bytes(1_000_000));
+    // Copied back once the jail is done
+    assert_eq!(output.metadata().unwrap().len(), 1_000_000);
+}
+
+#[test]
+fn test_batch_mount_size_limit() {
+    let temp_dir = Builder::new()
+        .prefix("test_batch_mount_size_limit")
+        .tempdir()
+        .unwrap();
+    fs::write(temp_dir.path().join("input"), &[b'a'; 1_000_000][..]).unwrap();
+
+    let mut mount_options = MountOptions::default();
+    mount_options.set_read_only(false);
+    mount_options.set_size_limit(Some(SpaceUsage::from_mebibytes(4)));
+    let mut helper = TestRunnerHelper::for_simple_exec(
+        "test_batch_mount_size_limit",
+        CREATE_NEW_FILE,
+        PivotRoot::Pivot,
+    );
+    let config = helper
+        .config_builder()
+        .mount(Mount::new(
+            temp_dir.path().into(),
+            "/mount".into(),
+            mount_options,
+        ))
+        .arg("/mount/output")
+        .build();
+
+    // Every case starts from the source, without what the case before it wrote
+    let cases = [
+        Case::new(None, None, config.limits()),
+        Case::new(None, None, config.limits()),
+    ];
+    let run_infos = batch::run(&config, &cases, OnFailure::KeepGoing).unwrap();
+    assert_eq!(run_infos.len(), 2);
+    for run_info in run_infos {
+        let run_info = run_info.unwrap();
+        assert!(run_info.is_success(), "{}", run_info.result());
+        // The copy of the source is not counted
+        assert!(run_info.usage().disk_usage() < SpaceUsage::from_bytes(1_000_000));
+    }
+
+    // What the last case left is copied back
+    assert_eq!(fs::read(temp_dir.path().join("output")).unwrap(), b"a");
+    assert_eq!(
+        temp_dir.path().join("input").metadata().unwrap().len(),
+        1_000_000
+    );
+}
+
+#[test]
+fn test_rootfs_mounts() {
+    let mut helper =
+        TestRunnerHelper::for_simple_exec("test_rootfs_mounts", HELLO_WORLD, PivotRoot::DoNot);
+    let new_root = helper.file_path("");
+
+    let config_builder = helper.config_builder();
+    config_builder.command("/hello_world").new_root(new_root);
+    for mount in rootfs::mounts(&[HELLO_WORLD]).unwrap() {
+        config_builder.mount(mount);
+    }
+
+    config_builder.build_and_run().unwrap().assert(IsSuccess);
+}
+
+#[test]
+fn test_rootfs_populate() {
+    let mut helper =
+        TestRunnerHelper::for_simple_exec("test_rootfs_populate", HELLO_WORLD, PivotRoot::DoNot);
+    let new_root = helper.file_path("");
+
+    rootfs::populate(&[HELLO_WORLD], &new_root).unwrap();
+    for library in rootfs::dependencies(&[HELLO_WORLD]).unwrap() {
+        let copy = new_root.join(library.strip_prefix("/").unwrap());
+        assert_eq!(
+            fs::metadata(&copy).unwrap().len(),
+            fs::metadata(&library).unwrap().len(),
+            "{:?} was not copied",
+            library
+        );
+    }
+
+    helper
+        .config_builder()
+        .command("/hello_world")
+        .new_root(&new_root)
+        .build_and_run()
+        .unwrap()
+        .assert(IsSuccess);
+
+    // Populating again replaces the copies with new files
+    let loader = rootfs::dependencies(&[HELLO_WORLD])
+        .unwrap()
+        .into_iter()
+        .next()
+        .unwrap();
+    let copy = new_root.join(loader.strip_prefix("/").unwrap());
+    // Held open, so its inode can not be reused
+    let old_copy = File::open(&copy).unwrap();
+    rootfs::populate(&[HELLO_WORLD], &new_root).unwrap();
+    assert_ne!(
+        fs::metadata(&copy).unwrap().ino(),
+        old_copy.metadata().unwrap().ino()
+    );
+
+    // A truncated ELF file is rejected instead of read past its end
+    let truncated = helper.file_path("truncated");
+    let contents = fs::read(HELLO_WORLD).unwrap();
+    fs::write(&truncated, &contents[..64]).unwrap();
+    match rootfs::populate(&[&truncated], &new_root) {
+        Err(RootfsError::InvalidElfError { path, .. }) => assert_eq!(path, truncated),
+        result => panic!("Expected an invalid ELF error, got {:?}", result),
+    }
+    let err = Error::from(rootfs::populate(&[helper.file_path("missing")], &new_root).unwrap_err());
+    assert_eq!(err.code(), "rootfs.open_elf");
+    assert_eq!(err.errno(), Some(Errno::new(libc::ENOENT)));
+}
+
+#[test]
+fn test_landlock() {
+    let temp_dir = Builder::new()
+        .prefix("test_landlock_special")
+        .tempdir()
+        .unwrap();
+    let input_path = temp_dir.path().join("input");
+    let mut file = File::create(&input_path).unwrap();
+    let _ = file.write(b"15\n").unwrap();
+
+    let exec = vec!["/exit_with_arg_file".into(), "/lib".into(), "/lib64".into()];
+    TestRunnerHelper::for_simple_exec("test_landlock", EXIT_WITH_ARG_FILE, PivotRoot::Pivot)
+        .config_builder()
+        .mount(Mount::new(
+            temp_dir.path().into(),
+            "/mount".into(),
+            MountOptions::default(),
+        ))
+        .landlock(LandlockRules::new(
+            vec!["/mount".into()],
+            vec![],
+            exec.clone(),
+        ))
+        .arg("/mount/input")
+        .build_and_run()
+        .unwrap()
+        .assert(NonZeroExitStatus::new(15));
+
+    // Not allowed to read /mount, so opening the file panics
+    TestRunnerHelper::for_simple_exec("test_landlock", EXIT_WITH_ARG_FILE, PivotRoot::Pivot)
+        .config_builder()
+        .mount(Mount::new(
+            temp_dir.path().into(),
+            "/mount".into(),
+            MountOptions::default(),
+        ))
+        .landlock(LandlockRules::new(vec![], vec![], exec))
+        .arg("/mount/input")
+        .build_and_run()
+        .unwrap()
+        .assert(NonZeroExitStatus::new(101));
+}
+
 #[test]
 fn test_clear_usage() {
     let mut limits = LimitsBuilder::new();
@@ -434,6 +1404,271 @@ fn test_clear_usage() {
         .assert(CompareLimits::new(IsSuccess, limits));
 }
 
+#[test]
+fn test_unique_instance() {
+    let mut helper =
+        TestRunnerHelper::for_simple_exec("test_unique_instance", HELLO_WORLD, PivotRoot::Pivot);
+    let handle = helper
+        .config_builder()
+        .unique_instance()
+        .build_and_spawn()
+        .unwrap();
+
+    let instance = Path::new("/sys/fs/cgroup/cpuacct/ia-sandbox").join(handle.instance_name());
+    handle.wait().unwrap().assert(IsSuccess);
+    assert!(!instance.exists());
+
+    // Dropped while the jail still runs
+    let mut helper =
+        TestRunnerHelper::for_simple_exec("test_unique_instance", SLEEP_1_SECOND, PivotRoot::Pivot);
+    let handle = helper
+        .config_builder()
+        .unique_instance()
+        .build_and_spawn()
+        .unwrap();
+    let instance = Path::new("/sys/fs/cgroup/pids/ia-sandbox").join(handle.instance_name());
+    std::thread::sleep(Duration::from_millis(100));
+    assert!(instance.exists());
+    drop(handle);
+    assert!(!instance.exists());
+}
+
+#[test]
+fn test_cpuset() {
+    TestRunnerHelper::for_simple_exec("test_cpuset", HELLO_WORLD, PivotRoot::Pivot)
+        .config_builder()
+        .cpuset(CpuSet::new(vec![0], vec![]))
+        .unique_instance()
+        .build_and_run()
+        .unwrap()
+        .assert(IsSuccess);
+}
+
+#[test]
+fn test_cpuset_out_of_range() {
+    match cpuset::check_cpus(&[0, 5000]) {
+        Err(Error::CpuSetError(CpuSetError::CpuOutOfRange { cpu: 5000, .. })) => {}
+        result => panic!("Expected cpu out of range, got {:?}", result),
+    }
+
+    // Fails the jail instead of panicking in it
+    let result = TestRunnerHelper::for_simple_exec(
+        "test_cpuset_out_of_range",
+        HELLO_WORLD,
+        PivotRoot::Pivot,
+    )
+    .config_builder()
+    .cpuset(CpuSet::new(vec![5000], vec![]))
+    .unique_instance()
+    .build_and_run();
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_cpu_allocator() {
+    let lock_dir = Builder::new()
+        .prefix("test_cpu_allocator")
+        .tempdir()
+        .unwrap();
+
+    let allocator = CpuAllocator::new(lock_dir.path().to_path_buf());
+    let lease = allocator.allocate(1).unwrap();
+    assert_eq!(lease.cpus().len(), 1);
+
+    // A different allocator (like one in another process) can not take the same cpu
+    match CpuAllocator::new(lock_dir.path().to_path_buf()).allocate(1) {
+        Ok(other) => assert_ne!(lease.cpus(), other.cpus()),
+        Err(Error::CpuSetError(CpuSetError::NotEnoughCpus { .. })) => {}
+        Err(err) => panic!("Unexpected error {}", err),
+    }
+
+    // Released once the lease is dropped
+    let cpus = lease.cpus().to_vec();
+    drop(lease);
+    assert_eq!(allocator.allocate(1).unwrap().cpus(), &cpus[..]);
+}
+
+#[test]
+fn test_doctor() {
+    let report = doctor::diagnose(&ControllerPath::default()).unwrap();
+    assert!(report.is_ok(), "{}", report);
+
+    let report = doctor::diagnose(&ControllerPath::new(
+        None,
+        Some("/sys/fs/cgroup/memory/missing".into()),
+        None,
+        None,
+        None,
+        None,
+    ))
+    .unwrap();
+    assert!(!report.is_ok());
+    assert!(report
+        .checks()
+        .iter()
+        .any(|check| check.status() == Status::Failed && check.name().contains("missing")));
+}
+
+#[test]
+fn test_exit_codes() {
+    let exit_code = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_ia-sandbox"))
+            .args(["--instance-name", "test_exit_codes", "--output", "oneline"])
+            .args(args)
+            .output()
+            .unwrap()
+            .status
+            .code()
+    };
+
+    // The verdicts
+    assert_eq!(
+        exit_code(&["--exit-codes", "detailed", HELLO_WORLD]),
+        Some(0)
+    );
+    assert_eq!(
+        exit_code(&["--exit-codes", "detailed", EXIT_WITH_LAST_ARGUMENT, "3"]),
+        Some(1)
+    );
+    assert_eq!(
+        exit_code(&["--exit-codes", "detailed", "--time", "100ms", LOOP_500_MS]),
+        Some(4)
+    );
+    assert_eq!(exit_code(&[EXIT_WITH_LAST_ARGUMENT, "3"]), Some(0));
+
+    // The sandbox failing, in the range of the part that failed
+    assert_eq!(
+        exit_code(&["--exit-codes", "detailed", "missing"]),
+        Some(103)
+    );
+    assert_eq!(
+        exit_code(&[
+            "--exit-codes",
+            "detailed",
+            "--memory-controller",
+            "/sys/fs/cgroup/memory/missing",
+            HELLO_WORLD,
+        ]),
+        Some(104)
+    );
+    assert_eq!(exit_code(&["missing"]), Some(1));
+
+    // Arguments clap rejects are not mistaken for a verdict
+    assert_eq!(
+        exit_code(&["--exit-codes", "detailed", "--time", "never", HELLO_WORLD]),
+        Some(100)
+    );
+    assert_eq!(
+        exit_code(&["--exit-codes=detailed", "--unknown", HELLO_WORLD]),
+        Some(100)
+    );
+    assert_eq!(exit_code(&["--unknown", HELLO_WORLD]), Some(1));
+
+    let output = Command::new(env!("CARGO_BIN_EXE_ia-sandbox"))
+        .args([
+            "--output",
+            "json",
+            "--exit-codes",
+            "detailed",
+            "--unknown",
+            HELLO_WORLD,
+        ])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(100));
+    let error: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(error["error"]["code"], "cli.invalid_arguments");
+}
+
+#[test]
+fn test_error_output() {
+    let run = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_ia-sandbox"))
+            .args(args)
+            .output()
+            .unwrap();
+        let error: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        (output.status.code(), error["error"].clone())
+    };
+
+    let (code, error) = run(&[
+        "--instance-name",
+        "test_error_output",
+        "--output",
+        "json",
+        "missing",
+    ]);
+    assert_eq!(code, Some(1));
+    assert_eq!(error["code"], "ffi.exec");
+    assert_eq!(error["fields"]["command"], "missing");
+    assert_eq!(error["fields"]["errno"], libc::ENOENT);
+    assert_eq!(
+        error["message"],
+        *error["causes"].as_array().unwrap().last().unwrap()
+    );
+
+    let (code, error) = run(&[
+        "doctor",
+        "--output",
+        "json",
+        "--memory-controller",
+        "/sys/fs/cgroup/memory/missing",
+    ]);
+    assert_eq!(code, Some(1));
+    assert_eq!(error["code"], "doctor.checks_failed");
+    let checks = error["fields"]["checks"].as_array().unwrap();
+    assert!(checks
+        .iter()
+        .any(|check| check["status"] == "Failed"
+            && check["name"].as_str().unwrap().contains("missing")));
+}
+
+#[test]
+fn test_setup() {
+    // Handing the hierarchies over to ourselves leaves them as they are
+    let owner = Owner::new(unsafe { libc::getuid() }, unsafe { libc::getgid() });
+    let report = setup::setup(&SetupOptions::new(Some(CGroupVersion::V1), owner)).unwrap();
+    assert_eq!(report.version(), CGroupVersion::V1);
+    for controller in &["cpuacct", "memory", "pids"] {
+        let hierarchy = Path::new("/sys/fs/cgroup")
+            .join(controller)
+            .join("ia-sandbox");
+        assert!(report.hierarchies().contains(&hierarchy), "{:?}", hierarchy);
+    }
+    for hierarchy in report.hierarchies() {
+        for path in &[
+            hierarchy.clone(),
+            hierarchy.join("cgroup.procs"),
+            hierarchy.join("tasks"),
+        ] {
+            let metadata = fs::metadata(path).unwrap();
+            assert_eq!((metadata.uid(), metadata.gid()), (owner.uid(), owner.gid()));
+        }
+    }
+    // A new cpuset cgroup gets the cpus and memory nodes of its parent
+    let cpuset = Path::new("/sys/fs/cgroup/cpuset/ia-sandbox");
+    if report
+        .hierarchies()
+        .iter()
+        .any(|hierarchy| hierarchy == cpuset)
+    {
+        for file in &["cpuset.cpus", "cpuset.mems"] {
+            assert!(!fs::read_to_string(cpuset.join(file))
+                .unwrap()
+                .trim()
+                .is_empty());
+        }
+    }
+    assert_eq!(report.features().len(), KernelFeature::all().len());
+
+    // Without a cgroup v2 root there are no controllers to delegate
+    match setup::setup(&SetupOptions::new(Some(CGroupVersion::V2), owner)) {
+        Err(Error::SetupError(SetupError::ControllerNotAvailable { ref path, .. }))
+            if path == Path::new("/sys/fs/cgroup") => {}
+        result => panic!("Unexpected result {:?}", result),
+    }
+}
+
 #[test]
 fn test_environment() {
     TestRunnerHelper::for_simple_exec("exit_with_env", EXIT_WITH_ENV, PivotRoot::Pivot)
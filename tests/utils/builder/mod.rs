@@ -3,8 +3,8 @@ use std::path::{Path, PathBuf};
 use std::time::Duration;
 
 use ia_sandbox::config::{
-    ClearUsage, Config, ControllerPath, Environment, Interactive, Limits, Mount, ShareNet,
-    SpaceUsage, SwapRedirects,
+    ClearUsage, Config, ControllerPath, CpuQuota, CpuSet, DetailedUsage, Environment, Interactive,
+    IoLimits, LandlockRules, Limits, Mount, ShareNet, SoftLimits, SpaceUsage, SwapRedirects,
 };
 use ia_sandbox::run_info::RunInfo;
 use ia_sandbox::{self, JailHandle, Result};
@@ -23,6 +23,10 @@ pub struct ConfigBuilder {
     swap_redirects: SwapRedirects,
     clear_usage: ClearUsage,
     environment: Environment,
+    landlock: Option<LandlockRules>,
+    cpuset: Option<CpuSet>,
+    detailed_usage: DetailedUsage,
+    memory_baseline: Option<SpaceUsage>,
 }
 
 impl ConfigBuilder {
@@ -41,6 +45,10 @@ impl ConfigBuilder {
             swap_redirects: SwapRedirects::default(),
             clear_usage: ClearUsage::default(),
             environment: Environment::default(),
+            landlock: None,
+            cpuset: None,
+            detailed_usage: DetailedUsage::default(),
+            memory_baseline: None,
         }
     }
 
@@ -91,6 +99,13 @@ impl ConfigBuilder {
         self
     }
 
+    /// Leaves stdout and stderr as they are, for capturing them
+    pub fn no_output_redirects(&mut self) -> &mut ConfigBuilder {
+        self.redirect_stdout = None;
+        self.redirect_stderr = None;
+        self
+    }
+
     pub fn limits<T: Into<Limits>>(&mut self, limits: T) -> &mut ConfigBuilder {
         self.limits = Some(limits.into());
         self
@@ -101,6 +116,11 @@ impl ConfigBuilder {
         self
     }
 
+    pub fn unique_instance(&mut self) -> &mut ConfigBuilder {
+        self.instance_name = None;
+        self
+    }
+
     pub fn mount(&mut self, mount: Mount) -> &mut ConfigBuilder {
         self.mounts.push(mount);
         self
@@ -121,8 +141,28 @@ impl ConfigBuilder {
         self
     }
 
-    pub fn build_and_spawn(&mut self) -> Result<JailHandle> {
-        let config = Config::new(
+    pub fn landlock(&mut self, landlock: LandlockRules) -> &mut ConfigBuilder {
+        self.landlock = Some(landlock);
+        self
+    }
+
+    pub fn cpuset(&mut self, cpuset: CpuSet) -> &mut ConfigBuilder {
+        self.cpuset = Some(cpuset);
+        self
+    }
+
+    pub fn detailed_usage(&mut self, detailed_usage: DetailedUsage) -> &mut ConfigBuilder {
+        self.detailed_usage = detailed_usage;
+        self
+    }
+
+    pub fn memory_baseline(&mut self, memory_baseline: SpaceUsage) -> &mut ConfigBuilder {
+        self.memory_baseline = Some(memory_baseline);
+        self
+    }
+
+    pub fn build(&mut self) -> Config {
+        Config::new(
             self.command.clone(),
             self.args.clone(),
             self.new_root.clone(),
@@ -142,9 +182,15 @@ impl ConfigBuilder {
             self.clear_usage,
             Interactive::default(),
             self.environment.clone(),
-        );
+            self.landlock.clone(),
+            self.cpuset.clone(),
+            self.detailed_usage,
+            self.memory_baseline,
+        )
+    }
 
-        ia_sandbox::spawn_jail(&config)
+    pub fn build_and_spawn(&mut self) -> Result<JailHandle> {
+        ia_sandbox::spawn_jail(&self.build())
     }
 
     pub fn build_and_run(&mut self) -> Result<RunInfo<()>> {
@@ -159,6 +205,10 @@ pub struct LimitsBuilder {
     memory: Option<SpaceUsage>,
     stack: Option<SpaceUsage>,
     pids: Option<usize>,
+    idle_time: Option<Duration>,
+    soft: SoftLimits,
+    cpu_quota: Option<CpuQuota>,
+    io: Option<IoLimits>,
 }
 
 impl LimitsBuilder {
@@ -169,6 +219,10 @@ impl LimitsBuilder {
             memory: None,
             stack: None,
             pids: None,
+            idle_time: None,
+            soft: SoftLimits::default(),
+            cpu_quota: None,
+            io: None,
         }
     }
 
@@ -196,6 +250,26 @@ impl LimitsBuilder {
         self.pids = Some(pids);
         self
     }
+
+    pub fn idle_time(&mut self, idle_time: Duration) -> &mut LimitsBuilder {
+        self.idle_time = Some(idle_time);
+        self
+    }
+
+    pub fn soft(&mut self, soft: SoftLimits) -> &mut LimitsBuilder {
+        self.soft = soft;
+        self
+    }
+
+    pub fn cpu_quota(&mut self, cpu_quota: CpuQuota) -> &mut LimitsBuilder {
+        self.cpu_quota = Some(cpu_quota);
+        self
+    }
+
+    pub fn io(&mut self, io: IoLimits) -> &mut LimitsBuilder {
+        self.io = Some(io);
+        self
+    }
 }
 
 impl From<LimitsBuilder> for Limits {
@@ -206,6 +280,10 @@ impl From<LimitsBuilder> for Limits {
             limits_builder.memory,
             limits_builder.stack,
             limits_builder.pids,
+            limits_builder.idle_time,
+            limits_builder.soft,
+            limits_builder.cpu_quota,
+            limits_builder.io,
         )
     }
 }
@@ -95,7 +95,25 @@ impl Matcher for WallTimeLimitExceeded {
 
     fn try_match(&self, run_info: RunInfo<()>) -> Result<(), Self::Output> {
         match *run_info.result() {
-            RunInfoResult::WallTimeLimitExceeded => Ok(()),
+            RunInfoResult::WallTimeLimitExceeded(_) => Ok(()),
+            _ => Err(run_info),
+        }
+    }
+}
+
+pub struct Idle;
+
+impl Matcher for Idle {
+    type AssertionString = &'static str;
+    type Output = RunInfo<()>;
+
+    fn assertion_string(&self) -> Self::AssertionString {
+        "result is Idle"
+    }
+
+    fn try_match(&self, run_info: RunInfo<()>) -> Result<(), Self::Output> {
+        match *run_info.result() {
+            RunInfoResult::Idle(_) => Ok(()),
             _ => Err(run_info),
         }
     }
@@ -113,7 +131,7 @@ impl Matcher for TimeLimitExceeded {
 
     fn try_match(&self, run_info: RunInfo<()>) -> Result<(), Self::Output> {
         match *run_info.result() {
-            RunInfoResult::TimeLimitExceeded => Ok(()),
+            RunInfoResult::TimeLimitExceeded(_) => Ok(()),
             _ => Err(run_info),
         }
     }
@@ -131,7 +149,7 @@ impl Matcher for MemoryLimitExceeded {
 
     fn try_match(&self, run_info: RunInfo<()>) -> Result<(), Self::Output> {
         match *run_info.result() {
-            RunInfoResult::MemoryLimitExceeded => Ok(()),
+            RunInfoResult::MemoryLimitExceeded(_) => Ok(()),
             _ => Err(run_info),
         }
     }
@@ -188,7 +206,7 @@ impl<T: Matcher> Matcher for CompareLimits<T> {
 
     fn try_match(&self, run_info: RunInfo<()>) -> Result<(), Self::Output> {
         self.matcher
-            .try_match(run_info)
+            .try_match(run_info.clone())
             .map_err(|_| CompareLimitsRunUsage(self.limits, run_info))
     }
 }
@@ -239,6 +257,15 @@ impl Display for CompareLimitsRunUsage {
             writeln!(formatter, "Memory usage: {}", self.1.usage().memory())?;
         }
 
+        if let Some(idle_time_limit) = self.0.idle_time() {
+            writeln!(
+                formatter,
+                "Idle time: {} of maximum allowed {}",
+                DurationDisplay(self.1.usage().idle_time()),
+                DurationDisplay(idle_time_limit)
+            )?;
+        }
+
         if let Some(stack_limit) = self.0.stack() {
             writeln!(formatter, "Maximum stack memory: {}", stack_limit)?;
         }
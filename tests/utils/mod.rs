@@ -1,10 +1,11 @@
 use std::ffi::CString;
 use std::fs::{self, File};
-use std::io::{BufRead, BufReader, Read, Write};
+use std::io::{Read, Write};
 use std::os::unix::ffi::OsStrExt;
 use std::path::{Path, PathBuf};
-use std::process::{Command, Stdio};
+use std::process::Command;
 
+use ia_sandbox::rootfs;
 use ia_sandbox::run_info::RunInfo;
 
 use libc;
@@ -16,37 +17,6 @@ pub use self::builder::{ConfigBuilder, LimitsBuilder};
 pub mod matchers;
 use self::matchers::Matcher;
 
-fn get_exec_libs<T>(file: T) -> Vec<PathBuf>
-where
-    T: AsRef<Path>,
-{
-    let output = Command::new("ldd")
-        .stdin(Stdio::null())
-        .arg(file.as_ref())
-        .output()
-        .unwrap();
-    if !output.status.success() {
-        return vec![];
-    }
-
-    BufReader::new(output.stdout.as_slice())
-        .lines()
-        .flat_map(|line| {
-            line.unwrap()
-                .split_whitespace()
-                .map(str::to_string)
-                .collect::<Vec<_>>()
-        })
-        .filter_map(|token| {
-            if token.starts_with('/') {
-                Some(token.into())
-            } else {
-                None
-            }
-        })
-        .collect()
-}
-
 // Until https://marc.info/?l=linux-kernel&m=150834137201488 gets resolved, we can't
 // use fs::copy on libs/executables
 fn copy_by_command<T1: AsRef<Path>, T2: AsRef<Path>>(from: T1, to: T2) {
@@ -63,7 +33,7 @@ where
     T1: AsRef<Path>,
     T2: AsRef<Path>,
 {
-    for lib in get_exec_libs(file) {
+    for lib in rootfs::dependencies(&[file]).unwrap() {
         let destination = path.as_ref().join(lib.strip_prefix("/").unwrap());
         if let Some(parent) = destination.parent() {
             fs::create_dir_all(parent).unwrap();